@@ -2,7 +2,10 @@ mod nes;
 
 use crate::nes::rom::Rom;
 
+#[cfg(feature="debug")]
 use std::io;
+use std::fs;
+use std::path::Path;
 
 // For testing
 #[cfg(feature="debug")]
@@ -11,46 +14,50 @@ fn start() {
 
     let roms = nes::loader::list_test_roms(path);
     let roms_with_idx = (1..roms.len()).zip(roms).collect::<Vec<_>>();
-    loop {
-        roms_with_idx.iter().for_each(|(ind, path)|{
-            println!("{} {:?}", ind, path);
-
-        });
-        let mut input = String::new();
-        let _ = io::stdin().read_line(&mut input).and_then(|_x|
-            match input.trim().parse::<usize>() {
-                Ok(line) => {
-                    println!("loading game {:?} {:?}", line, &roms_with_idx[line-1].1.path().to_str().unwrap());
-                    let rom_data = nes::loader::load_rom(&roms_with_idx[line-1].1.path().to_str().unwrap());
-                    let rom: nes::rom::RomV1 = nes::rom::Rom::new(&rom_data.unwrap());
-                    let mut processor = nes::cpu::processor::Processor::new(rom.get_rom_data());
-                    let mut i = 0;
-                    loop {
-                        processor.execute_next_instruction();
-                        i += 1;
-                        // only for testing
-                        // TODO :: remove this break
-                        if i > 60 {
-                            break;
-                        }
+    roms_with_idx.iter().for_each(|(ind, path)|{
+        println!("{} {:?}", ind, path);
+
+    });
+    let mut input = String::new();
+    let _ = io::stdin().read_line(&mut input).and_then(|_x|
+        match input.trim().parse::<usize>() {
+            Ok(line) => {
+                println!("loading game {:?} {:?}", line, &roms_with_idx[line-1].1.path().to_str().unwrap());
+                let rom_data = nes::loader::load_rom(roms_with_idx[line-1].1.path().to_str().unwrap());
+                let rom: nes::rom::RomV1 = nes::rom::Rom::new(&rom_data.unwrap());
+                let mut processor = nes::cpu::processor::Processor::new();
+                match nes::mapper::new_mapper(nes::mapper::Cartridge::new(&rom)) {
+                    Ok(mapper) => processor.get_memory_mut().set_mapper(mapper),
+                    Err(e) => println!("{}", e),
+                }
+                let mut i = 0;
+                loop {
+                    processor.execute_next_instruction();
+                    i += 1;
+                    // only for testing
+                    // TODO :: remove this break
+                    if i > 60 {
+                        break;
                     }
-                    nes::loader::load_rom(&roms_with_idx[line-1].1.path().to_str().unwrap()).unwrap().iter().for_each(|x|
-                        print!("{:#04X?}, ",x)
-                    );
-                    println!();
-                    println!("header constant:: {:?}", rom.get_header().constant_as_str());
-                    println!("header prg size:: {:#x?}", rom.get_header().get_prg_rom_size());
-                    println!("header chr size:: {:#x?}", rom.get_header().get_chr_rom_size());
-                    println!("header flags:: {:#x?}", rom.get_header().get_all_flag());
-                    Ok(())
-                },
-                Err(_e) => {
-                    Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid Input"))
                 }
+                nes::loader::load_rom(roms_with_idx[line-1].1.path().to_str().unwrap()).unwrap().iter().for_each(|x|
+                    print!("{:#04X?}, ",x)
+                );
+                println!();
+                println!("header constant:: {:?}", rom.get_header().constant_as_str());
+                println!("header format:: {:?}", rom.get_header().get_format());
+                println!("header mapper:: {}.{}", rom.get_header().get_mapper(), rom.get_header().get_submapper());
+                println!("header prg size:: {:#x?}", rom.get_header().get_prg_rom_size());
+                println!("header chr size:: {:#x?}", rom.get_header().get_chr_rom_size());
+                println!("header bytes:: {:#x?}", rom.get_header().get_raw());
+                println!("title:: {:?}", rom.get_title());
+                Ok(())
+            },
+            Err(_e) => {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid Input"))
             }
-        );
-        break;
-    }
+        }
+    );
 
 }
 
@@ -60,7 +67,319 @@ fn start() {
     println!("Main logic");
 }
 
+// Runs every ROM of test_roms.xml headlessly and prints a status.txt style table, exits with 1
+// when a test fails
+// usage: test-suite [xml path] [--jobs N] [--status out.txt] [--junit out.xml]
+fn test_suite(args: &[String]) -> Result<(), String> {
+    let mut xml_path = String::from("resources/test/test_roms.xml");
+    let mut jobs = std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1);
+    let mut status_path = None;
+    let mut junit_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--jobs" => {
+                jobs = args.next().and_then(|x| x.parse().ok()).ok_or("--jobs expects a number")?;
+            },
+            "--status" => {
+                status_path = Some(args.next().ok_or("--status expects a path")?.clone());
+            },
+            "--junit" => {
+                junit_path = Some(args.next().ok_or("--junit expects a path")?.clone());
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => {
+                xml_path = arg.clone();
+            }
+        }
+    }
+
+    let xml = fs::read_to_string(&xml_path).map_err(|e| format!("{}: {}", xml_path, e))?;
+    let tests = nes::testsuite::parse_test_roms(&xml)?;
+    let base = Path::new(&xml_path).parent().unwrap_or_else(|| Path::new("."));
+    let results = nes::testsuite::run_all(base, &tests, jobs);
+
+    let table = nes::testsuite::report::status_table(&tests, &results);
+    match status_path {
+        Some(path) => fs::write(&path, &table).map_err(|e| format!("{}: {}", path, e))?,
+        None => print!("{}", table),
+    }
+    if let Some(path) = junit_path {
+        let junit = nes::testsuite::report::junit_xml("test_roms", &tests, &results);
+        fs::write(&path, junit).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    let count = |status: nes::testsuite::TestStatus| results.iter().filter(|x| x.status == status).count();
+    println!("{} / {} passed", count(nes::testsuite::TestStatus::Pass), results.len());
+    let failed = count(nes::testsuite::TestStatus::Fail) + count(nes::testsuite::TestStatus::Error);
+    if failed > 0 {
+        return Err(format!("{} tests failed", failed));
+    }
+    Ok(())
+}
+
+// Loads an iNES file, missing ROM bytes read as zero so a cut off dump still runs
+fn load_cartridge(path: &str) -> Result<nes::rom::RomV1, String> {
+    let data = nes::loader::load_rom(path).map_err(|e| format!("{}: {}", path, e))?;
+    let rom: nes::rom::RomV1 = Rom::new(&data);
+    if rom.is_truncated() {
+        eprintln!("warning: {} is truncated, the missing ROM is filled with zeros", path);
+    }
+    if let Some(title) = rom.get_title() {
+        println!("{}", title);
+    }
+    Ok(rom)
+}
+
+// Prints the header and the sections of an iNES file
+// usage: info <rom>
+fn rom_info(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or("missing ROM path")?;
+    let rom = load_cartridge(path)?;
+    let header = rom.get_header();
+    println!("format: {:?} ({})", header.get_format(), header.constant_as_str());
+    println!("mapper: {}.{}", header.get_mapper(), header.get_submapper());
+    match nes::mapper::new_mapper(nes::mapper::Cartridge::new(&rom)) {
+        Ok(mapper) => println!("board: {}", mapper.get_name()),
+        Err(e) => println!("board: {}", e),
+    }
+    println!("console: {:?}, timing: {:?}", header.get_console_type(), header.get_timing());
+    if header.get_console_type() == nes::rom::ConsoleType::VsSystem {
+        println!("vs ppu: {}, vs hardware: {}", header.get_vs_ppu_type(), header.get_vs_hardware_type());
+    }
+    println!("mirroring: {:?}, battery: {}, trainer: {}", header.get_mirroring(), header.has_battery(), header.has_trainer());
+    println!("prg rom: {:#x}, chr rom: {:#x}", header.get_prg_rom_size(), header.get_chr_rom_size());
+    println!("prg ram: {:#x}, prg nvram: {:#x}", header.get_prg_ram_size(), header.get_prg_nvram_size());
+    println!("chr ram: {:#x}, chr nvram: {:#x}", header.get_chr_ram_size(), header.get_chr_nvram_size());
+    println!("misc roms: {}, expansion device: {:#04x}", header.get_misc_rom_count(), header.get_expansion_device());
+    println!("header bytes: {:02X?}", header.get_raw());
+    if let Some(inst_rom) = rom.get_inst_rom() {
+        println!("inst rom: {:#x}, prom: {}", inst_rom.len(), rom.get_prom().is_some());
+    }
+    if !rom.get_extra_data().is_empty() {
+        println!("extra data: {:#x}", rom.get_extra_data().len());
+    }
+    Ok(())
+}
+
+// Runs a ROM headlessly and writes the APU output to a WAV file
+// usage: <rom> --record-audio out.wav [--frames N] [--sample-rate N] [--float] [--pal]
+//              [--mute ch1,ch2] [--solo ch1,ch2] [--stems]
+// --stems also writes every channel alone to out-<channel>.wav
+fn record_audio(args: &[String]) -> Result<(), String> {
+    use nes::audio::AudioSink;
+    use nes::audio::wav::{SampleFormat, WavWriter};
+    use nes::cpu::apu::Channel;
+
+    // expansion channels ("vrc6-saw") are only known once the cartridge is loaded
+    let parse_channels = |apu: &nes::cpu::apu::Apu, list: &str| -> Result<Vec<Channel>, String> {
+        list.split(',')
+            .map(|x| apu.find_channel(x.trim()).ok_or_else(|| format!("unknown channel {:?}", x)))
+            .collect()
+    };
+
+    let mut rom_path = None;
+    let mut wav_path = None;
+    let mut frames = 600;
+    let mut sample_rate = nes::cpu::apu::mixer::DEFAULT_SAMPLE_RATE;
+    let mut format = SampleFormat::Pcm16;
+    let mut system = nes::console::System::Ntsc;
+    let mut muted = None;
+    let mut soloed = None;
+    let mut stems = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record-audio" => {
+                wav_path = Some(args.next().ok_or("--record-audio expects a path")?.clone());
+            },
+            "--frames" => {
+                frames = args.next().and_then(|x| x.parse().ok()).ok_or("--frames expects a number")?;
+            },
+            "--sample-rate" => {
+                sample_rate = args.next().and_then(|x| x.parse().ok()).ok_or("--sample-rate expects a number")?;
+            },
+            "--float" => format = SampleFormat::Float32,
+            "--pal" => system = nes::console::System::Pal,
+            "--mute" => {
+                muted = Some(args.next().ok_or("--mute expects a channel list")?.clone());
+            },
+            "--solo" => {
+                soloed = Some(args.next().ok_or("--solo expects a channel list")?.clone());
+            },
+            "--stems" => stems = true,
+            _ => rom_path = Some(arg.clone()),
+        }
+    }
+    let rom_path = rom_path.ok_or("missing ROM path")?;
+    let wav_path = wav_path.ok_or("missing --record-audio path")?;
+
+    let rom = load_cartridge(&rom_path)?;
+    let mut console = nes::console::Console::new(&rom, system)?;
+    console.set_trace(false);
+    let muted = muted.map(|x| parse_channels(console.get_apu(), &x)).transpose()?.unwrap_or_default();
+    let soloed = soloed.map(|x| parse_channels(console.get_apu(), &x)).transpose()?.unwrap_or_default();
+    let channels: Vec<(Channel, String)> = console.get_apu().get_channels().into_iter()
+        .map(|x| (x, console.get_apu().get_channel_name(x)))
+        .collect();
+    let mixer = console.get_apu_mut().get_mixer_mut();
+    mixer.set_sample_rate(sample_rate);
+    mixer.set_channel_outputs(stems);
+    muted.iter().for_each(|x| mixer.set_muted(*x, true));
+    soloed.iter().for_each(|x| mixer.set_soloed(*x, true));
+
+    let mut wav = WavWriter::create(&wav_path, sample_rate, format).map_err(|e| format!("{}: {}", wav_path, e))?;
+    let mut stem_wavs = vec![];
+    if stems {
+        let prefix = wav_path.strip_suffix(".wav").unwrap_or(&wav_path);
+        for (channel, name) in channels.iter() {
+            let path = format!("{}-{}.wav", prefix, name);
+            let stem = WavWriter::create(&path, sample_rate, format).map_err(|e| format!("{}: {}", path, e))?;
+            stem_wavs.push((*channel, path, stem));
+        }
+    }
+    for _ in 0..frames {
+        console.run_frame();
+        let mixer = console.get_apu_mut().get_mixer_mut();
+        wav.write_samples(&mixer.take_samples()).map_err(|e| format!("{}: {}", wav_path, e))?;
+        for (channel, path, stem) in stem_wavs.iter_mut() {
+            stem.write_samples(&mixer.take_channel_samples(*channel)).map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    wav.finish().map_err(|e| format!("{}: {}", wav_path, e))?;
+    for (_, path, stem) in stem_wavs.iter_mut() {
+        stem.finish().map_err(|e| format!("{}: {}", path, e))?;
+    }
+    println!("{} samples written to {}", wav.get_sample_count(), wav_path);
+    Ok(())
+}
+
+// Renders a track of an NSF/NSFe file to a WAV file
+// usage: nsf <file> --record-audio out.wav [--track N] [--seconds S] [--sample-rate N]
+//            [--float] [--pal]
+//        nsf <file> --list
+// Without --seconds the track time and fade of the NSFe/NSF2 metadata are used, or 120 s.
+fn nsf_player(args: &[String]) -> Result<(), String> {
+    use nes::audio::AudioSink;
+    use nes::audio::wav::{SampleFormat, WavWriter};
+
+    let mut nsf_path = None;
+    let mut wav_path = None;
+    let mut track = None;
+    let mut seconds = None;
+    let mut list = false;
+    let mut sample_rate = nes::cpu::apu::mixer::DEFAULT_SAMPLE_RATE;
+    let mut format = SampleFormat::Pcm16;
+    let mut system = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record-audio" => {
+                wav_path = Some(args.next().ok_or("--record-audio expects a path")?.clone());
+            },
+            "--track" => {
+                track = Some(args.next().and_then(|x| x.parse::<u8>().ok()).ok_or("--track expects a number")?);
+            },
+            "--seconds" => {
+                seconds = Some(args.next().and_then(|x| x.parse::<f64>().ok()).ok_or("--seconds expects a number")?);
+            },
+            "--sample-rate" => {
+                sample_rate = args.next().and_then(|x| x.parse().ok()).ok_or("--sample-rate expects a number")?;
+            },
+            "--float" => format = SampleFormat::Float32,
+            "--pal" => system = Some(nes::console::System::Pal),
+            "--list" => list = true,
+            _ => nsf_path = Some(arg.clone()),
+        }
+    }
+    let nsf_path = nsf_path.ok_or("missing NSF path")?;
+
+    let data = nes::loader::load_rom(&nsf_path).map_err(|e| format!("{}: {}", nsf_path, e))?;
+    let nsf = nes::nsf::Nsf::new(&data).map_err(|e| format!("{}: {}", nsf_path, e))?;
+    let system = system.unwrap_or_else(|| nsf.get_preferred_system());
+    println!("{} - {} ({}), {} songs", nsf.get_name(), nsf.get_artist(), nsf.get_copyright(), nsf.get_total_songs());
+    if list {
+        for song in nsf.get_playlist() {
+            let length = match nsf.get_track_length(song) {
+                Some((time, fade)) => format!("{}:{:02} +{}s fade", time / 60000, time / 1000 % 60, fade / 1000),
+                None => String::from("?"),
+            };
+            let author = nsf.get_track_author(song).map(|x| format!(" by {}", x)).unwrap_or_default();
+            println!("{:3} {}{} [{}]", song, nsf.get_track_title(song), author, length);
+        }
+        return Ok(());
+    }
+    let wav_path = wav_path.ok_or("missing --record-audio path")?;
+
+    let mut player = nes::nsf::player::Player::new(nsf, system);
+    if let Some(track) = track {
+        player.start_song(track);
+    }
+    player.get_apu_mut().set_sample_rate(sample_rate);
+    let song = player.get_song();
+    let (time, fade) = match seconds {
+        Some(seconds) => ((seconds * 1000.0) as u32, 0),
+        None => player.get_nsf().get_track_length(song).unwrap_or((nes::nsf::DEFAULT_TRACK_TIME, 0)),
+    };
+    println!("track {}: {}", song, player.get_nsf().get_track_title(song));
+
+    let mut wav = WavWriter::create(&wav_path, sample_rate, format).map_err(|e| format!("{}: {}", wav_path, e))?;
+    let chunk = system.cpu_frequency() / 60;
+    let total = ((time + fade) as f64 / 1000.0 * system.cpu_frequency() as f64) as usize;
+    let fade_start = time as usize * sample_rate / 1000;
+    let fade_length = (fade as usize * sample_rate / 1000).max(1);
+    let mut written = 0;
+    let mut done = 0;
+    while done < total {
+        let cycles = chunk.min(total - done);
+        player.run_cycles(cycles);
+        done += cycles;
+        let mut samples = player.get_apu_mut().take_samples();
+        for (idx, sample) in samples.iter_mut().enumerate() {
+            let pos = written + idx;
+            if pos > fade_start {
+                *sample *= 1.0 - ((pos - fade_start) as f32 / fade_length as f32).min(1.0);
+            }
+        }
+        written += samples.len();
+        wav.write_samples(&samples).map_err(|e| format!("{}: {}", wav_path, e))?;
+    }
+    wav.finish().map_err(|e| format!("{}: {}", wav_path, e))?;
+    println!("{} samples written to {}", wav.get_sample_count(), wav_path);
+    Ok(())
+}
+
 fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.len() > 1 && args[1] == "test-suite" {
+        if let Err(e) = test_suite(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.len() > 1 && args[1] == "info" {
+        if let Err(e) = rom_info(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.len() > 1 && args[1] == "nsf" {
+        if let Err(e) = nsf_player(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.iter().any(|x| x == "--record-audio") {
+        if let Err(e) = record_audio(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     println!("Hello, world!");
     start();
 }
@@ -0,0 +1,15 @@
+// Destinations for the mixed APU output
+//
+// The APU produces f32 samples at the host rate (see cpu::apu::mixer), roughly in -1.0-1.0.
+// A sink takes them in chunks, usually once per emulated frame.
+
+pub mod wav;
+
+use std::io;
+
+pub trait AudioSink {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    // flushes whatever the format needs at the end (headers, sizes)
+    fn finish(&mut self) -> io::Result<()>;
+}
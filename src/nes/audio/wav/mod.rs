@@ -0,0 +1,160 @@
+// RIFF WAVE writer, mono, 16 bit PCM or 32 bit float
+//
+// "RIFF" <file size - 8> "WAVE"
+// "fmt " 16 <format 1 = PCM, 3 = float> <channels> <sample rate> <byte rate> <block align> <bits>
+// "data" <data size> <samples, little endian>
+//
+// Both sizes are unknown while recording, they are written as 0 and patched in `finish`.
+
+use super::AudioSink;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    Pcm16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes_per_sample(&self) -> u32 {
+        match self {
+            SampleFormat::Pcm16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+
+    fn format_tag(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm16 => 1,
+            SampleFormat::Float32 => 3,
+        }
+    }
+}
+
+const HEADER_SIZE: u32 = 44;
+
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    format: SampleFormat,
+    data_size: u32,
+    finished: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &str, sample_rate: usize, format: SampleFormat) -> io::Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate, format)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: usize, format: SampleFormat) -> io::Result<WavWriter<W>> {
+        let channels: u16 = 1;
+        let block_align = channels as u32 * format.bytes_per_sample();
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&format.format_tag().to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&(sample_rate as u32).to_le_bytes())?;
+        out.write_all(&(sample_rate as u32 * block_align).to_le_bytes())?;
+        out.write_all(&(block_align as u16).to_le_bytes())?;
+        out.write_all(&(format.bytes_per_sample() as u16 * 8).to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            out,
+            format,
+            data_size: 0,
+            finished: false,
+        })
+    }
+
+    pub fn get_sample_count(&self) -> usize {
+        (self.data_size / self.format.bytes_per_sample()) as usize
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * self.format.bytes_per_sample() as usize);
+        for sample in samples {
+            let sample = sample.clamp(-1.0, 1.0);
+            match self.format {
+                SampleFormat::Pcm16 => bytes.extend_from_slice(&((sample * 32767.0).round() as i16).to_le_bytes()),
+                SampleFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        self.out.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    fn record(format: SampleFormat, samples: &[f32]) -> Vec<u8> {
+        let mut writer = WavWriter::new(Cursor::new(vec![]), 44100, format).unwrap();
+        writer.write_samples(samples).unwrap();
+        writer.finish().unwrap();
+        writer.out.into_inner()
+    }
+
+    #[test]
+    fn pcm_header_and_samples() {
+        let data = record(SampleFormat::Pcm16, &[0.0, 1.0, -2.0]);
+        assert_eq!(data.len(), HEADER_SIZE as usize + 6);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4), data.len() as u32 - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 16), 16);
+        assert_eq!(u16_at(&data, 20), 1);
+        assert_eq!(u16_at(&data, 22), 1);
+        assert_eq!(u32_at(&data, 24), 44100);
+        assert_eq!(u32_at(&data, 28), 88200);
+        assert_eq!(u16_at(&data, 32), 2);
+        assert_eq!(u16_at(&data, 34), 16);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 6);
+        assert_eq!(&data[44..], &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn float_header_and_samples() {
+        let data = record(SampleFormat::Float32, &[0.5]);
+        assert_eq!(u16_at(&data, 20), 3);
+        assert_eq!(u32_at(&data, 28), 176400);
+        assert_eq!(u16_at(&data, 32), 4);
+        assert_eq!(u16_at(&data, 34), 32);
+        assert_eq!(u32_at(&data, 40), 4);
+        assert_eq!(&data[44..], &0.5f32.to_le_bytes());
+    }
+}
@@ -0,0 +1,243 @@
+// The console ties the 2A03 (CPU + APU) and the 2C02 (PPU) together and keeps the
+// master timing, so a ROM can be run for a number of frames without any frontend.
+//
+// NTSC: 341 dots * 262 lines, one dot skipped every other frame, 3 dots per CPU cycle
+//       => 29780.5 CPU cycles per frame
+// PAL:  341 dots * 312 lines, 3.2 dots per CPU cycle
+//       => 33247.5 CPU cycles per frame
+//
+// DMC sample fetches halt the CPU for 4 cycles (halt, dummy, alignment, read) while the rest
+// of the console keeps running. The halted CPU cycle is repeated afterwards, a read of
+// $4016/$4017 there clocks the controller twice and loses a bit. A $4014 write halts it for
+// 513 cycles (514 when it starts on an odd cycle) to copy a page to OAM, a read and a write
+// per byte.
+//
+// The CPU sees the NMI and mapper IRQ lines as they are one dot into its cycle, the APU IRQ
+// one cycle after the APU raised it.
+//
+// The picture of the PPU is copied when vblank starts, `tv_sha1` shows the last full frame.
+
+// CPU cycles lost to a DMC sample fetch
+pub const DMC_DMA_CYCLES: usize = 4;
+// CPU cycles of the copy part of OAM DMA
+pub const OAM_DMA_CYCLES: usize = 512;
+// PPU dots of a CPU cycle run before the NMI and mapper IRQ lines are sampled
+const INTERRUPT_SAMPLE_DOT: usize = 1;
+
+pub const NTSC_CPU_FREQUENCY: usize = 1789773;
+pub const PAL_CPU_FREQUENCY: usize = 1662607;
+
+use crate::nes::controller::InputSource;
+use crate::nes::cpu::apu::Apu;
+use crate::nes::cpu::processor::Processor;
+use crate::nes::mapper::{self, Cartridge};
+use crate::nes::ppu::frame::Frame;
+use crate::nes::ppu::Ppu;
+use crate::nes::rom::RomV1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum System {
+    Ntsc,
+    Pal,
+}
+
+impl System {
+    pub fn cpu_frequency(&self) -> usize {
+        match self {
+            System::Ntsc => NTSC_CPU_FREQUENCY,
+            System::Pal => PAL_CPU_FREQUENCY,
+        }
+    }
+
+    // PPU dots per CPU cycle, in fifths
+    pub fn dots_per_cycle_x5(&self) -> usize {
+        match self {
+            System::Ntsc => 15,
+            System::Pal => 16,
+        }
+    }
+
+    // CPU cycles per frame, doubled to keep the half cycle
+    pub fn half_cycles_per_frame(&self) -> usize {
+        match self {
+            System::Ntsc => 59561,
+            System::Pal => 66495,
+        }
+    }
+}
+
+pub struct Console {
+    processor: Processor,
+    frame: Frame,
+    system: System,
+    cycle: usize,
+    dots_x5: usize,
+    frame_count: usize,
+    dma_stall: usize,
+    // CPU cycles until the one a DMC fetch halted
+    dma_halted: usize,
+    // interrupt lines as the CPU samples them
+    apu_irq: bool,
+    mapper_irq: bool,
+    // cycles left of the OAM DMA, the source address and the byte in flight
+    oam_dma: usize,
+    oam_dma_addr: u16,
+    oam_dma_data: u8,
+    input: Option<Box<dyn InputSource>>,
+}
+
+impl Console {
+    // fails for boards that are not emulated
+    pub fn new(rom: &RomV1, system: System) -> Result<Console, String> {
+        let mut mapper = mapper::new_mapper(Cartridge::new(rom))?;
+        let mut apu = Apu::new(system);
+        if let Some(audio) = mapper.take_expansion_audio() {
+            apu.add_expansion(audio);
+        }
+        let mut processor = Processor::new();
+        let memory = processor.get_memory_mut();
+        memory.set_ppu(Ppu::new(system));
+        memory.set_apu(apu);
+        memory.set_mapper(mapper);
+        // power on goes through the reset sequence as well
+        processor.reset();
+        Ok(Console {
+            processor,
+            frame: Frame::new(),
+            system,
+            cycle: 0,
+            dots_x5: 0,
+            frame_count: 0,
+            dma_stall: 0,
+            dma_halted: 0,
+            apu_irq: false,
+            mapper_irq: false,
+            oam_dma: 0,
+            oam_dma_addr: 0x0000,
+            oam_dma_data: 0x00,
+            input: None,
+        })
+    }
+
+    // reset button, memory is kept
+    pub fn reset(&mut self) {
+        self.processor.get_memory_mut().get_apu_mut().reset();
+        self.processor.reset();
+    }
+
+    // CPU bus read without side effects
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.processor.get_memory().peek(addr as usize)
+    }
+
+    // PPU bus read without side effects
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.processor.get_memory().ppu_peek(addr)
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.processor.set_trace(trace);
+    }
+
+    // replaces whatever is plugged in, None leaves the buttons released
+    pub fn set_input_source(&mut self, input: Option<Box<dyn InputSource>>) {
+        self.input = input;
+    }
+
+    pub fn step(&mut self) {
+        if let Some(input) = self.input.as_mut() {
+            for port in 0..2 {
+                let buttons = input.get_buttons(port, self.cycle);
+                self.processor.get_memory_mut().set_buttons(port, buttons);
+            }
+        }
+        self.processor.set_irq_line(self.apu_irq || self.mapper_irq);
+        self.apu_irq = self.processor.get_memory().get_apu().irq_line();
+        if self.dma_stall > 0 {
+            self.dma_stall -= 1;
+        } else if let Some(addr) = self.processor.get_memory().get_apu().get_dma_request() {
+            // the byte is taken right away, the following cycles only keep the CPU halted
+            let memory = self.processor.get_memory_mut();
+            let data = memory.get_instruction(addr as usize);
+            memory.get_apu_mut().complete_dma(data);
+            self.dma_stall = DMC_DMA_CYCLES - 1;
+            self.dma_halted = 2;
+        } else if self.oam_dma > 0 {
+            self.oam_dma -= 1;
+            if self.oam_dma < OAM_DMA_CYCLES {
+                let memory = self.processor.get_memory_mut();
+                if self.oam_dma % 2 == 1 {
+                    self.oam_dma_data = memory.get_instruction(self.oam_dma_addr as usize);
+                    self.oam_dma_addr = self.oam_dma_addr.wrapping_add(1);
+                } else {
+                    memory.get_ppu_mut().write_oam(self.oam_dma_data);
+                }
+            }
+        } else {
+            self.processor.get_memory_mut().set_dma_halted(self.dma_halted == 1);
+            self.dma_halted = self.dma_halted.saturating_sub(1);
+            self.processor.execute_next_instruction();
+            self.processor.get_memory_mut().set_dma_halted(false);
+            if let Some(page) = self.processor.get_memory_mut().take_oam_dma() {
+                self.oam_dma = OAM_DMA_CYCLES + 1 + (self.cycle & 0x01);
+                self.oam_dma_addr = (page as u16) << 8;
+            }
+        }
+        self.cycle += 1;
+        self.dots_x5 += self.system.dots_per_cycle_x5();
+        let memory = self.processor.get_memory_mut();
+        memory.get_apu_mut().clock();
+        memory.get_mapper_mut().clock_cpu();
+        let mut dot = 0;
+        while self.dots_x5 >= 5 {
+            self.processor.get_memory_mut().clock_ppu();
+            self.dots_x5 -= 5;
+            dot += 1;
+            if dot == INTERRUPT_SAMPLE_DOT {
+                let memory = self.processor.get_memory();
+                let nmi = memory.get_ppu().nmi_line();
+                self.mapper_irq = memory.get_mapper().irq_line();
+                self.processor.set_nmi_line(nmi);
+            }
+        }
+        let memory = self.processor.get_memory_mut();
+        if memory.get_ppu_mut().take_frame_complete() {
+            self.frame = memory.get_ppu().get_frame().clone();
+        }
+        if self.cycle * 2 >= (self.frame_count + 1) * self.system.half_cycles_per_frame() {
+            self.frame_count += 1;
+        }
+    }
+
+    pub fn run_frame(&mut self) {
+        let frame_count = self.frame_count;
+        while self.frame_count == frame_count {
+            self.step();
+        }
+    }
+
+    pub fn get_apu(&self) -> &Apu {
+        self.processor.get_memory().get_apu()
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut Apu {
+        self.processor.get_memory_mut().get_apu_mut()
+    }
+
+    pub fn get_ppu(&self) -> &Ppu {
+        self.processor.get_memory().get_ppu()
+    }
+
+    pub fn get_cycle(&self) -> usize {
+        self.cycle
+    }
+
+    pub fn get_system(&self) -> System {
+        self.system
+    }
+
+    // digest of the current picture, comparable with `tvsha1` of test_roms.xml
+    pub fn tv_sha1(&self) -> String {
+        self.frame.tv_sha1()
+    }
+}
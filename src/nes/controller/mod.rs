@@ -0,0 +1,58 @@
+// Standard NES controller
+//
+// Writing bit 0 of $4016 sets the strobe of both ports. While strobe is high the shift register
+// keeps reloading the buttons, when it goes low every read of $4016 (port 1) / $4017 (port 2)
+// returns the next button on bit 0, in the order
+//
+// A, B, Select, Start, Up, Down, Left, Right
+//
+// Button states use the same order, A is bit 0.
+//
+// After the 8th read the register returns 1. Bit 6 is open bus, which is the high byte of
+// the address on a real console.
+
+pub mod recording;
+
+pub struct Controller {
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller {
+            buttons: 0x00,
+            shift: 0x00,
+            strobe: false,
+        }
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 0x01 > 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return 0x40 | (self.buttons & 0x01);
+        }
+        let bit = self.shift & 0x01;
+        self.shift = (self.shift >> 1) | 0x80;
+        0x40 | bit
+    }
+}
+
+// Anything feeding button states to the controller ports, polled on every CPU cycle.
+pub trait InputSource: Send {
+    fn get_buttons(&mut self, port: usize, cycle: usize) -> u8;
+}
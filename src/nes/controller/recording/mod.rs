@@ -0,0 +1,108 @@
+// Controller logs of test_roms.xml (`recordedinput`)
+//
+// After base64 decoding, the log is a list of 5 byte records
+//
+// 0-3: CPU cycle since power on, little-endian
+// 4:   buttons of controller 1, bit 0 = A ... bit 7 = Right
+//
+// The reference emulator wrote one record per frame, so the timestamps step by 29780/29781
+// cycles on NTSC and 33247/33248 on PAL. A state holds until the next record.
+
+use super::InputSource;
+
+const RECORD_SIZE: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub cycle: usize,
+    pub buttons: u8,
+}
+
+pub struct RecordedInput {
+    events: Vec<InputEvent>,
+    position: usize,
+    buttons: u8,
+}
+
+impl RecordedInput {
+    pub fn decode(data: &[u8]) -> Result<RecordedInput, String> {
+        if !data.len().is_multiple_of(RECORD_SIZE) {
+            return Err(format!("recorded input of {} bytes is not a multiple of {}", data.len(), RECORD_SIZE));
+        }
+        let events = data.chunks(RECORD_SIZE)
+            .map(|x| InputEvent {
+                cycle: u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as usize,
+                buttons: x[4],
+            })
+            .collect::<Vec<InputEvent>>();
+        if events.windows(2).any(|x| x[1].cycle < x[0].cycle) {
+            return Err(String::from("recorded input timestamps are not in order"));
+        }
+        Ok(RecordedInput {
+            events,
+            position: 0,
+            buttons: 0x00,
+        })
+    }
+}
+
+impl InputSource for RecordedInput {
+    // only controller 1 is recorded
+    fn get_buttons(&mut self, port: usize, cycle: usize) -> u8 {
+        if port != 0 {
+            return 0x00;
+        }
+        while self.position < self.events.len() && self.events[self.position].cycle <= cycle {
+            self.buttons = self.events[self.position].buttons;
+            self.position += 1;
+        }
+        self.buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one record per NTSC frame, the way test_roms.xml stores them
+    const RECORDS: [u8; 15] = [
+        0x08, 0x00, 0x00, 0x00, 0x00,
+        0x54, 0x74, 0x00, 0x00, 0x01,
+        0xA9, 0xE8, 0x00, 0x00, 0x80,
+    ];
+
+    #[test]
+    fn records_are_cycles_and_buttons() {
+        let input = RecordedInput::decode(&RECORDS).unwrap();
+        assert_eq!(input.events, vec![
+            InputEvent { cycle: 8, buttons: 0x00 },
+            InputEvent { cycle: 29780, buttons: 0x01 },
+            InputEvent { cycle: 59561, buttons: 0x80 },
+        ]);
+    }
+
+    #[test]
+    fn partial_records_are_rejected() {
+        assert!(RecordedInput::decode(&RECORDS[..14]).is_err());
+        assert!(RecordedInput::decode(&[]).is_ok());
+    }
+
+    #[test]
+    fn timestamps_must_not_go_back() {
+        let mut data = RECORDS.to_vec();
+        data.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00]);
+        assert!(RecordedInput::decode(&data).is_err());
+    }
+
+    #[test]
+    fn buttons_change_on_the_recorded_cycle() {
+        let mut input = RecordedInput::decode(&RECORDS).unwrap();
+        assert_eq!(input.get_buttons(0, 0), 0x00);
+        assert_eq!(input.get_buttons(0, 29779), 0x00);
+        assert_eq!(input.get_buttons(0, 29780), 0x01);
+        assert_eq!(input.get_buttons(1, 29780), 0x00);
+        assert_eq!(input.get_buttons(0, 59560), 0x01);
+        assert_eq!(input.get_buttons(0, 59561), 0x80);
+        assert_eq!(input.get_buttons(0, 1000000), 0x80);
+    }
+}
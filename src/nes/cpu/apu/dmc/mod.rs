@@ -0,0 +1,196 @@
+// Delta modulation channel, $4010-$4013
+//
+// $4010 IL-- RRRR  I: IRQ enable, L: loop, R: rate index
+// $4011 -DDD DDDD  direct load of the output level
+// $4012 AAAA AAAA  sample address = $C000 + A * 64
+// $4013 LLLL LLLL  sample length = L * 16 + 1 bytes
+//
+// The memory reader fills the one byte sample buffer through DMA on the CPU bus: the channel
+// raises a request with the address and the console halts the CPU, reads the byte and hands it
+// back with `complete_dma`. The address wraps from $FFFF to $8000. When the last byte is read
+// the sample restarts in loop mode, otherwise the IRQ flag is set if enabled.
+//
+// The output unit shifts out one bit of the shift register every timer period (in CPU cycles)
+// and moves the 7 bit level up or down by 2, staying inside 0-127. After 8 bits it takes the
+// sample buffer, or goes silent for the next 8 bits if the buffer is empty.
+//
+// Disabling the channel drops a fetch that hasn't happened yet, the sample buffer is kept.
+
+use crate::nes::console::System;
+
+pub const NTSC_RATE_TABLE: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+pub const PAL_RATE_TABLE: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+pub struct Dmc {
+    rate_table: &'static [u16; 16],
+    irq_enabled: bool,
+    irq_flag: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+
+    // memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    dma_pending: bool,
+
+    // output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    level: u8,
+}
+
+impl Dmc {
+    pub fn new(system: System) -> Dmc {
+        let rate_table = match system {
+            System::Ntsc => &NTSC_RATE_TABLE,
+            System::Pal => &PAL_RATE_TABLE,
+        };
+        Dmc {
+            rate_table,
+            irq_enabled: false,
+            irq_flag: false,
+            looping: false,
+            timer_period: rate_table[0],
+            timer: rate_table[0],
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            dma_pending: false,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            level: 0,
+        }
+    }
+
+    // `reg` is the register number 0-3
+    pub fn write_register(&mut self, reg: u16, data: u8) {
+        match reg {
+            0x0 => {
+                self.irq_enabled = data & 0x80 > 0;
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+                self.looping = data & 0x40 > 0;
+                self.timer_period = self.rate_table[(data & 0x0F) as usize];
+            },
+            0x1 => {
+                self.level = data & 0x7F;
+            },
+            0x2 => {
+                self.sample_address = 0xC000 | ((data as u16) << 6);
+            },
+            0x3 => {
+                self.sample_length = ((data as u16) << 4) + 1;
+            },
+            _ => {}
+        }
+    }
+
+    // $4015 bit 4, also acknowledges the IRQ
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+            self.dma_pending = false;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+            self.request_dma();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // status bit 4
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn get_irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    // address the memory reader wants to fetch
+    pub fn get_dma_request(&self) -> Option<u16> {
+        if self.dma_pending {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn complete_dma(&mut self, data: u8) {
+        if !self.dma_pending {
+            return;
+        }
+        self.dma_pending = false;
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    fn request_dma(&mut self) {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            self.dma_pending = true;
+        }
+    }
+
+    // every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer <= 1 {
+            self.timer = self.timer_period;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if self.shift_register & 0x01 > 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.shift_register = data;
+                    self.silence = false;
+                    self.request_dma();
+                },
+                None => {
+                    self.silence = true;
+                }
+            }
+        }
+    }
+
+    // 0-127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
@@ -0,0 +1,63 @@
+// Envelope generator of the pulse and noise channels
+//
+// --LC VVVV  L: loop (shared with the length counter halt), C: constant volume, V: volume/period
+//
+// Clocked by the quarter frame signal. After a write to the 4th channel register the decay
+// level restarts at 15, then goes down by one every V+1 clocks, wrapping back to 15 with loop set.
+
+pub struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+    period: u8,
+    constant_volume: bool,
+    looping: bool,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            start: false,
+            divider: 0,
+            decay: 0,
+            period: 0,
+            constant_volume: false,
+            looping: false,
+        }
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.looping = data & 0x20 > 0;
+        self.constant_volume = data & 0x10 > 0;
+        self.period = data & 0x0F;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+        } else if self.divider == 0 {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        if self.constant_volume {
+            self.period
+        } else {
+            self.decay
+        }
+    }
+}
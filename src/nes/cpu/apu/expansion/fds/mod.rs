@@ -0,0 +1,255 @@
+// Famicom Disk System audio: one 64 step wavetable channel with a frequency modulator
+//
+// $4040-$407F wave table, 6 bit samples, writable while $4089 bit 7 is set
+// $4080       MDVV VVVV  volume envelope: M: off (V is the gain), D: increase, V: speed/gain
+// $4082/$4083 wave frequency low, H--- FFFF  H: halt and reset the wave, E: bit 6 disables the envelopes
+// $4084       MDVV VVVV  modulator envelope, as $4080
+// $4085       -CCC CCCC  modulator counter (7 bit signed)
+// $4086/$4087 modulator frequency low, H--- FFFF  H: halt the modulator (allows $4088 writes)
+// $4088       ---- -MMM  appends an entry to the 32 step modulation table (each takes 2 slots)
+// $4089       W--- --VV  W: wave table write, V: master volume 2/2, 2/3, 2/4, 2/5
+// $408A       envelope speed multiplier
+// $4090/$4092 read: volume gain, modulator gain
+//
+// Both units add their frequency to a 16 bit accumulator every CPU cycle and step on overflow.
+// A modulation table entry changes the counter by 0, +1, +2, +4, reset, -4, -2, -1 and the
+// counter times the modulator gain bends the wave frequency (formula from the FDS audio page
+// of the NesDev wiki). Envelopes move their gain by one every 8 * (speed + 1) * multiplier
+// CPU cycles. The output is wave * min(gain, 32) * master volume.
+
+use super::{ExpansionAudio, APU_PULSE_LEVEL};
+
+// wave 63 * gain 32 at full master volume is about 2.4 APU pulses
+const STEP_LEVEL: f32 = APU_PULSE_LEVEL * 2.4 / (63.0 * 32.0);
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+const MODULATION_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+struct Envelope {
+    off: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            off: true,
+            increase: false,
+            speed: 0,
+            gain: 0,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8, multiplier: u8) {
+        self.off = data & 0x80 > 0;
+        self.increase = data & 0x40 > 0;
+        self.speed = data & 0x3F;
+        if self.off {
+            self.gain = self.speed;
+        }
+        self.reset_timer(multiplier);
+    }
+
+    fn reset_timer(&mut self, multiplier: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * multiplier as u32;
+    }
+
+    fn clock(&mut self, multiplier: u8) {
+        if self.off || multiplier == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.reset_timer(multiplier);
+        if self.increase {
+            if self.gain < 32 {
+                self.gain += 1;
+            }
+        } else if self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_halt: bool,
+    wave_frequency: u16,
+    wave_accumulator: u32,
+    wave_position: u8,
+    envelopes_disabled: bool,
+    volume: Envelope,
+    modulator: Envelope,
+    mod_table: [u8; 32],
+    mod_table_position: u8,
+    mod_write_position: u8,
+    mod_counter: i8,
+    mod_halt: bool,
+    mod_frequency: u16,
+    mod_accumulator: u32,
+    master_volume: u8,
+    envelope_multiplier: u8,
+    output: u8,
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_position: 0,
+            envelopes_disabled: false,
+            volume: Envelope::new(),
+            modulator: Envelope::new(),
+            mod_table: [0; 32],
+            mod_table_position: 0,
+            mod_write_position: 0,
+            mod_counter: 0,
+            mod_halt: true,
+            mod_frequency: 0,
+            mod_accumulator: 0,
+            master_volume: 0,
+            envelope_multiplier: 0xE8,
+            output: 0,
+        }
+    }
+
+    // wave frequency bent by the modulator
+    fn modulated_frequency(&self) -> u16 {
+        let pitch = self.wave_frequency as i32;
+        if self.mod_halt {
+            return self.wave_frequency;
+        }
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.modulator.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= pitch;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (pitch + temp).max(0) as u16
+    }
+
+    fn clock_modulator(&mut self) {
+        if self.mod_halt {
+            return;
+        }
+        self.mod_accumulator += self.mod_frequency as u32;
+        if self.mod_accumulator > 0xFFFF {
+            self.mod_accumulator &= 0xFFFF;
+            let entry = self.mod_table[self.mod_table_position as usize];
+            self.mod_table_position = (self.mod_table_position + 1) & 0x1F;
+            self.mod_counter = if entry == 4 {
+                0
+            } else {
+                // 7 bit signed wrap around
+                (((self.mod_counter as i16 + MODULATION_STEPS[entry as usize] as i16) << 9) >> 9) as i8
+            };
+        }
+    }
+}
+
+impl ExpansionAudio for FdsAudio {
+    fn get_name(&self) -> &'static str {
+        "fds"
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave[(addr - 0x4040) as usize] = data & 0x3F;
+            },
+            0x4080 => self.volume.write(data, self.envelope_multiplier),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.wave_halt = data & 0x80 > 0;
+                self.envelopes_disabled = data & 0x40 > 0;
+                if self.wave_halt {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+            },
+            0x4084 => self.modulator.write(data, self.envelope_multiplier),
+            0x4085 => self.mod_counter = (((data & 0x7F) << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.mod_halt = data & 0x80 > 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            },
+            0x4088 if self.mod_halt => {
+                let pos = self.mod_write_position as usize;
+                self.mod_table[pos] = data & 0x07;
+                self.mod_table[(pos + 1) & 0x1F] = data & 0x07;
+                self.mod_write_position = ((pos + 2) & 0x1F) as u8;
+            },
+            0x4089 => {
+                self.wave_write = data & 0x80 > 0;
+                self.master_volume = data & 0x03;
+            },
+            0x408A => self.envelope_multiplier = data,
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[(addr - 0x4040) as usize] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.modulator.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.envelopes_disabled && !self.wave_halt {
+            self.volume.clock(self.envelope_multiplier);
+            self.modulator.clock(self.envelope_multiplier);
+        }
+        self.clock_modulator();
+        if !self.wave_halt && !self.wave_write {
+            self.wave_accumulator += self.modulated_frequency() as u32;
+            if self.wave_accumulator > 0xFFFF {
+                self.wave_accumulator &= 0xFFFF;
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+            }
+            self.output = self.wave[self.wave_position as usize];
+        }
+    }
+
+    fn get_channel_count(&self) -> usize {
+        1
+    }
+
+    fn get_channel_name(&self, _channel: usize) -> &'static str {
+        "wave"
+    }
+
+    fn output(&self, _channel: usize) -> f32 {
+        let gain = self.volume.gain.min(32) as f32;
+        self.output as f32 * gain * MASTER_VOLUME[self.master_volume as usize] * STEP_LEVEL
+    }
+}
@@ -0,0 +1,112 @@
+// MMC5 audio: 2 pulses and 8 bit PCM
+//
+// $5000-$5003 pulse 1, as $4000-$4003 without the sweep ($5001 unused)
+// $5004-$5007 pulse 2
+// $5010       I--- ---M  I: PCM IRQ enable, M: PCM read mode
+// $5011       PCM level in write mode, writes of 0 are ignored
+// $5015       write: pulse enables (bits 0-1), read: pulse length counter status
+//
+// The pulses have no frame counter of their own timing, their envelopes and length counters
+// are clocked at a fixed 240 Hz. Only the PCM write mode is supported: read mode samples bytes
+// the CPU fetches from $8000-$BFFF, which a chip seeing only addresses cannot do.
+
+use super::ExpansionAudio;
+use crate::nes::cpu::apu::pulse::Pulse;
+
+// 240 Hz in CPU cycles
+const FRAME_PERIOD: usize = 7457;
+// PCM 0-255 spans about the DMC range
+const PCM_STEP_LEVEL: f32 = 0.5 / 255.0;
+
+pub struct Mmc5Audio {
+    pulses: [Pulse; 2],
+    pcm_read_mode: bool,
+    pcm: u8,
+    cycle: usize,
+    pulse_table: [f32; 16],
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Mmc5Audio {
+        let mut pulse_table = [0.0; 16];
+        for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        Mmc5Audio {
+            pulses: [Pulse::without_sweep(), Pulse::without_sweep()],
+            pcm_read_mode: false,
+            pcm: 0,
+            cycle: 0,
+            pulse_table,
+        }
+    }
+}
+
+impl ExpansionAudio for Mmc5Audio {
+    fn get_name(&self) -> &'static str {
+        "mmc5"
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulses[0].write_register(addr - 0x5000, data),
+            0x5004..=0x5007 => self.pulses[1].write_register(addr - 0x5004, data),
+            0x5010 => {
+                // the IRQ only fires in read mode
+                self.pcm_read_mode = data & 0x01 > 0;
+            },
+            0x5011 if !self.pcm_read_mode && data != 0 => {
+                self.pcm = data;
+            },
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0x01 > 0);
+                self.pulses[1].set_enabled(data & 0x02 > 0);
+            },
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        if addr == 0x5015 {
+            let mut status = 0x00;
+            if self.pulses[0].get_length_counter().is_active() {
+                status |= 0x01;
+            }
+            if self.pulses[1].get_length_counter().is_active() {
+                status |= 0x02;
+            }
+            Some(status)
+        } else {
+            None
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.cycle % 2 == 1 {
+            self.pulses.iter_mut().for_each(|x| x.clock_timer());
+        }
+        if self.cycle.is_multiple_of(FRAME_PERIOD) {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+        self.pulses.iter_mut().for_each(|x| x.get_length_counter_mut().update());
+        self.cycle += 1;
+    }
+
+    fn get_channel_count(&self) -> usize {
+        3
+    }
+
+    fn get_channel_name(&self, channel: usize) -> &'static str {
+        ["pulse1", "pulse2", "pcm"][channel]
+    }
+
+    fn output(&self, channel: usize) -> f32 {
+        match channel {
+            0 | 1 => self.pulse_table[self.pulses[channel].output() as usize],
+            _ => self.pcm as f32 * PCM_STEP_LEVEL,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Expansion audio of Famicom cartridges (and NSF files)
+//
+// A chip sees every CPU write and may answer CPU reads, it picks its registers by address
+// itself since they live in the cartridge space ($4040-$FFFF). The APU clocks it once per CPU
+// cycle and mixes its channels with the 2A03 ones. A mapper owning such a chip hands it to the
+// APU with `Apu::add_expansion`, the NSF player does the same from the header flags.
+//
+// Channel outputs are already scaled to the mixer output (see mixer), so that chips sit at
+// their usual level next to the 2A03. One APU pulse at volume 15 is about 0.15 there:
+//
+// VRC6     pulse volume step   = APU pulse / 15, saw 0-31 on the same steps
+// VRC7     channel full scale  ~ 1.5 APU pulses
+// N163     (sample - 8) * volume, full scale ~ 1 APU pulse shared by the enabled channels
+// 5B       logarithmic volume, full scale ~ 1 APU pulse
+// MMC5     pulses like the APU ones, PCM 0-255 ~ the DMC range
+// FDS      wave 0-63 * gain 0-32 * master volume, full scale ~ 2.4 APU pulses
+
+pub mod fds;
+pub mod mmc5;
+pub mod n163;
+pub mod sunsoft5b;
+pub mod vrc6;
+pub mod vrc7;
+
+// output of one APU pulse at volume 15, the reference for the levels above
+pub const APU_PULSE_LEVEL: f32 = 0.1494;
+
+pub trait ExpansionAudio {
+    // short name used for channel names, e.g. "vrc6"
+    fn get_name(&self) -> &'static str;
+
+    // every CPU write, the chip ignores the addresses that are not its registers
+    fn write_register(&mut self, addr: u16, data: u8);
+
+    // Some(data) if `addr` is a readable register of the chip
+    fn read_register(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    // once per CPU cycle
+    fn clock(&mut self);
+
+    fn get_channel_count(&self) -> usize;
+
+    fn get_channel_name(&self, channel: usize) -> &'static str;
+
+    // current output of a channel, in mixer units
+    fn output(&self, channel: usize) -> f32;
+
+    // IRQ output, for chips with their own (MMC5 PCM)
+    fn irq_line(&self) -> bool {
+        false
+    }
+}
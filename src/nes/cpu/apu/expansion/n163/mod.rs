@@ -0,0 +1,140 @@
+// Namco 163 audio: up to 8 wavetable channels in 128 bytes of internal RAM
+//
+// $F800 IAAA AAAA  RAM address, I: auto increment after each data access
+// $4800 DDDD DDDD  RAM data, read and write
+// $E000 -S-- ----  S: sound disable (the rest of $E000 is the mapper's PRG bank)
+//
+// Channel n (0-7) has its registers at $40 + 8 * n:
+//
+// +0 frequency low      +1 phase low
+// +2 frequency mid      +3 phase mid
+// +4 LLLL LLFF  F: frequency high, L: wave length = 256 - (L << 2) samples
+// +5 phase high         +6 wave address in samples (nibbles, low nibble first)
+// +7 ---- VVVV  volume, and in $7F bits 4-6 the number of enabled channels - 1
+//
+// The chip updates one channel every 15 CPU cycles, going from channel 7 down to the lowest
+// enabled one: phase += frequency (modulo length << 16) and the output becomes
+// (sample - 8) * volume. The real DAC plays the channels one after the other, which whines
+// when few channels are enabled, each channel here holds its value instead and is divided by
+// the number of enabled channels.
+
+use super::{ExpansionAudio, APU_PULSE_LEVEL};
+
+const UPDATE_PERIOD: usize = 15;
+// a full scale channel, (15 - 8) * 15, is about one APU pulse
+const STEP_LEVEL: f32 = APU_PULSE_LEVEL / 105.0;
+
+pub struct N163Audio {
+    ram: [u8; 0x80],
+    address: u8,
+    auto_increment: bool,
+    disabled: bool,
+    outputs: [i16; 8],
+    current: usize,
+    cycle: usize,
+}
+
+impl N163Audio {
+    pub fn new() -> N163Audio {
+        N163Audio {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            disabled: false,
+            outputs: [0; 8],
+            current: 7,
+            cycle: 0,
+        }
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    fn advance_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let regs: [u32; 8] = std::array::from_fn(|idx| self.ram[base + idx] as u32);
+        let reg = |idx: usize| regs[idx];
+        let frequency = reg(0) | reg(2) << 8 | (reg(4) & 0x03) << 16;
+        let length = (256 - (reg(4) & 0xFC)) << 16;
+        let mut phase = reg(1) | reg(3) << 8 | reg(5) << 16;
+        phase = (phase + frequency) % length;
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let sample_address = (((phase >> 16) + reg(6)) & 0xFF) as usize;
+        let byte = self.ram[sample_address >> 1];
+        let sample = if sample_address & 0x01 == 0 { byte & 0x0F } else { byte >> 4 };
+        let volume = (reg(7) & 0x0F) as i16;
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+    }
+}
+
+impl ExpansionAudio for N163Audio {
+    fn get_name(&self) -> &'static str {
+        "n163"
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr & 0xF800 {
+            0x4800 => {
+                self.ram[self.address as usize] = data;
+                self.advance_address();
+            },
+            0xE000 => self.disabled = data & 0x40 > 0,
+            0xF800 => {
+                self.address = data & 0x7F;
+                self.auto_increment = data & 0x80 > 0;
+            },
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        if addr & 0xF800 == 0x4800 {
+            let data = self.ram[self.address as usize];
+            self.advance_address();
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle < UPDATE_PERIOD {
+            return;
+        }
+        self.cycle = 0;
+        if self.disabled {
+            return;
+        }
+        let lowest = 8 - self.enabled_channels();
+        self.update_channel(self.current);
+        self.current = if self.current <= lowest { 7 } else { self.current - 1 };
+    }
+
+    fn get_channel_count(&self) -> usize {
+        8
+    }
+
+    fn get_channel_name(&self, channel: usize) -> &'static str {
+        ["wave1", "wave2", "wave3", "wave4", "wave5", "wave6", "wave7", "wave8"][channel]
+    }
+
+    // channel n of the list is the one with its registers at $40 + 8 * n
+    fn output(&self, channel: usize) -> f32 {
+        let enabled = self.enabled_channels();
+        if self.disabled || channel < 8 - enabled {
+            return 0.0;
+        }
+        self.outputs[channel] as f32 * STEP_LEVEL / enabled as f32
+    }
+}
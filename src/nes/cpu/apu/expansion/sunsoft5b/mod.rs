@@ -0,0 +1,187 @@
+// Sunsoft 5B audio, a YM2149F (AY-3-8910 family) inside the FME-7
+//
+// $C000 ---- RRRR  register select
+// $E000 DDDD DDDD  register write
+//
+// R0/R1, R2/R3, R4/R5  tone period of A, B, C (12 bit)
+// R6                   noise period (5 bit)
+// R7                   --CB Acba  C/B/A: noise disable, c/b/a: tone disable (1 = off)
+// R8, R9, R10          ---E VVVV  E: use the envelope, V: volume
+// R11/R12              envelope period (16 bit)
+// R13                  envelope shape: continue, attack, alternate, hold
+//
+// The chip runs on the CPU clock divided by 16. A tone flips when its counter reaches the
+// period, noise is a 17 bit LFSR clocked at half that rate. The envelope walks 32 levels. A
+// channel outputs its level while (tone or tone disabled) and (noise or noise disabled).
+// Levels are logarithmic, 1.5 dB per step of the 32 step scale, a fixed volume V is step
+// 2V + 1.
+
+use super::{ExpansionAudio, APU_PULSE_LEVEL};
+
+const PRESCALER: usize = 16;
+
+pub struct Sunsoft5bAudio {
+    selected: u8,
+    registers: [u8; 16],
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    noise_half: bool,
+    noise_shift: u32,
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_holding: bool,
+    envelope_attack: bool,
+    level_table: [f32; 32],
+    cycle: usize,
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Sunsoft5bAudio {
+        let mut level_table = [0.0; 32];
+        for (step, value) in level_table.iter_mut().enumerate().skip(1) {
+            *value = APU_PULSE_LEVEL * 10f32.powf((step as f32 - 31.0) * 1.5 / 20.0);
+        }
+        Sunsoft5bAudio {
+            selected: 0,
+            registers: [0; 16],
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_half: false,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_holding: false,
+            envelope_attack: false,
+            level_table,
+            cycle: 0,
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = self.registers[channel * 2] as u16 | ((self.registers[channel * 2 + 1] as u16 & 0x0F) << 8);
+        period.max(1)
+    }
+
+    fn restart_envelope(&mut self) {
+        let shape = self.registers[13];
+        self.envelope_attack = shape & 0x04 > 0;
+        self.envelope_step = 0;
+        self.envelope_counter = 0;
+        self.envelope_holding = false;
+    }
+
+    // 0-31
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack { self.envelope_step } else { 31 - self.envelope_step }
+    }
+
+    fn clock_envelope(&mut self) {
+        let period = (self.registers[11] as u16 | (self.registers[12] as u16) << 8).max(1);
+        // one step every period * 16 CPU cycles: 32 steps at twice the rate of the AY's 16
+        self.envelope_counter += 1;
+        if self.envelope_counter < period {
+            return;
+        }
+        self.envelope_counter = 0;
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        let shape = self.registers[13];
+        let (continues, alternate, hold) = (shape & 0x08 > 0, shape & 0x02 > 0, shape & 0x01 > 0);
+        if !continues {
+            // one ramp then silence
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+        } else if hold {
+            self.envelope_holding = true;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+}
+
+impl ExpansionAudio for Sunsoft5bAudio {
+    fn get_name(&self) -> &'static str {
+        "5b"
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr & 0xE000 {
+            0xC000 => self.selected = data & 0x0F,
+            0xE000 => {
+                self.registers[self.selected as usize] = data;
+                if self.selected == 13 {
+                    self.restart_envelope();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle < PRESCALER {
+            return;
+        }
+        self.cycle = 0;
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_half = !self.noise_half;
+        if self.noise_half {
+            self.noise_counter += 1;
+            if self.noise_counter >= (self.registers[6] & 0x1F).max(1) {
+                self.noise_counter = 0;
+                let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 0x01;
+                self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+            }
+        }
+
+        self.clock_envelope();
+    }
+
+    fn get_channel_count(&self) -> usize {
+        3
+    }
+
+    fn get_channel_name(&self, channel: usize) -> &'static str {
+        ["a", "b", "c"][channel]
+    }
+
+    fn output(&self, channel: usize) -> f32 {
+        let mixer = self.registers[7];
+        let tone = self.tone_outputs[channel] || mixer & (0x01 << channel) > 0;
+        let noise = self.noise_shift & 0x01 > 0 || mixer & (0x08 << channel) > 0;
+        if !(tone && noise) {
+            return 0.0;
+        }
+        let volume = self.registers[8 + channel];
+        let step = if volume & 0x10 > 0 {
+            self.envelope_level()
+        } else if volume & 0x0F == 0 {
+            0
+        } else {
+            (volume & 0x0F) * 2 + 1
+        };
+        self.level_table[step as usize]
+    }
+}
@@ -0,0 +1,207 @@
+// Konami VRC6 audio: 2 pulses and a sawtooth
+//
+// $9000/$A000 MDDD VVVV  pulse: M: mode (constant volume), D: duty (1/16 steps), V: volume
+// $9001/$A001 LLLL LLLL  pulse period low
+// $9002/$A002 E--- HHHH  pulse enable, period high
+// $9003       ---- -ABH  H: halt all, B: periods >> 8, A: periods >> 4
+// $B000       --RR RRRR  saw accumulator rate
+// $B001       LLLL LLLL  saw period low
+// $B002       E--- HHHH  saw enable, period high
+//
+// Pulses step a 16 step sequencer and output the volume while the step is at most the duty
+// value. The saw adds its rate to an 8 bit accumulator on every other of its 14 steps and
+// outputs the top 5 bits, the accumulator is cleared on the 14th step.
+//
+// VRC6b boards (mapper 26) swap the A0 and A1 lines, see `new`.
+
+use super::{ExpansionAudio, APU_PULSE_LEVEL};
+
+const STEP_LEVEL: f32 = APU_PULSE_LEVEL / 15.0;
+
+struct Pulse {
+    mode: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse {
+            mode: false,
+            duty: 0,
+            volume: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.mode = data & 0x80 > 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0F;
+            },
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 > 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = if self.step == 0 { 15 } else { self.step - 1 };
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.mode || self.step <= self.duty) { self.volume } else { 0 }
+    }
+}
+
+struct Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Saw {
+    fn new() -> Saw {
+        Saw {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 > 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+pub struct Vrc6 {
+    swap_lines: bool,
+    pulses: [Pulse; 2],
+    saw: Saw,
+    halt: bool,
+    shift: u8,
+}
+
+impl Vrc6 {
+    // `swap_lines` for VRC6b (mapper 26)
+    pub fn new(swap_lines: bool) -> Vrc6 {
+        Vrc6 {
+            swap_lines,
+            pulses: [Pulse::new(), Pulse::new()],
+            saw: Saw::new(),
+            halt: false,
+            shift: 0,
+        }
+    }
+}
+
+impl ExpansionAudio for Vrc6 {
+    fn get_name(&self) -> &'static str {
+        "vrc6"
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let reg = if self.swap_lines {
+            (addr & 0xFFFC) | ((addr & 0x01) << 1) | ((addr & 0x02) >> 1)
+        } else {
+            addr
+        } & 0xF003;
+        match reg {
+            0x9000..=0x9002 => self.pulses[0].write(reg - 0x9000, data),
+            0x9003 => {
+                self.halt = data & 0x01 > 0;
+                self.shift = if data & 0x04 > 0 { 8 } else if data & 0x02 > 0 { 4 } else { 0 };
+            },
+            0xA000..=0xA002 => self.pulses[1].write(reg - 0xA000, data),
+            0xB000..=0xB002 => self.saw.write(reg - 0xB000, data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulses[0].clock(self.shift);
+        self.pulses[1].clock(self.shift);
+        self.saw.clock(self.shift);
+    }
+
+    fn get_channel_count(&self) -> usize {
+        3
+    }
+
+    fn get_channel_name(&self, channel: usize) -> &'static str {
+        ["pulse1", "pulse2", "saw"][channel]
+    }
+
+    fn output(&self, channel: usize) -> f32 {
+        let level = match channel {
+            0 => self.pulses[0].output(),
+            1 => self.pulses[1].output(),
+            _ => self.saw.output(),
+        };
+        level as f32 * STEP_LEVEL
+    }
+}
@@ -0,0 +1,391 @@
+// Konami VRC7 audio, a cut down YM2413 (OPLL): 6 two-operator FM channels
+//
+// $9010 register select
+// $9030 register write
+//
+// $00-$07 custom instrument (instrument 0), see below
+// $10-$15 channel F-number low 8 bits
+// $20-$25 --SK BBBF  S: sustain, K: key on, B: block (octave), F: F-number bit 8
+// $30-$35 IIII VVVV  I: instrument, V: volume (3 dB steps of attenuation)
+//
+// Instrument bytes, modulator then carrier where there are two:
+// 0/1 AVEK MMMM  A: tremolo, V: vibrato, E: sustained envelope, K: key scale rate, M: multiplier
+// 2   KKTT TTTT  modulator key scale level, total level (0.75 dB steps)
+// 3   KK-C MFFF  carrier key scale level, C/M: half sine carrier/modulator, F: feedback
+// 4/5 AAAA DDDD  attack rate, decay rate
+// 6/7 SSSS RRRR  sustain level (3 dB steps), release rate
+//
+// The chip produces one sample every 36 CPU cycles (49.7 kHz). Operators are computed with
+// floats rather than the log-sin/exp tables of the real chip: the frequency is
+// F-number * 2^block / 2^19 cycles per sample times the multiplier, attenuation is summed in
+// dB (envelope, total level or volume, key scale level, tremolo) and the modulator output
+// shifts the carrier phase. Envelope times follow the OPL rate tables.
+
+use super::{ExpansionAudio, APU_PULSE_LEVEL};
+
+use std::f32::consts::PI;
+
+// instruments 1-15 of the VRC7
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+const MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+// key scale level attenuation of block 7 by the top 4 bits of the F-number, in dB
+const KSL_TABLE: [f32; 16] = [0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0];
+// key scale level 0-3: 0, 1.5, 3 and 6 dB per octave
+const KSL_SCALE: [f32; 4] = [0.0, 0.5, 1.0, 2.0];
+
+const SAMPLE_PERIOD: usize = 36;
+// envelope range of the OPLL
+const MAX_ATTENUATION: f32 = 48.0;
+// phase shift of a full scale modulator, in cycles
+const MODULATION_DEPTH: f32 = 2.0;
+const TREMOLO_DEPTH: f32 = 4.8;
+const TREMOLO_FREQUENCY: f32 = 3.7;
+const VIBRATO_DEPTH: f32 = 0.004;
+const VIBRATO_FREQUENCY: f32 = 6.4;
+const CHANNEL_LEVEL: f32 = APU_PULSE_LEVEL * 0.75;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+// 4 bit rates of the instrument, `rks` the key scale rate offset
+struct EnvelopeRates {
+    attack: u8,
+    decay: u8,
+    // dB
+    sustain_level: f32,
+    release: u8,
+    sustained: bool,
+    rks: u8,
+}
+
+#[derive(Clone, Copy)]
+struct Operator {
+    phase: f32,
+    state: EnvelopeState,
+    // attenuation in dB, 0 = full volume
+    envelope: f32,
+    outputs: [f32; 2],
+}
+
+impl Operator {
+    fn new() -> Operator {
+        Operator {
+            phase: 0.0,
+            state: EnvelopeState::Off,
+            envelope: MAX_ATTENUATION,
+            outputs: [0.0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    // seconds to go through the whole range for a rate index 0-63
+    fn decay_time(rate: u8) -> f32 {
+        19.64 / 2f32.powf((rate as f32 - 4.0) / 4.0)
+    }
+
+    fn attack_time(rate: u8) -> f32 {
+        2.826 / 2f32.powf((rate as f32 - 4.0) / 4.0)
+    }
+
+    // one sample of the envelope
+    fn clock_envelope(&mut self, rates: &EnvelopeRates, sample_rate: f32) {
+        let rate = |value: u8| if value == 0 { 0 } else { (value * 4 + rates.rks).min(63) };
+        let decay_step = |value: u8| {
+            let rate = rate(value);
+            if rate == 0 { 0.0 } else { MAX_ATTENUATION / (Self::decay_time(rate) * sample_rate) }
+        };
+        match self.state {
+            EnvelopeState::Attack => {
+                let rate = rate(rates.attack);
+                if rate >= 60 {
+                    self.envelope = 0.0;
+                } else if rate > 0 {
+                    // exponential approach, ln(480) time constants reach 0.1 dB
+                    let factor = 6.17 / (Self::attack_time(rate) * sample_rate);
+                    self.envelope -= (self.envelope + 0.1) * factor.min(1.0);
+                }
+                if self.envelope <= 0.1 {
+                    self.envelope = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            },
+            EnvelopeState::Decay => {
+                self.envelope += decay_step(rates.decay);
+                if self.envelope >= rates.sustain_level {
+                    self.envelope = rates.sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            },
+            EnvelopeState::Sustain => {
+                if !rates.sustained {
+                    self.envelope += decay_step(rates.release);
+                }
+            },
+            EnvelopeState::Release => {
+                self.envelope += decay_step(rates.release);
+            },
+            EnvelopeState::Off => {},
+        }
+        if self.envelope >= MAX_ATTENUATION {
+            self.envelope = MAX_ATTENUATION;
+            if self.state != EnvelopeState::Attack {
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+
+    // one sample, `modulation` in cycles, `attenuation` in dB without the envelope
+    fn output(&mut self, increment: f32, modulation: f32, attenuation: f32, half_sine: bool) -> f32 {
+        self.phase = (self.phase + increment).fract();
+        let mut wave = (2.0 * PI * (self.phase + modulation)).sin();
+        if half_sine && wave < 0.0 {
+            wave = 0.0;
+        }
+        let output = if self.state == EnvelopeState::Off {
+            0.0
+        } else {
+            wave * 10f32.powf(-(self.envelope + attenuation) / 20.0)
+        };
+        self.outputs = [self.outputs[1], output];
+        output
+    }
+}
+
+// what an operator needs from its channel for one sample
+struct OperatorContext {
+    patch: [u8; 8],
+    // cycles per sample before the multiplier
+    base_increment: f32,
+    rks: u8,
+    sustain: bool,
+    tremolo: f32,
+    vibrato: f32,
+    sample_rate: f32,
+}
+
+impl OperatorContext {
+    // `op` 0 is the modulator, 1 the carrier
+    fn run(&self, op: usize, operator: &mut Operator, modulation: f32, attenuation: f32) -> f32 {
+        let flags = self.patch[op];
+        let rks = if flags & 0x10 > 0 { self.rks } else { self.rks >> 2 };
+        // the channel sustain bit replaces the release rate with 5 after key off
+        let release = if operator.state == EnvelopeState::Release && self.sustain {
+            5
+        } else {
+            self.patch[6 + op] & 0x0F
+        };
+        let rates = EnvelopeRates {
+            attack: self.patch[4 + op] >> 4,
+            decay: self.patch[4 + op] & 0x0F,
+            sustain_level: (self.patch[6 + op] >> 4) as f32 * 3.0,
+            release,
+            sustained: flags & 0x20 > 0,
+            rks,
+        };
+        operator.clock_envelope(&rates, self.sample_rate);
+
+        let mut increment = self.base_increment * MULTIPLIERS[(flags & 0x0F) as usize];
+        if flags & 0x40 > 0 {
+            increment *= self.vibrato;
+        }
+        let tremolo = if flags & 0x80 > 0 { self.tremolo } else { 0.0 };
+        let half_sine = self.patch[3] & (if op == 0 { 0x08 } else { 0x10 }) > 0;
+        operator.output(increment, modulation, attenuation + tremolo, half_sine)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    output: f32,
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            fnum: 0,
+            block: 0,
+            key: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+            output: 0.0,
+        }
+    }
+
+    fn set_key(&mut self, key: bool) {
+        if key && !self.key {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key && self.key {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.key = key;
+    }
+
+    fn key_scale_level(&self, ksl: u8) -> f32 {
+        let level = KSL_TABLE[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        level.max(0.0) * KSL_SCALE[ksl as usize]
+    }
+}
+
+pub struct Vrc7Audio {
+    selected: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    sample_rate: f32,
+    // LFO positions inside their periods, 0-1, kept small so f32 doesn't run out of precision
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+    cycle: usize,
+}
+
+impl Vrc7Audio {
+    pub fn new(cpu_frequency: usize) -> Vrc7Audio {
+        Vrc7Audio {
+            selected: 0,
+            custom: [0; 8],
+            channels: [Channel::new(); 6],
+            sample_rate: cpu_frequency as f32 / SAMPLE_PERIOD as f32,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            cycle: 0,
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        let reg = self.selected;
+        match reg {
+            0x00..=0x07 => self.custom[reg as usize] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[(reg & 0x0F) as usize];
+                channel.fnum = (channel.fnum & 0x100) | data as u16;
+            },
+            0x20..=0x25 => {
+                let channel = &mut self.channels[(reg & 0x0F) as usize];
+                channel.fnum = (channel.fnum & 0xFF) | ((data as u16 & 0x01) << 8);
+                channel.block = (data >> 1) & 0x07;
+                channel.sustain = data & 0x20 > 0;
+                channel.set_key(data & 0x10 > 0);
+            },
+            0x30..=0x35 => {
+                let channel = &mut self.channels[(reg & 0x0F) as usize];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0F;
+            },
+            _ => {}
+        }
+    }
+
+    fn generate_sample(&mut self) {
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_FREQUENCY / self.sample_rate).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_FREQUENCY / self.sample_rate).fract();
+        let tremolo = (1.0 - (2.0 * PI * self.tremolo_phase).cos()) / 2.0 * TREMOLO_DEPTH;
+        let vibrato = 1.0 + (2.0 * PI * self.vibrato_phase).sin() * VIBRATO_DEPTH;
+
+        for idx in 0..6 {
+            let patch = match self.channels[idx].instrument {
+                0 => self.custom,
+                n => PATCHES[n as usize - 1],
+            };
+            let channel = &mut self.channels[idx];
+            let context = OperatorContext {
+                patch,
+                base_increment: channel.fnum as f32 * 2f32.powi(channel.block as i32) / 524288.0,
+                rks: ((channel.block << 1) | (channel.fnum >> 8) as u8) & 0x0F,
+                sustain: channel.sustain,
+                tremolo,
+                vibrato,
+                sample_rate: self.sample_rate,
+            };
+
+            let feedback = patch[3] & 0x07;
+            let modulation = if feedback == 0 {
+                0.0
+            } else {
+                (channel.modulator.outputs[0] + channel.modulator.outputs[1]) * 2f32.powi(feedback as i32 - 7)
+            };
+            let modulator_attenuation = (patch[2] & 0x3F) as f32 * 0.75 + channel.key_scale_level(patch[2] >> 6);
+            let carrier_attenuation = channel.volume as f32 * 3.0 + channel.key_scale_level(patch[3] >> 6);
+            let modulator_output = context.run(0, &mut channel.modulator, modulation, modulator_attenuation);
+            channel.output = context.run(1, &mut channel.carrier, modulator_output * MODULATION_DEPTH, carrier_attenuation);
+        }
+    }
+}
+
+impl ExpansionAudio for Vrc7Audio {
+    fn get_name(&self) -> &'static str {
+        "vrc7"
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9010 => self.selected = data,
+            0x9030 => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle >= SAMPLE_PERIOD {
+            self.cycle = 0;
+            self.generate_sample();
+        }
+    }
+
+    fn get_channel_count(&self) -> usize {
+        6
+    }
+
+    fn get_channel_name(&self, channel: usize) -> &'static str {
+        ["fm1", "fm2", "fm3", "fm4", "fm5", "fm6"][channel]
+    }
+
+    fn output(&self, channel: usize) -> f32 {
+        self.channels[channel].output * CHANNEL_LEVEL
+    }
+}
@@ -0,0 +1,207 @@
+// Frame counter (frame sequencer), $4017
+//
+// $4017 MI-- ----  M: mode (0 = 4-step, 1 = 5-step), I: IRQ inhibit
+//
+// Steps in CPU cycles after the sequencer was reset (NTSC, PAL in brackets):
+//
+// 4-step:  7457 (8313)  quarter
+//         14913 (16627) quarter + half
+//         22371 (24939) quarter
+//         29828 (33252) IRQ
+//         29829 (33253) quarter + half + IRQ
+//         29830 (33254) IRQ, back to 0
+// 5-step:  7457, 14913, 22371 as above, 29829 nothing,
+//         37281 (41565) quarter + half
+//         37282 (41566) back to 0
+//
+// Writing the inhibit bit clears the IRQ flag right away. The mode change and the sequencer
+// reset happen 3 CPU cycles after the write when it lands on an even cycle and 4 on an odd
+// one. Entering the 5-step mode clocks the quarter and half frame units immediately.
+//
+// At power $00 is written, at reset the last value is written again. The CPU only starts
+// executing RESET_DELAY cycles after that write took effect.
+
+use crate::nes::console::System;
+
+const NTSC_STEPS: [usize; 8] = [7457, 14913, 22371, 29828, 29829, 29830, 37281, 37282];
+const PAL_STEPS: [usize; 8] = [8313, 16627, 24939, 33252, 33253, 33254, 41565, 41566];
+const RESET_DELAY: usize = 9;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameClocks {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+pub struct FrameCounter {
+    steps: &'static [usize; 8],
+    five_step: bool,
+    irq_inhibit: bool,
+    irq_flag: bool,
+    cycle: usize,
+    // value and remaining CPU cycles of a delayed $4017 write
+    pending_write: Option<(u8, usize)>,
+    last_write: u8,
+}
+
+impl FrameCounter {
+    pub fn new(system: System) -> FrameCounter {
+        FrameCounter {
+            steps: match system {
+                System::Ntsc => &NTSC_STEPS,
+                System::Pal => &PAL_STEPS,
+            },
+            five_step: false,
+            irq_inhibit: false,
+            irq_flag: false,
+            cycle: RESET_DELAY,
+            pending_write: None,
+            last_write: 0x00,
+        }
+    }
+
+    // `odd_cycle` is the parity of the CPU cycle doing the write
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.last_write = data;
+        self.irq_inhibit = data & 0x40 > 0;
+        if self.irq_inhibit {
+            self.irq_flag = false;
+        }
+        self.pending_write = Some((data, if odd_cycle { 4 } else { 3 }));
+    }
+
+    // reset button: the last value is written again, the IRQ flag is cleared
+    pub fn reset(&mut self) {
+        self.irq_flag = false;
+        self.irq_inhibit = self.last_write & 0x40 > 0;
+        self.five_step = self.last_write & 0x80 > 0;
+        self.pending_write = None;
+        self.cycle = RESET_DELAY;
+    }
+
+    pub fn get_irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    // $4015 read
+    pub fn clear_irq_flag(&mut self) {
+        self.irq_flag = false;
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq_flag = true;
+        }
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) -> FrameClocks {
+        let mut clocks = FrameClocks::default();
+
+        if let Some((data, delay)) = self.pending_write {
+            if delay <= 1 {
+                self.pending_write = None;
+                self.five_step = data & 0x80 > 0;
+                self.cycle = 0;
+                if self.five_step {
+                    clocks.quarter = true;
+                    clocks.half = true;
+                }
+                return clocks;
+            }
+            self.pending_write = Some((data, delay - 1));
+        }
+
+        self.cycle += 1;
+        let steps = self.steps;
+        match self.cycle {
+            x if x == steps[0] || x == steps[2] => {
+                clocks.quarter = true;
+            },
+            x if x == steps[1] => {
+                clocks.quarter = true;
+                clocks.half = true;
+            },
+            x if x == steps[3] && !self.five_step => {
+                self.raise_irq();
+            },
+            x if x == steps[4] && !self.five_step => {
+                clocks.quarter = true;
+                clocks.half = true;
+                self.raise_irq();
+            },
+            x if x == steps[5] && !self.five_step => {
+                self.raise_irq();
+                self.cycle = 0;
+            },
+            x if x == steps[6] => {
+                clocks.quarter = true;
+                clocks.half = true;
+            },
+            x if x == steps[7] => {
+                self.cycle = 0;
+            },
+            _ => {}
+        }
+        clocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // frame counter right after a $4017 write took effect
+    fn frame_counter(data: u8) -> FrameCounter {
+        let mut frame_counter = FrameCounter::new(System::Ntsc);
+        frame_counter.write(data, false);
+        for _ in 0..3 {
+            frame_counter.clock();
+        }
+        frame_counter
+    }
+
+    // cycles after the write that clock the half frame units
+    fn half_frames(frame_counter: &mut FrameCounter, cycles: usize) -> Vec<usize> {
+        (1..=cycles).filter(|_| frame_counter.clock().half).collect()
+    }
+
+    #[test]
+    fn four_step_mode_raises_the_irq() {
+        let mut frame_counter = frame_counter(0x00);
+        assert_eq!(half_frames(&mut frame_counter, 29827), vec![14913]);
+        assert!(!frame_counter.get_irq_flag());
+        frame_counter.clock();
+        assert!(frame_counter.get_irq_flag());
+        frame_counter.clear_irq_flag();
+        assert!(frame_counter.clock().half);
+        assert!(frame_counter.get_irq_flag());
+    }
+
+    #[test]
+    fn five_step_mode_clocks_right_away_and_has_no_irq() {
+        let mut frame_counter = FrameCounter::new(System::Ntsc);
+        frame_counter.write(0x80, false);
+        frame_counter.clock();
+        frame_counter.clock();
+        assert!(frame_counter.clock().half);
+        assert_eq!(half_frames(&mut frame_counter, 37282), vec![14913, 37281]);
+        assert!(!frame_counter.get_irq_flag());
+    }
+
+    #[test]
+    fn writes_on_odd_cycles_take_a_cycle_longer() {
+        let mut frame_counter = FrameCounter::new(System::Ntsc);
+        frame_counter.write(0x80, true);
+        assert_eq!(half_frames(&mut frame_counter, 4), vec![4]);
+    }
+
+    #[test]
+    fn inhibit_clears_the_flag() {
+        let mut frame_counter = frame_counter(0x00);
+        half_frames(&mut frame_counter, 29830);
+        assert!(frame_counter.get_irq_flag());
+        frame_counter.write(0x40, false);
+        assert!(!frame_counter.get_irq_flag());
+    }
+}
@@ -0,0 +1,74 @@
+// Length counter of the pulse, triangle and noise channels
+//
+// Writing the 4th channel register loads the counter from LENGTH_TABLE with bits 7-3, but only
+// while the channel is enabled in $4015. Clocked by the half frame signal, it silences the
+// channel when it reaches 0 unless halted.
+//
+// Halt and reload writes take effect after the length clock of the same CPU cycle: `update`
+// applies them once per cycle. A reload made while the counter is clocked from a non-zero value
+// is ignored.
+
+pub const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+pub struct LengthCounter {
+    counter: u8,
+    enabled: bool,
+    halt: bool,
+    new_halt: bool,
+    // reload value and counter at the time of the write
+    reload: Option<(u8, u8)>,
+}
+
+impl LengthCounter {
+    pub fn new() -> LengthCounter {
+        LengthCounter {
+            counter: 0,
+            enabled: false,
+            halt: false,
+            new_halt: false,
+            reload: None,
+        }
+    }
+
+    // $4015 channel bit, disabling clears the counter right away
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+            self.reload = None;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.new_halt = halt;
+    }
+
+    pub fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.reload = Some((LENGTH_TABLE[(data >> 3) as usize], self.counter));
+        }
+    }
+
+    // once per CPU cycle, after the frame counter
+    pub fn update(&mut self) {
+        if let Some((value, counter)) = self.reload.take() {
+            if self.counter == counter {
+                self.counter = value;
+            }
+        }
+        self.halt = self.new_halt;
+    }
+
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
@@ -0,0 +1,69 @@
+// First order IIR filters modelling the analog output stage of the console
+//
+// NES:     high-pass 90 Hz, high-pass 440 Hz, low-pass 14 kHz
+//
+// high-pass: y[i] = a * (y[i-1] + x[i] - x[i-1]),  a = rc / (rc + dt)
+// low-pass:  y[i] = y[i-1] + a * (x[i] - y[i-1]),  a = dt / (rc + dt)
+// with rc = 1 / (2 * pi * cutoff) and dt = 1 / sample rate
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    HighPass,
+    LowPass,
+}
+
+pub struct Filter {
+    kind: Kind,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    pub fn new(kind: Kind, cutoff: f32, sample_rate: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter {
+            kind,
+            alpha: match kind {
+                Kind::HighPass => rc / (rc + dt),
+                Kind::LowPass => dt / (rc + dt),
+            },
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            Kind::HighPass => self.alpha * (self.previous_output + input - self.previous_input),
+            Kind::LowPass => self.previous_output + self.alpha * (input - self.previous_output),
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+// the three filters of the NES, in order
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn nes(sample_rate: f32) -> FilterChain {
+        FilterChain {
+            filters: vec![
+                Filter::new(Kind::HighPass, 90.0, sample_rate),
+                Filter::new(Kind::HighPass, 440.0, sample_rate),
+                Filter::new(Kind::LowPass, 14000.0, sample_rate),
+            ],
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.filters.iter_mut().fold(input, |sample, filter| filter.process(sample))
+    }
+}
@@ -0,0 +1,215 @@
+// Non-linear mixer of the 2A03 channels
+//
+// pulse_out = 95.52 / (8128 / (pulse1 + pulse2) + 100)
+// tnd_out   = 163.67 / (24329 / (3 * triangle + 2 * noise + dmc) + 100)
+// output    = pulse_out + tnd_out + expansion channels, 0.0-1.0 for the 2A03 alone
+//
+// Both formulas are precomputed into lookup tables indexed by the sums above (0-30 and
+// 0-202). The mixed signal is resampled from the CPU clock to the host rate and sent through
+// the output filters (see filter and resampler). Nothing is produced until a sample rate is
+// set. Expansion audio channels arrive already scaled and are simply added (see expansion).
+//
+// Channels can be muted or soloed at any time, a muted channel counts as level 0. As soon as
+// one channel is soloed only the soloed ones are heard.
+//
+// With channel outputs enabled every channel is also mixed on its own (as if the others were
+// silent) into a separate stream, for stem export, and the latest WAVEFORM_LENGTH samples of
+// each stream are kept for an oscilloscope view. Stems ignore mute/solo.
+
+pub mod filter;
+pub mod resampler;
+
+use self::filter::FilterChain;
+use self::resampler::Resampler;
+use super::{Channel, CHANNEL_COUNT};
+
+use std::collections::VecDeque;
+
+pub const DEFAULT_SAMPLE_RATE: usize = 44100;
+pub const WAVEFORM_LENGTH: usize = 1024;
+
+// resampled and filtered stream of one signal
+struct Output {
+    resampler: Resampler,
+    filters: FilterChain,
+    samples: Vec<f32>,
+}
+
+impl Output {
+    fn new(cpu_frequency: usize, sample_rate: usize) -> Output {
+        Output {
+            resampler: Resampler::new(cpu_frequency, sample_rate),
+            filters: FilterChain::nes(sample_rate as f32),
+            samples: vec![],
+        }
+    }
+
+    // returns the new host rate sample, if any
+    fn push(&mut self, sample: f32) -> Option<f32> {
+        let output = self.filters.process(self.resampler.push(sample)?);
+        self.samples.push(output);
+        Some(output)
+    }
+}
+
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+    cpu_frequency: usize,
+    sample_rate: Option<usize>,
+    output: Option<Output>,
+    muted: Vec<bool>,
+    soloed: Vec<bool>,
+    channel_outputs: Option<Vec<Output>>,
+    waveforms: Vec<VecDeque<f32>>,
+}
+
+impl Mixer {
+    pub fn new(cpu_frequency: usize) -> Mixer {
+        let mut pulse_table = [0.0; 31];
+        for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, value) in tnd_table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Mixer {
+            pulse_table,
+            tnd_table,
+            cpu_frequency,
+            sample_rate: None,
+            output: None,
+            muted: vec![false; CHANNEL_COUNT],
+            soloed: vec![false; CHANNEL_COUNT],
+            channel_outputs: None,
+            waveforms: vec![VecDeque::with_capacity(WAVEFORM_LENGTH); CHANNEL_COUNT],
+        }
+    }
+
+    // starts producing samples at `sample_rate`, the pending ones are dropped
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = Some(sample_rate);
+        self.output = Some(Output::new(self.cpu_frequency, sample_rate));
+        if self.channel_outputs.is_some() {
+            self.set_channel_outputs(true);
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> Option<usize> {
+        self.sample_rate
+    }
+
+    // room for `count` more expansion channels
+    pub fn add_channels(&mut self, count: usize) {
+        let total = self.muted.len() + count;
+        self.muted.resize(total, false);
+        self.soloed.resize(total, false);
+        self.waveforms.resize(total, VecDeque::with_capacity(WAVEFORM_LENGTH));
+        if self.channel_outputs.is_some() {
+            self.set_channel_outputs(true);
+        }
+    }
+
+    // per channel streams and waveforms, only produced while a sample rate is set
+    pub fn set_channel_outputs(&mut self, enabled: bool) {
+        self.channel_outputs = if enabled {
+            let (cpu_frequency, sample_rate) = (self.cpu_frequency, self.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE));
+            Some((0..self.muted.len()).map(|_| Output::new(cpu_frequency, sample_rate)).collect())
+        } else {
+            None
+        };
+        self.waveforms.iter_mut().for_each(|x| x.clear());
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.soloed[channel.index()] = soloed;
+    }
+
+    pub fn is_audible(&self, channel: Channel) -> bool {
+        if self.soloed.iter().any(|x| *x) {
+            self.soloed[channel.index()]
+        } else {
+            !self.muted[channel.index()]
+        }
+    }
+
+    // channel levels in Channel::ALL order, pulses/triangle/noise 0-15, dmc 0-127
+    pub fn mix(&self, levels: &[u8; CHANNEL_COUNT]) -> f32 {
+        let level = |channel: Channel| levels[channel.index()] as usize;
+        let pulse = self.pulse_table[level(Channel::Pulse1) + level(Channel::Pulse2)];
+        let tnd = self.tnd_table[3 * level(Channel::Triangle) + 2 * level(Channel::Noise) + level(Channel::Dmc)];
+        pulse + tnd
+    }
+
+    // one set of channel levels per CPU cycle, `expansion` holds the expansion channels in
+    // mixer units
+    pub fn push(&mut self, levels: &[u8; CHANNEL_COUNT], expansion: &[f32]) {
+        if self.output.is_none() {
+            return;
+        }
+        let mut audible = *levels;
+        for channel in Channel::ALL.iter() {
+            if !self.is_audible(*channel) {
+                audible[channel.index()] = 0;
+            }
+        }
+        let mut sample = self.mix(&audible);
+        for (n, level) in expansion.iter().enumerate() {
+            if self.is_audible(Channel::Expansion(n)) {
+                sample += level;
+            }
+        }
+        if let Some(output) = self.output.as_mut() {
+            output.push(sample);
+        }
+
+        if self.channel_outputs.is_some() {
+            let mut alone = [0; CHANNEL_COUNT];
+            for channel in Channel::ALL.iter() {
+                let idx = channel.index();
+                alone[idx] = levels[idx];
+                let sample = self.mix(&alone);
+                alone[idx] = 0;
+                self.push_channel(idx, sample);
+            }
+            for (n, level) in expansion.iter().enumerate() {
+                self.push_channel(Channel::Expansion(n).index(), *level);
+            }
+        }
+    }
+
+    fn push_channel(&mut self, idx: usize, sample: f32) {
+        let output = self.channel_outputs.as_mut().and_then(|x| x[idx].push(sample));
+        if let Some(output) = output {
+            let waveform = &mut self.waveforms[idx];
+            if waveform.len() == WAVEFORM_LENGTH {
+                waveform.pop_front();
+            }
+            waveform.push_back(output);
+        }
+    }
+
+    // filtered samples at the host rate, roughly -1.0-1.0
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.output.as_mut().map(|x| std::mem::take(&mut x.samples)).unwrap_or_default()
+    }
+
+    // samples of a channel alone, see set_channel_outputs
+    pub fn take_channel_samples(&mut self, channel: Channel) -> Vec<f32> {
+        self.channel_outputs.as_mut()
+            .map(|x| std::mem::take(&mut x[channel.index()].samples))
+            .unwrap_or_default()
+    }
+
+    // latest WAVEFORM_LENGTH samples of a channel alone, oldest first, for an oscilloscope view
+    // (there is no front end drawing one yet)
+    #[allow(dead_code)]
+    pub fn get_waveform(&self, channel: Channel) -> &VecDeque<f32> {
+        &self.waveforms[channel.index()]
+    }
+}
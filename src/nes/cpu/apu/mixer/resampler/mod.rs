@@ -0,0 +1,139 @@
+// Band-limited downsampling from the CPU clock to the host sample rate
+//
+// The APU output is a step function changing at most once per CPU cycle. Like blip_buf, every
+// change is drawn as a band-limited step instead of being sampled: the difference between
+// the new and the old level is spread over TAPS output samples with a windowed sinc impulse
+// (cutoff CUTOFF of the output rate, Blackman window), interpolated between PHASES + 1
+// precomputed phases by the position of the change inside its output period. Output samples
+// are the running sum of those differences, so a constant input comes out exactly. Everything
+// from half the output rate up is attenuated by more than 60 dB before it can fold back into
+// the audible range. Cycles without a change cost nothing.
+//
+// The output lags the input by TAPS / 2 samples.
+
+use std::f64::consts::PI;
+
+const TAPS: usize = 64;
+const PHASES: usize = 64;
+const CUTOFF: f64 = 0.42;
+// points per output period when integrating the impulse into the taps
+const INTEGRATION_STEPS: usize = 32;
+
+pub struct Resampler {
+    // output periods per input sample, always below 1
+    step: f64,
+    // position inside the current output period, 0-1
+    position: f64,
+    last: f64,
+    kernels: Vec<[f64; TAPS]>,
+    // differences still to be added to the output, `head` is the current sample
+    deltas: [f64; TAPS],
+    head: usize,
+    level: f64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: usize, output_rate: usize) -> Resampler {
+        Resampler {
+            step: output_rate as f64 / input_rate as f64,
+            position: 0.0,
+            last: 0.0,
+            kernels: (0..=PHASES).map(Self::kernel).collect(),
+            deltas: [0.0; TAPS],
+            head: 0,
+            level: 0.0,
+        }
+    }
+
+    // impulse for a change `phase / PHASES` into the output period, the taps add up to 1. Tap k
+    // is the windowed sinc integrated over output period k, so the running sum is the
+    // band-limited step itself and not an approximation that lifts the treble.
+    fn kernel(phase: usize) -> [f64; TAPS] {
+        let center = (TAPS / 2) as f64;
+        let offset = phase as f64 / PHASES as f64;
+        let mut kernel = [0.0; TAPS];
+        for (k, tap) in kernel.iter_mut().enumerate() {
+            // distance from the change to the start of output period k
+            let start = k as f64 - offset - center;
+            *tap = (0..INTEGRATION_STEPS)
+                .map(|i| Self::impulse(start + (i as f64 + 0.5) / INTEGRATION_STEPS as f64, center))
+                .sum::<f64>() / INTEGRATION_STEPS as f64;
+        }
+        let sum: f64 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|x| *x /= sum);
+        kernel
+    }
+
+    // windowed sinc `x` output periods from the change, the window spans -center-1..center
+    fn impulse(x: f64, center: f64) -> f64 {
+        let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x) };
+        let w = (x + center + 1.0) / (TAPS + 1) as f64;
+        let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+        sinc * window
+    }
+
+    // one input sample, returns an output sample when its period is complete
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        let sample = sample as f64;
+        if sample != self.last {
+            let delta = sample - self.last;
+            let phase = self.position * PHASES as f64;
+            let index = (phase as usize).min(PHASES - 1);
+            let fraction = phase - index as f64;
+            let (before, after) = (&self.kernels[index], &self.kernels[index + 1]);
+            for k in 0..TAPS {
+                let tap = before[k] + (after[k] - before[k]) * fraction;
+                self.deltas[(self.head + k) % TAPS] += delta * tap;
+            }
+            self.last = sample;
+        }
+        self.position += self.step;
+        if self.position < 1.0 {
+            return None;
+        }
+        self.position -= 1.0;
+        self.level += self.deltas[self.head];
+        self.deltas[self.head] = 0.0;
+        self.head = (self.head + 1) % TAPS;
+        Some(self.level as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(input: impl Iterator<Item = f32>) -> Vec<f32> {
+        let mut resampler = Resampler::new(1789773, 44100);
+        input.filter_map(|x| resampler.push(x)).collect()
+    }
+
+    #[test]
+    fn constant_input_comes_out_exactly() {
+        let output = resample((0..100000).map(|_| 0.5));
+        assert!(output[TAPS..].iter().all(|x| (x - 0.5).abs() < 1e-6));
+    }
+
+    // peak of the output once the kernel is filled, for a sine held for each CPU cycle
+    fn sine_peak(frequency: f64) -> f32 {
+        let output = resample((0..200000).map(|i| (2.0 * PI * frequency * i as f64 / 1789773.0).sin() as f32));
+        output[2 * TAPS..].iter().fold(0.0, |peak, x| x.abs().max(peak))
+    }
+
+    #[test]
+    fn passband_tones_keep_their_level() {
+        for frequency in [440.0, 5000.0, 12000.0, 16000.0] {
+            let peak = sine_peak(frequency);
+            assert!((peak - 1.0).abs() < 0.01, "{} Hz: peak {}", frequency, peak);
+        }
+    }
+
+    #[test]
+    fn stopband_is_60_db_down() {
+        // 22.05 kHz and up would fold back below half the output rate
+        for frequency in [22050.0, 24000.0, 30000.0, 44100.0, 100000.0, 400000.0] {
+            let peak = sine_peak(frequency);
+            assert!(peak < 0.001, "{} Hz: peak {}", frequency, peak);
+        }
+    }
+}
@@ -1 +1,305 @@
-//TODO:: implement audio
\ No newline at end of file
+// Implementation of the APU (audio processing unit) part of the 2A03
+//
+// $4000-$4003 pulse 1
+// $4004-$4007 pulse 2
+// $4008-$400B triangle
+// $400C-$400F noise
+// $4010-$4013 DMC
+// $4015       write: channel enable (length counters, DMC restart), clears the DMC IRQ
+//             read: length counter status, DMC active, frame IRQ in bit 6, DMC IRQ in bit 7,
+//             clears the frame IRQ
+// $4017       frame counter mode and IRQ inhibit
+//
+// The APU runs on the CPU clock, `clock` must be called once per CPU cycle. Every channel
+// exposes its own digital output so the channels can be mixed (or checked) separately. The
+// mixed and filtered signal is collected at the host sample rate, see mixer.
+//
+// DMC sample fetches are not done here, the owner of the CPU bus services them, see
+// `get_dma_request` and `complete_dma`.
+//
+// Expansion audio chips (see expansion) are clocked and mixed along with the 2A03 channels,
+// their channels follow the 2A03 ones as Channel::Expansion(0..) in the order they were added.
+
+pub mod dmc;
+pub mod envelope;
+pub mod expansion;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod triangle;
+
+use self::dmc::Dmc;
+use self::expansion::ExpansionAudio;
+use self::frame_counter::FrameCounter;
+use self::mixer::Mixer;
+use self::noise::Noise;
+use self::pulse::{Negate, Pulse};
+use self::triangle::Triangle;
+use crate::nes::console::System;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Expansion(usize),
+}
+
+pub const CHANNEL_COUNT: usize = 5;
+
+impl Channel {
+    pub const ALL: [Channel; CHANNEL_COUNT] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+
+    // mixer index, expansion channels follow the 2A03 ones
+    pub fn index(&self) -> usize {
+        match self {
+            Channel::Pulse1 => 0,
+            Channel::Pulse2 => 1,
+            Channel::Triangle => 2,
+            Channel::Noise => 3,
+            Channel::Dmc => 4,
+            Channel::Expansion(n) => CHANNEL_COUNT + *n,
+        }
+    }
+
+    // expansion channels are named by the APU, see Apu::get_channel_name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+            Channel::Expansion(_) => "expansion",
+        }
+    }
+}
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    mixer: Mixer,
+    expansions: Vec<Box<dyn ExpansionAudio>>,
+    expansion_levels: Vec<f32>,
+    cycle: usize,
+}
+
+impl Apu {
+    pub fn new(system: System) -> Apu {
+        Apu {
+            pulse1: Pulse::new(Negate::OnesComplement),
+            pulse2: Pulse::new(Negate::TwosComplement),
+            triangle: Triangle::new(),
+            noise: Noise::new(system),
+            dmc: Dmc::new(system),
+            frame_counter: FrameCounter::new(system),
+            mixer: Mixer::new(system.cpu_frequency()),
+            expansions: vec![],
+            expansion_levels: vec![],
+            cycle: 0,
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write_register(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write_register(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write_register(addr - 0x4010, data),
+            0x4015 => {
+                self.pulse1.set_enabled(data & 0x01 > 0);
+                self.pulse2.set_enabled(data & 0x02 > 0);
+                self.triangle.set_enabled(data & 0x04 > 0);
+                self.noise.set_enabled(data & 0x08 > 0);
+                self.dmc.set_enabled(data & 0x10 > 0);
+            },
+            0x4017 => self.frame_counter.write(data, self.cycle.is_multiple_of(2)),
+            _ => {}
+        }
+    }
+
+    // plugs in an expansion audio chip, its channels are appended to the mixer
+    pub fn add_expansion(&mut self, expansion: Box<dyn ExpansionAudio>) {
+        let count = expansion.get_channel_count();
+        self.expansion_levels.resize(self.expansion_levels.len() + count, 0.0);
+        self.mixer.add_channels(count);
+        self.expansions.push(expansion);
+    }
+
+    // CPU writes to the cartridge space, every chip picks its own registers
+    pub fn write_expansion(&mut self, addr: u16, data: u8) {
+        self.expansions.iter_mut().for_each(|x| x.write_register(addr, data));
+    }
+
+    // CPU reads from the cartridge space, None if no chip answers
+    pub fn read_expansion(&mut self, addr: u16) -> Option<u8> {
+        self.expansions.iter_mut().find_map(|x| x.read_register(addr))
+    }
+
+    // 2A03 channels followed by the expansion ones
+    pub fn get_channels(&self) -> Vec<Channel> {
+        let mut channels = Channel::ALL.to_vec();
+        channels.extend((0..self.expansion_levels.len()).map(Channel::Expansion));
+        channels
+    }
+
+    // e.g. "pulse1" or "vrc6-saw"
+    pub fn get_channel_name(&self, channel: Channel) -> String {
+        if let Channel::Expansion(mut n) = channel {
+            for expansion in self.expansions.iter() {
+                if n < expansion.get_channel_count() {
+                    return format!("{}-{}", expansion.get_name(), expansion.get_channel_name(n));
+                }
+                n -= expansion.get_channel_count();
+            }
+        }
+        channel.name().to_string()
+    }
+
+    pub fn find_channel(&self, name: &str) -> Option<Channel> {
+        self.get_channels().into_iter().find(|x| self.get_channel_name(*x).eq_ignore_ascii_case(name))
+    }
+
+    // $4015
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0x00;
+        if self.pulse1.get_length_counter().is_active() {
+            status |= 0x01;
+        }
+        if self.pulse2.get_length_counter().is_active() {
+            status |= 0x02;
+        }
+        if self.triangle.get_length_counter().is_active() {
+            status |= 0x04;
+        }
+        if self.noise.get_length_counter().is_active() {
+            status |= 0x08;
+        }
+        if self.dmc.is_active() {
+            status |= 0x10;
+        }
+        if self.frame_counter.get_irq_flag() {
+            status |= 0x40;
+        }
+        if self.dmc.get_irq_flag() {
+            status |= 0x80;
+        }
+        self.frame_counter.clear_irq_flag();
+        status
+    }
+
+    // reset button: all channels silenced, the frame counter keeps its mode
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0x00);
+        self.frame_counter.reset();
+    }
+
+    // enables audio output at `sample_rate` Hz
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.mixer.set_sample_rate(sample_rate);
+    }
+
+    // mixed samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.mixer.take_samples()
+    }
+
+    // mute/solo and per channel outputs
+    pub fn get_mixer_mut(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    // IRQ output, level triggered
+    pub fn irq_line(&self) -> bool {
+        self.frame_counter.get_irq_flag() || self.dmc.get_irq_flag() || self.expansions.iter().any(|x| x.irq_line())
+    }
+
+    // address of a pending DMC sample fetch
+    pub fn get_dma_request(&self) -> Option<u16> {
+        self.dmc.get_dma_request()
+    }
+
+    pub fn complete_dma(&mut self, data: u8) {
+        self.dmc.complete_dma(data);
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        let clocks = self.frame_counter.clock();
+        if clocks.quarter {
+            self.clock_quarter_frame();
+        }
+        if clocks.half {
+            self.clock_half_frame();
+        }
+        self.pulse1.get_length_counter_mut().update();
+        self.pulse2.get_length_counter_mut().update();
+        self.triangle.get_length_counter_mut().update();
+        self.noise.get_length_counter_mut().update();
+        let mut level = 0;
+        for expansion in self.expansions.iter_mut() {
+            expansion.clock();
+            for channel in 0..expansion.get_channel_count() {
+                self.expansion_levels[level] = expansion.output(channel);
+                level += 1;
+            }
+        }
+        if self.mixer.get_sample_rate().is_some() {
+            let levels = Channel::ALL.map(|x| self.output(x));
+            self.mixer.push(&levels, &self.expansion_levels);
+        }
+        // pulse timers run at half the CPU clock
+        if self.cycle % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.cycle += 1;
+    }
+
+    // envelopes (and the triangle linear counter)
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    // length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // digital output of a channel, 0-15 for the pulses, triangle and noise, 0-127 for the DMC,
+    // expansion channels are mixed in mixer units and read 0 here
+    pub fn output(&self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Pulse1 => self.pulse1.output(),
+            Channel::Pulse2 => self.pulse2.output(),
+            Channel::Triangle => self.triangle.output(),
+            Channel::Noise => self.noise.output(),
+            Channel::Dmc => self.dmc.output(),
+            Channel::Expansion(_) => 0,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Noise channel, $400C-$400F
+//
+// $400C --LC VVVV  L: length counter halt / envelope loop, C: constant volume, V: volume
+// $400E M--- PPPP  M: mode, P: period index
+// $400F LLLL L---  L: length counter load, restarts the envelope
+//
+// The timer reloads from the period table (in CPU cycles, NTSC and PAL differ) and clocks a
+// 15 bit LFSR. Feedback is bit 0 XOR bit 1, or bit 0 XOR bit 6 in mode 1 which gives the
+// short 93 (or 31) step "metallic" sequence. The channel is silent while bit 0 is set.
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::nes::console::System;
+
+pub const NTSC_PERIOD_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+pub const PAL_PERIOD_TABLE: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+pub struct Noise {
+    period_table: &'static [u16; 16],
+    mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(system: System) -> Noise {
+        Noise {
+            period_table: match system {
+                System::Ntsc => &NTSC_PERIOD_TABLE,
+                System::Pal => &PAL_PERIOD_TABLE,
+            },
+            mode: false,
+            timer_period: NTSC_PERIOD_TABLE[0],
+            timer: 0,
+            shift_register: 0x0001,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    // `reg` is the register number 0-3
+    pub fn write_register(&mut self, reg: u16, data: u8) {
+        match reg {
+            0x0 => {
+                self.length_counter.set_halt(data & 0x20 > 0);
+                self.envelope.write_control(data);
+            },
+            0x2 => {
+                self.mode = data & 0x80 > 0;
+                self.timer_period = self.period_table[(data & 0x0F) as usize];
+            },
+            0x3 => {
+                self.length_counter.load(data);
+                self.envelope.restart();
+            },
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn get_length_counter(&self) -> &LengthCounter {
+        &self.length_counter
+    }
+
+    pub fn get_length_counter_mut(&mut self) -> &mut LengthCounter {
+        &mut self.length_counter
+    }
+
+    // every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer <= 1 {
+            self.timer = self.timer_period;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 0x01 > 0 || !self.length_counter.is_active() {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // steps of the LFSR until it comes back to its start value
+    fn sequence_length(mode: u8) -> usize {
+        let mut noise = Noise::new(System::Ntsc);
+        noise.write_register(0x2, mode);
+        let start = noise.shift_register;
+        (1..).find(|_| {
+            for _ in 0..NTSC_PERIOD_TABLE[0] {
+                noise.clock_timer();
+            }
+            noise.shift_register == start
+        }).unwrap()
+    }
+
+    #[test]
+    fn lfsr_sequence_lengths() {
+        assert_eq!(sequence_length(0x00), 32767);
+        assert_eq!(sequence_length(0x80), 93);
+    }
+
+    #[test]
+    fn pal_uses_its_own_periods() {
+        let mut noise = Noise::new(System::Pal);
+        noise.write_register(0x2, 0x0F);
+        assert_eq!(noise.timer_period, 3778);
+    }
+}
@@ -0,0 +1,232 @@
+// Pulse (square) channels, $4000-$4003 and $4004-$4007
+//
+// $4000 DDLC VVVV  D: duty, L: length counter halt / envelope loop, C: constant volume, V: volume
+// $4001 EPPP NSSS  sweep: E: enable, P: period, N: negate, S: shift
+// $4002 TTTT TTTT  timer low
+// $4003 LLLL LTTT  L: length counter load, T: timer high
+//                  restarts the envelope and the duty sequencer
+//
+// The 11 bit timer is clocked every APU cycle (2 CPU cycles) and steps the 8 step duty sequencer.
+// The sweep unit computes target = period +/- (period >> shift). Pulse 1 negates with one's
+// complement (an extra -1), pulse 2 with two's complement. The channel is muted while the
+// period is below 8 or the target goes above $7FF, even with the sweep disabled.
+//
+// The MMC5 pulses are the same channels without the sweep unit, and without its muting.
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Negate {
+    // pulse 1
+    OnesComplement,
+    // pulse 2
+    TwosComplement,
+}
+
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+    mode: Negate,
+}
+
+impl Sweep {
+    fn new(mode: Negate) -> Sweep {
+        Sweep {
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            divider: 0,
+            reload: false,
+            mode,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0x80 > 0;
+        self.period = (data >> 4) & 0x07;
+        self.negate = data & 0x08 > 0;
+        self.shift = data & 0x07;
+        self.reload = true;
+    }
+
+    fn target_period(&self, period: u16) -> u16 {
+        let change = period >> self.shift;
+        if !self.negate {
+            period + change
+        } else {
+            match self.mode {
+                Negate::OnesComplement => period.saturating_sub(change + 1),
+                Negate::TwosComplement => period.saturating_sub(change),
+            }
+        }
+    }
+
+    fn is_muting(&self, period: u16) -> bool {
+        period < 8 || self.target_period(period) > 0x7FF
+    }
+}
+
+pub struct Pulse {
+    duty: u8,
+    sequence: u8,
+    timer_period: u16,
+    timer: u16,
+    sweep: Sweep,
+    has_sweep: bool,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(negate: Negate) -> Pulse {
+        Pulse {
+            duty: 0,
+            sequence: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep: Sweep::new(negate),
+            has_sweep: true,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    // MMC5 pulse, $x001 writes are ignored
+    pub fn without_sweep() -> Pulse {
+        let mut pulse = Pulse::new(Negate::TwosComplement);
+        pulse.has_sweep = false;
+        pulse
+    }
+
+    // `reg` is the register number 0-3
+    pub fn write_register(&mut self, reg: u16, data: u8) {
+        match reg {
+            0x0 => {
+                self.duty = data >> 6;
+                self.length_counter.set_halt(data & 0x20 > 0);
+                self.envelope.write_control(data);
+            },
+            0x1 if self.has_sweep => {
+                self.sweep.write(data);
+            },
+            0x2 => {
+                self.timer_period = (self.timer_period & 0x0700) | data as u16;
+            },
+            0x3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                self.length_counter.load(data);
+                self.envelope.restart();
+                self.sequence = 0;
+            },
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn get_length_counter(&self) -> &LengthCounter {
+        &self.length_counter
+    }
+
+    pub fn get_length_counter_mut(&mut self) -> &mut LengthCounter {
+        &mut self.length_counter
+    }
+
+    // every APU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        let sweep = &mut self.sweep;
+        if sweep.divider == 0 && sweep.enabled && sweep.shift > 0 && !sweep.is_muting(self.timer_period) {
+            self.timer_period = sweep.target_period(self.timer_period);
+        }
+        if sweep.divider == 0 || sweep.reload {
+            sweep.divider = sweep.period;
+            sweep.reload = false;
+        } else {
+            sweep.divider -= 1;
+        }
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0
+            || !self.length_counter.is_active()
+            || (self.has_sweep && self.sweep.is_muting(self.timer_period)) {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // constant volume 15, 50% duty, sequencer on a high step
+    fn pulse(negate: Negate, sweep: u8, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(negate);
+        pulse.set_enabled(true);
+        pulse.write_register(0x0, 0xBF);
+        pulse.write_register(0x1, sweep);
+        pulse.write_register(0x2, period as u8);
+        pulse.write_register(0x3, (period >> 8) as u8);
+        pulse.length_counter.update();
+        pulse.sequence = 1;
+        pulse
+    }
+
+    #[test]
+    fn pulse_1_negates_with_ones_complement() {
+        let sweep = pulse(Negate::OnesComplement, 0x89, 0x100).sweep;
+        assert_eq!(sweep.target_period(0x100), 0x100 - 0x80 - 1);
+        let sweep = pulse(Negate::TwosComplement, 0x89, 0x100).sweep;
+        assert_eq!(sweep.target_period(0x100), 0x100 - 0x80);
+    }
+
+    #[test]
+    fn sweep_updates_the_period_on_half_frames() {
+        let mut pulse = pulse(Negate::TwosComplement, 0x82, 0x100);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x140);
+    }
+
+    #[test]
+    fn short_periods_and_overflowing_targets_mute() {
+        assert_eq!(pulse(Negate::TwosComplement, 0x00, 0x008).output(), 15);
+        assert_eq!(pulse(Negate::TwosComplement, 0x00, 0x007).output(), 0);
+        // target $400 + $400 is above $7FF, muted although the sweep is disabled
+        assert_eq!(pulse(Negate::TwosComplement, 0x00, 0x400).output(), 0);
+        assert_eq!(pulse(Negate::TwosComplement, 0x08, 0x400).output(), 15);
+        assert_eq!(pulse(Negate::TwosComplement, 0x01, 0x3FF).output(), 15);
+    }
+}
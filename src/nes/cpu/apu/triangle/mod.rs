@@ -0,0 +1,176 @@
+// Triangle channel, $4008-$400B
+//
+// $4008 CRRR RRRR  C: length counter halt / linear counter control, R: linear counter reload
+// $400A TTTT TTTT  timer low
+// $400B LLLL LTTT  L: length counter load, T: timer high, sets the linear counter reload flag
+//
+// The timer is clocked every CPU cycle and steps the 32 step sequencer 15..0 0..15, but only
+// while both the linear and the length counter are non-zero. A stopped sequencer holds its
+// level instead of going to 0.
+//
+// With a period below 2 the channel produces an ultrasonic wave that the output filters turn
+// into its average, so the sequencer is left alone and the mid level is output instead of
+// aliasing noise.
+
+use super::length_counter::LengthCounter;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+const ULTRASONIC_LEVEL: u8 = 7;
+
+pub struct Triangle {
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    sequence: u8,
+    length_counter: LengthCounter,
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle {
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            timer_period: 0,
+            timer: 0,
+            sequence: 0,
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    // `reg` is the register number 0-3
+    pub fn write_register(&mut self, reg: u16, data: u8) {
+        match reg {
+            0x0 => {
+                self.control = data & 0x80 > 0;
+                self.length_counter.set_halt(self.control);
+                self.linear_reload_value = data & 0x7F;
+            },
+            0x2 => {
+                self.timer_period = (self.timer_period & 0x0700) | data as u16;
+            },
+            0x3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                self.length_counter.load(data);
+                self.linear_reload = true;
+            },
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn get_length_counter(&self) -> &LengthCounter {
+        &self.length_counter
+    }
+
+    pub fn get_length_counter_mut(&mut self) -> &mut LengthCounter {
+        &mut self.length_counter
+    }
+
+    // every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear_counter > 0 && self.length_counter.is_active() && !self.is_ultrasonic() {
+                self.sequence = (self.sequence + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    fn is_ultrasonic(&self) -> bool {
+        self.timer_period < 2
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        if self.is_ultrasonic() && self.linear_counter > 0 && self.length_counter.is_active() {
+            ULTRASONIC_LEVEL
+        } else {
+            SEQUENCE[self.sequence as usize]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(linear: u8, period: u16) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.set_enabled(true);
+        triangle.write_register(0x0, linear);
+        triangle.write_register(0x2, period as u8);
+        triangle.write_register(0x3, (period >> 8) as u8);
+        triangle.length_counter.update();
+        triangle.clock_quarter_frame();
+        triangle
+    }
+
+    #[test]
+    fn sequencer_steps_while_both_counters_run() {
+        let mut triangle = triangle(0x10, 0x002);
+        assert_eq!(triangle.output(), 15);
+        for _ in 0..3 * 16 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), 0);
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 1);
+    }
+
+    #[test]
+    fn stopped_sequencer_holds_its_level() {
+        let mut silent = triangle(0x00, 0x002);
+        for _ in 0..3 * 5 {
+            silent.clock_timer();
+        }
+        assert_eq!(silent.output(), 15);
+        let mut short = triangle(0x01, 0x002);
+        for _ in 0..3 * 5 {
+            short.clock_timer();
+        }
+        assert_eq!(short.output(), 10);
+        short.clock_quarter_frame();
+        for _ in 0..3 * 5 {
+            short.clock_timer();
+        }
+        assert_eq!(short.output(), 10);
+    }
+
+    #[test]
+    fn ultrasonic_periods_output_the_mid_level() {
+        let mut triangle = triangle(0x10, 0x001);
+        for _ in 0..10 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), ULTRASONIC_LEVEL);
+    }
+}
@@ -1,9 +1,7 @@
 // Implementation of 2A03 chip used in NES CPU
 // It consists of MOS 6502 processor and APU
 // Apart from 6502, it also contains 22 extra registers for sound generation, joystick reading and OAM DMA transferring.
+// They sit at $4000-$4017, the APU and the memory map of the processor decode them.
 
+pub mod apu;
 pub mod processor;
-
-struct CPU {
-    special_registers: [u8;22],
-}
\ No newline at end of file
@@ -7,30 +7,183 @@
 //6000-7FFF is often cartridge WRAM. Since emulators usually emulate this whether it actually exists in the cartridge or not, there's a little bit of controversy about NES headers not adequately representing a cartridge.
 //8000-FFFF is the main area the cartridge ROM is mapped to in memory. Sometimes it can be bank switched, usually in 32k, 16k, or 8k sized banks.
 
+use crate::nes::console::System;
+use crate::nes::cpu::apu::Apu;
+use crate::nes::controller::Controller;
+use crate::nes::mapper::{Mapper, NoCartridge};
+use crate::nes::ppu::Ppu;
+
 pub struct Memory {
-    data: [u8; 0x10000]
+    data: [u8; 0x10000],
+    ppu: Ppu,
+    apu: Apu,
+    mapper: Box<dyn Mapper>,
+    controllers: [Controller; 2],
+    open_bus: u8,
+    // page written to $4014, the DMA itself is run by the console
+    oam_dma: Option<u8>,
+    // the current cycle repeats a read a DMC fetch halted
+    dma_halted: bool,
 }
 
 impl Memory {
     pub fn new() -> Memory {
         Memory {
-            data:[0; 0x10000]
+            data:[0; 0x10000],
+            ppu: Ppu::new(System::Ntsc),
+            apu: Apu::new(System::Ntsc),
+            mapper: Box::new(NoCartridge),
+            controllers: [Controller::new(), Controller::new()],
+            open_bus: 0x00,
+            oam_dma: None,
+            dma_halted: false,
         }
     }
 
-    pub fn load(&mut self, data: &Vec<u8>, from: usize) {
-        let mut pos = from;
-        for byte in data.iter() {
-            self.data[pos] = *byte;
-            pos += 1;
+    pub fn set_address(&mut self, data: u8, at: usize) {
+        self.open_bus = data;
+        match at {
+            0x0000..=0x1FFF => {
+                self.data[at & 0x07FF] = data;
+                return;
+            },
+            0x2000..=0x3FFF => {
+                self.ppu.write_register(at as u16, data, self.mapper.as_mut());
+                self.mapper.cpu_write(at as u16, data);
+            },
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(at as u16, data),
+            0x4014 => self.oam_dma = Some(data),
+            0x4016 => self.controllers.iter_mut().for_each(|x| x.write(data)),
+            0x4020..=0xFFFF => {
+                // the cartridge and its audio chip decode their own registers
+                self.apu.write_expansion(at as u16, data);
+                self.mapper.cpu_write(at as u16, data);
+                return;
+            },
+            _ => {}
         }
+        self.data[at] = data;
     }
 
-    pub fn set_address(&mut self, data: u8, at: usize) {
-        self.data[at] = data;
+    pub fn get_instruction(&mut self, idx: usize) -> u8 {
+        let data = match idx {
+            0x0000..=0x1FFF => self.data[idx & 0x07FF],
+            0x2000..=0x3FFF => self.ppu.read_register(idx as u16, self.mapper.as_mut()),
+            0x4015 => self.apu.read_status(),
+            0x4016 | 0x4017 => {
+                let controller = &mut self.controllers[idx - 0x4016];
+                // the halted read already clocked the port, the dummy and alignment reads in
+                // between don't as the port stays selected
+                if self.dma_halted {
+                    controller.read();
+                }
+                controller.read()
+            },
+            0x4020..=0xFFFF => {
+                let addr = idx as u16;
+                let data = self.mapper.cpu_read(addr);
+                self.apu.read_expansion(addr).or(data).unwrap_or(self.open_bus)
+            },
+            _ => self.data[idx]
+        };
+        self.open_bus = data;
+        data
+    }
+
+    // read without side effects, for debuggers and test harnesses
+    pub fn peek(&self, idx: usize) -> u8 {
+        match idx {
+            0x0000..=0x1FFF => self.data[idx & 0x07FF],
+            0x4020..=0xFFFF => self.mapper.cpu_peek(idx as u16).unwrap_or(self.open_bus),
+            _ => self.data[idx]
+        }
+    }
+
+    // PPU bus read without side effects
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.ppu.read(addr, self.mapper.as_ref())
+    }
+
+    pub fn set_ppu(&mut self, ppu: Ppu) {
+        self.ppu = ppu;
+    }
+
+    pub fn get_ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn get_ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    // one PPU dot, rendering fetches go through the cartridge
+    pub fn clock_ppu(&mut self) {
+        self.ppu.clock(self.mapper.as_mut());
+        self.mapper.clock_ppu();
+    }
+
+    pub fn set_dma_halted(&mut self, halted: bool) {
+        self.dma_halted = halted;
     }
 
-    pub fn get_instruction(&self, idx: usize) -> u8 {
-        self.data[idx]
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
     }
-}
\ No newline at end of file
+
+    pub fn set_mapper(&mut self, mapper: Box<dyn Mapper>) {
+        self.mapper = mapper;
+    }
+
+    pub fn get_mapper(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+
+    pub fn get_mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+
+    pub fn set_apu(&mut self, apu: Apu) {
+        self.apu = apu;
+    }
+
+    pub fn get_apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.controllers[port].set_buttons(buttons);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_is_mirrored_up_to_1fff() {
+        let mut memory = Memory::new();
+        memory.set_address(0x5A, 0x0801);
+        assert_eq!(memory.get_instruction(0x0001), 0x5A);
+        assert_eq!(memory.get_instruction(0x1801), 0x5A);
+        assert_eq!(memory.peek(0x1001), 0x5A);
+        memory.set_address(0xA5, 0x1FFF);
+        assert_eq!(memory.peek(0x07FF), 0xA5);
+    }
+
+    #[test]
+    fn a_read_halted_by_dmc_dma_clocks_the_controller_twice() {
+        let mut memory = Memory::new();
+        memory.set_buttons(0, 0x05);
+        memory.set_address(0x01, 0x4016);
+        memory.set_address(0x00, 0x4016);
+        assert_eq!(memory.get_instruction(0x4016), 0x41);
+        memory.set_dma_halted(true);
+        // B is lost, Select is read
+        assert_eq!(memory.get_instruction(0x4016), 0x41);
+        memory.set_dma_halted(false);
+        assert_eq!(memory.get_instruction(0x4016), 0x40);
+    }
+}
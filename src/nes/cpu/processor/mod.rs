@@ -32,9 +32,17 @@
 
 // refer http://www.atarihq.com/danb/files/64doc.txt for cycle
 // vflag http://www.6502.org/tutorials/vflag.html
+//
+//Interrupts:
+//NMI is edge triggered (vector $FFFA), IRQ is level triggered and masked by I (vector $FFFE).
+//Both lines are polled between instructions, NMI wins when both are active. The sequence
+//takes 7 cycles like BRK but pushes SR with B clear.
 
 pub mod memory;
 
+// read-modify-write instruction, gets the operand and returns the value to write back
+type ReadWrite = dyn Fn(&mut Processor, u8) -> u8;
+
 #[allow(non_snake_case)]
 pub struct Processor {
     PC: u16,
@@ -47,29 +55,154 @@ pub struct Processor {
     new_instruction: bool,
     current_instruction: u8,
     cycle: usize,
-    arg: u16 // useful in 3bytes opcodes
+    arg: u16, // useful in 3bytes opcodes
+    operand: u8, // value read by read-modify-write instructions
+    page_crossed: bool, // indexed address in another page than its base
+    trace: bool,
+    irq_line: bool,
+    nmi_line: bool,
+    nmi_pending: bool,
+    interrupt_vector: Option<u16>, // set while an interrupt sequence runs
+    interrupt_poll: bool, // an interrupt sequence follows the current instruction
+    last_poll: bool, // poll of the previous cycle
+    branch_skips_poll: bool,
 }
 
 impl Processor {
-    pub fn new(data: &Vec<u8>) -> Processor {
-        let mut memory = memory::Memory::new();
-        memory.load(data, 0x8000);
+    // the cartridge is plugged into the memory afterwards, see Memory::set_mapper
+    pub fn new() -> Processor {
+        let memory = memory::Memory::new();
         Processor {
             PC: 0x8000,
             AC: 0x00,
             X: 0x00,
             Y: 0x00,
             SR: 0x30,
-            SP: 0xFF, //top down stack pointer from 0x0100 - 0x01FF
+            SP: 0x00, //top down stack pointer from 0x0100 - 0x01FF, $FD after the power on reset
             ram: memory,
             new_instruction: true,
             current_instruction: 0x00,
             cycle: 0x00,
-            arg: 0x00
+            arg: 0x00,
+            operand: 0x00,
+            page_crossed: false,
+            trace: true,
+            irq_line: false,
+            nmi_line: false,
+            nmi_pending: false,
+            interrupt_vector: None,
+            interrupt_poll: false,
+            last_poll: false,
+            branch_skips_poll: false,
+        }
+    }
+
+    // Reset line: PC is loaded from the vector at $FFFC-$FFFD, SP goes down by 3 as the
+    // pushes of the interrupt sequence are turned into reads, I flag is set, A/X/Y are kept.
+    pub fn reset(&mut self) {
+        let low = self.ram.get_instruction(0xFFFC) as u16;
+        let high = self.ram.get_instruction(0xFFFD) as u16;
+        self.PC = (high << 8) | low;
+        self.SP = self.SP.wrapping_sub(3);
+        self.SR |= 0x04;
+        self.nmi_pending = false;
+        self.interrupt_vector = None;
+        self.interrupt_poll = false;
+        self.reset_instruction();
+    }
+
+    // Starts the subroutine at `addr` as JSR would, its RTS lands on `return_addr`. Used to
+    // drive code that has no reset vector of its own (NSF INIT/PLAY).
+    pub fn call(&mut self, addr: u16, return_addr: u16) {
+        let pushed = return_addr.wrapping_sub(1);
+        self.ram.set_address((pushed >> 8) as u8, 0x100 | self.SP as usize);
+        self.SP = self.SP.wrapping_sub(1);
+        self.ram.set_address((pushed & 0xFF) as u8, 0x100 | self.SP as usize);
+        self.SP = self.SP.wrapping_sub(1);
+        self.PC = addr;
+        self.interrupt_vector = None;
+        self.interrupt_poll = false;
+        self.reset_instruction();
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.PC
+    }
+
+    pub fn set_accumulator(&mut self, value: u8) {
+        self.AC = value;
+    }
+
+    pub fn set_x(&mut self, value: u8) {
+        self.X = value;
+    }
+
+    // true between two instructions
+    pub fn is_instruction_boundary(&self) -> bool {
+        self.new_instruction && self.interrupt_vector.is_none()
+    }
+
+    pub fn set_irq_line(&mut self, active: bool) {
+        self.irq_line = active;
+    }
+
+    pub fn set_nmi_line(&mut self, active: bool) {
+        if active && !self.nmi_line {
+            self.nmi_pending = true;
         }
+        self.nmi_line = active;
     }
 
+    pub fn get_memory(&self) -> &memory::Memory {
+        &self.ram
+    }
+
+    pub fn get_memory_mut(&mut self) -> &mut memory::Memory {
+        &mut self.ram
+    }
+
+    // print registers on every cycle, turned off when running headless
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    // Interrupts are polled before the last cycle of an instruction: the lines and the I flag
+    // seen at the start of that cycle decide whether the next one is an interrupt sequence. A
+    // taken branch that stays in its page doesn't poll on its last cycle, and like the
+    // interrupt sequences BRK always runs the first instruction of its handler. An NMI seen
+    // until BRK or IRQ pushes the status takes over their vector.
     pub fn execute_next_instruction(&mut self) {
+        let poll = self.nmi_pending || (self.irq_line && self.SR & 0x04 == 0);
+        if self.new_instruction && self.interrupt_poll {
+            self.interrupt_poll = false;
+            if self.nmi_pending {
+                self.nmi_pending = false;
+                self.interrupt_vector = Some(0xFFFA);
+            } else {
+                self.interrupt_vector = Some(0xFFFE);
+            }
+        }
+        if let Some(vector) = self.interrupt_vector {
+            self.execute_interrupt(vector);
+            self.last_poll = poll;
+            return;
+        }
+
+        self.execute_cycle();
+        if self.new_instruction {
+            self.interrupt_poll = if self.current_instruction == 0x00 {
+                false
+            } else if self.branch_skips_poll {
+                self.last_poll
+            } else {
+                poll
+            };
+            self.branch_skips_poll = false;
+        }
+        self.last_poll = poll;
+    }
+
+    fn execute_cycle(&mut self) {
         let nibble = if self.new_instruction {
             let instruction = self.ram.get_instruction(self.PC as usize);
             self.PC += 1;
@@ -79,8 +212,10 @@ impl Processor {
             self.current_instruction
         };
 
-        println!("inst1 :: nibble: {:#04X?}, new_inst: {},  cur_inst: {:#04X}, cycle: {}, arg: {}",nibble, self.new_instruction, self.current_instruction, self.cycle, self.arg);
-        println!("before registers AC: {:#X?}, X: {:#X?}, Y: {:#X?}, SP: {:#X?}, PC: {:?}, SR: {:08b}", self.AC, self.X, self.Y, self.SP, self.PC, self.SR);
+        if self.trace {
+            println!("inst1 :: nibble: {:#04X?}, new_inst: {},  cur_inst: {:#04X}, cycle: {}, arg: {}",nibble, self.new_instruction, self.current_instruction, self.cycle, self.arg);
+            println!("before registers AC: {:#X?}, X: {:#X?}, Y: {:#X?}, SP: {:#X?}, PC: {:?}, SR: {:08b}", self.AC, self.X, self.Y, self.SP, self.PC, self.SR);
+        }
 
         match nibble & 0xF0 {
             0x00 => {
@@ -96,27 +231,28 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.ram.set_address((self.PC >> 8) as u8, self.SP as usize);
-                                self.SP -= 1;
+                                self.ram.set_address((self.PC >> 8) as u8, 0x100 | self.SP as usize);
+                                self.SP = self.SP.wrapping_sub(1);
                                 self.cycle += 1;
                             },
                             0x3 => {
-                                self.ram.set_address((self.PC & 0xFF) as u8, self.SP as usize);
-                                self.SP -= 1;
+                                self.ram.set_address((self.PC & 0xFF) as u8, 0x100 | self.SP as usize);
+                                self.SP = self.SP.wrapping_sub(1);
                                 self.cycle += 1;
                             },
                             0x4 => {
-                                self.ram.set_address((self.SR | 0x10) as u8, self.SP as usize);
-                                self.SP -= 1;
+                                self.ram.set_address(self.SR | 0x30, 0x100 | self.SP as usize);
+                                self.SP = self.SP.wrapping_sub(1);
+                                self.arg = self.hijack_vector(0xFFFE);
                                 self.cycle += 1;
                             },
                             0x5 => {
-                                self.PC |= ((self.ram.get_instruction(0xFFFE) as u16) << 8) as u16;
+                                self.operand = self.ram.get_instruction(self.arg as usize);
+                                self.SR |= 0x04;
                                 self.cycle += 1;
                             },
                             0x6 => {
-                                self.PC |= self.ram.get_instruction(0xFFFF) as u16;
-                                self.SR |= 0x10; // set B flag
+                                self.PC = ((self.ram.get_instruction(self.arg as usize + 1) as u16) << 8) | self.operand as u16;
                                 self.reset_instruction();
                             },
                             _ => {}
@@ -142,8 +278,9 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.ram.set_address(self.SR, self.SP as usize);
-                                self.SP -= 1;
+                                // B and bit 5 only exist on the stack
+                                self.ram.set_address(self.SR | 0x30, 0x100 | self.SP as usize);
+                                self.SP = self.SP.wrapping_sub(1);
                                 self.reset_instruction();
                             },
                             _ => {}
@@ -164,7 +301,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_read_write(&Self::instruction_asl_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x10 => {
@@ -197,7 +334,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_with_index_read_write(true, &Self::instruction_asl_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x20 => {
@@ -217,13 +354,13 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x3 => {
-                                self.ram.set_address((self.PC>>8) as u8, self.SP as usize );
-                                self.SP -= 1;
+                                self.ram.set_address((self.PC>>8) as u8, 0x100 | self.SP as usize );
+                                self.SP = self.SP.wrapping_sub(1);
                                 self.cycle += 1;
                             },
                             0x4 => {
-                                self.ram.set_address((self.PC & 0xFF) as u8, self.SP as usize );
-                                self.SP -= 1;
+                                self.ram.set_address((self.PC & 0xFF) as u8, 0x100 | self.SP as usize );
+                                self.SP = self.SP.wrapping_sub(1);
                                 self.cycle += 1;
                             },
                             0x5 => {
@@ -245,7 +382,7 @@ impl Processor {
                         self.addressing_mode_zero_page_read(&Self::instruction_and);
                     },
                     0x06 => {
-                        self.addressing_mode_zero_page_write(&Self::instruction_rol_memory);
+                        self.addressing_mode_zero_page_read_write(&Self::instruction_rol_memory);
                     },
                     0x08 => {
                         // PLP 4 cycles, 1 byte
@@ -257,11 +394,11 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.SP += 1;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x3 => {
-                                self.SR = self.ram.get_instruction(self.SP as usize);
+                                self.SR = (self.ram.get_instruction(0x100 | self.SP as usize) & 0xCF) | 0x20;
                                 self.reset_instruction();
                             },
                             _ => {}
@@ -282,9 +419,9 @@ impl Processor {
                         self.addressing_mode_absolute_read(&Self::instruction_and);
                     },
                     0x0E => {
-                        self.addressing_mode_absolute_write(&Self::instruction_rol_memory);
+                        self.addressing_mode_absolute_read_write(&Self::instruction_rol_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x30 => {
@@ -301,7 +438,7 @@ impl Processor {
                         self.addressing_mode_zero_page_with_index_read(true, &Self::instruction_and);
                     },
                     0x06 => {
-                        self.addressing_mode_zero_page_with_index_write(true, &Self::instruction_rol_memory);
+                        self.addressing_mode_zero_page_with_index_read_write(true, &Self::instruction_rol_memory);
                     },
                     0x08 => {
                         self.addressing_mode_implied_or_accumulator(&Self::instruction_sec);
@@ -315,9 +452,9 @@ impl Processor {
                         self.addressing_mode_absolute_with_index_read(true, &Self::instruction_and);
                     },
                     0x0E => {
-                        self.addressing_mode_absolute_with_index_write(true, &Self::instruction_rol_memory);
+                        self.addressing_mode_absolute_with_index_read_write(true, &Self::instruction_rol_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x40 => {
@@ -332,21 +469,21 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.SP += 1;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x3 => {
-                                self.SR = self.ram.get_instruction(self.SP as usize);
-                                self.SP += 1;
+                                self.SR = (self.ram.get_instruction(0x100 | self.SP as usize) & 0xCF) | 0x20;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x4 => {
-                                self.arg = self.ram.get_instruction(self.SP as usize) as u16;
-                                self.SP += 1;
+                                self.arg = self.ram.get_instruction(0x100 | self.SP as usize) as u16;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x5 => {
-                                self.arg = (self.ram.get_instruction(self.SP as usize) as u16) << 8 | self.arg;
+                                self.arg |= (self.ram.get_instruction(0x100 | self.SP as usize) as u16) << 8;
                                 self.PC = self.arg;
                                 self.reset_instruction();
                             },
@@ -373,8 +510,8 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.ram.set_address(self.AC, self.SP as usize);
-                                self.SP -= 1;
+                                self.ram.set_address(self.AC, 0x100 | self.SP as usize);
+                                self.SP = self.SP.wrapping_sub(1);
                                 self.reset_instruction();
                             },
                             _ => {}
@@ -413,7 +550,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_read_write(&Self::instruction_lsr_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x50 => {
@@ -446,7 +583,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_with_index_read_write(true, &Self::instruction_lsr_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x60 => {
@@ -461,16 +598,16 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.SP += 1;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x3 => {
-                                self.arg = self.ram.get_instruction(self.SP as usize) as u16;
-                                self.SP += 1;
+                                self.arg = self.ram.get_instruction(0x100 | self.SP as usize) as u16;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x4 => {
-                                self.arg = (self.ram.get_instruction(self.SP as usize) as u16) << 8 | self.arg;
+                                self.arg |= (self.ram.get_instruction(0x100 | self.SP as usize) as u16) << 8;
                                 self.cycle += 1;
                             },
                             0x5 => {
@@ -502,11 +639,13 @@ impl Processor {
                                 self.cycle += 1;
                             },
                             0x2 => {
-                                self.SP += 1;
+                                self.SP = self.SP.wrapping_add(1);
                                 self.cycle += 1;
                             },
                             0x3 => {
-                                self.AC = self.ram.get_instruction(self.SP as usize);
+                                self.AC = self.ram.get_instruction(0x100 | self.SP as usize);
+                                self.set_flag_1st_bit_zero(self.AC);
+                                self.set_flag_7th_bit_nagetive(self.AC);
                                 self.reset_instruction();
                             },
                             _ => {}
@@ -532,7 +671,7 @@ impl Processor {
                             },
                             0x2 => {
                                 let high=self.ram.get_instruction(self.PC as usize) as u16;
-                                self.arg = (high << 8) | self.arg;
+                                self.arg |= high << 8;
                                 self.cycle += 1;
                             },
                             0x3 => {
@@ -540,7 +679,8 @@ impl Processor {
                             },
                             0x4 => {
                                 let low= self.ram.get_instruction(self.arg as usize) as u16;
-                                let high = self.ram.get_instruction((self.arg+1) as usize) as u16;
+                                // the pointer doesn't carry into its high byte
+                                let high = self.ram.get_instruction(((self.arg & 0xFF00) | (self.arg.wrapping_add(1) & 0x00FF)) as usize) as u16;
                                 self.PC=(high << 8) | low;
                                 self.reset_instruction();
                             },
@@ -555,7 +695,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_read_write(&Self::instruction_ror_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x70 => {
@@ -601,7 +741,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_with_index_read_write(true, &Self::instruction_ror_memory);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x80 => {
@@ -652,7 +792,7 @@ impl Processor {
                         // STX absolute, 4 cycle, 3 bytes
                         self.addressing_mode_absolute_write(&Self::instruction_stx);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0x90 => {
@@ -713,7 +853,7 @@ impl Processor {
                         //STA absolute X, 5 cycle
                         self.addressing_mode_absolute_with_index_write(true, &Self::instruction_sta);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0xA0 => {
@@ -764,10 +904,9 @@ impl Processor {
                             },
                             0x01 => {
                                 self.X = self.AC;
-                                self.SR |= self.X & 0x80;
-                                self.SR |= if self.X == 0x0 {0x2} else {0x0};
-                                self.new_instruction = true;
-                                self.cycle = 0;
+                                self.set_flag_7th_bit_nagetive(self.X);
+                                self.set_flag_1st_bit_zero(self.X);
+                                self.reset_instruction();
                             },
                             _ => {
                                 //unreachable
@@ -783,7 +922,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_read(&Self::instruction_ldx);
                     },
-                    _ => {},
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0xB0 => {
@@ -834,7 +973,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_with_index_read(false, &Self::instruction_ldx);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0xC0 => {
@@ -885,7 +1024,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_read_write(&Self::instruction_dec);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0xD0 => {
@@ -915,7 +1054,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_with_index_read_write(true, &Self::instruction_dec);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0xE0 => {
@@ -975,7 +1114,7 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_read_write(&Self::instruction_inc);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             0xF0 => {
@@ -1004,14 +1143,126 @@ impl Processor {
                     0x0E => {
                         self.addressing_mode_absolute_with_index_read_write(true, &Self::instruction_inc);
                     },
-                    _ => {}
+                    _ => self.execute_unofficial(nibble),
                 }
             },
             _ => {}
         }
 
-        println!("after registers AC: {:#X?}, X: {:#X?}, Y: {:#X?}, SP: {:#X?}, PC: {:?}, SR: {:08b}", self.AC, self.X, self.Y, self.SP, self.PC, self.SR);
-        println!();
+        if self.trace {
+            println!("after registers AC: {:#X?}, X: {:#X?}, Y: {:#X?}, SP: {:#X?}, PC: {:?}, SR: {:08b}", self.AC, self.X, self.Y, self.SP, self.PC, self.SR);
+            println!();
+        }
+    }
+
+    // the pushed status keeps its B flag when an NMI takes over BRK
+    fn hijack_vector(&mut self, vector: u16) -> u16 {
+        if vector == 0xFFFE && self.nmi_pending {
+            self.nmi_pending = false;
+            0xFFFA
+        } else {
+            vector
+        }
+    }
+
+    // NMI/IRQ sequence, 7 cycles
+    fn execute_interrupt(&mut self, vector: u16) {
+        match self.cycle {
+            0x0 => {
+                // the opcode fetch is discarded
+                self.new_instruction = false;
+                self.cycle += 1;
+            },
+            0x1 => {
+                self.cycle += 1;
+            },
+            0x2 => {
+                self.ram.set_address((self.PC >> 8) as u8, 0x100 | self.SP as usize);
+                self.SP = self.SP.wrapping_sub(1);
+                self.cycle += 1;
+            },
+            0x3 => {
+                self.ram.set_address((self.PC & 0xFF) as u8, 0x100 | self.SP as usize);
+                self.SP = self.SP.wrapping_sub(1);
+                self.cycle += 1;
+            },
+            0x4 => {
+                self.ram.set_address((self.SR & !0x10) | 0x20, 0x100 | self.SP as usize);
+                self.SP = self.SP.wrapping_sub(1);
+                self.interrupt_vector = Some(self.hijack_vector(vector));
+                self.cycle += 1;
+            },
+            0x5 => {
+                self.arg = self.ram.get_instruction(vector as usize) as u16;
+                self.SR |= 0x04;
+                self.cycle += 1;
+            },
+            _ => {
+                self.PC = ((self.ram.get_instruction(vector as usize + 1) as u16) << 8) | self.arg;
+                self.interrupt_vector = None;
+                self.reset_instruction();
+            }
+        }
+    }
+
+    // Unofficial opcodes. The combined ones (SLO RLA SRE RRA SAX LAX DCP ISB) sit in the
+    // columns next to the official instructions they are made of and use the same addressing
+    // modes, most of the others are NOPs reading an operand or halt the CPU (KIL).
+    fn execute_unofficial(&mut self, opcode: u8) {
+        let indexed = opcode & 0x10 > 0;
+        let read_write: Option<&ReadWrite> = match opcode >> 5 {
+            0x0 => Some(&Self::instruction_slo),
+            0x1 => Some(&Self::instruction_rla),
+            0x2 => Some(&Self::instruction_sre),
+            0x3 => Some(&Self::instruction_rra),
+            0x6 => Some(&Self::instruction_dcp),
+            0x7 => Some(&Self::instruction_isb),
+            _ => None,
+        };
+        match (opcode & 0x0F, read_write) {
+            (0x03, Some(instruction)) if indexed => self.addressing_mode_indirect_y_read_write(instruction),
+            (0x03, Some(instruction)) => self.addressing_mode_indirect_x_read_write(instruction),
+            (0x07, Some(instruction)) if indexed => self.addressing_mode_zero_page_with_index_read_write(true, instruction),
+            (0x07, Some(instruction)) => self.addressing_mode_zero_page_read_write(instruction),
+            (0x0B, Some(instruction)) if indexed => self.addressing_mode_absolute_with_index_read_write(false, instruction),
+            (0x0F, Some(instruction)) if indexed => self.addressing_mode_absolute_with_index_read_write(true, instruction),
+            (0x0F, Some(instruction)) => self.addressing_mode_absolute_read_write(instruction),
+            _ => match opcode {
+                0x83 => self.addressing_mode_indirect_x_write(&Self::instruction_sax),
+                0x87 => self.addressing_mode_zero_page_write(&Self::instruction_sax),
+                0x8F => self.addressing_mode_absolute_write(&Self::instruction_sax),
+                0x97 => self.addressing_mode_zero_page_with_index_write(false, &Self::instruction_sax),
+                0x93 => self.addressing_mode_indirect_y_write(&Self::instruction_sha),
+                0x9F => self.addressing_mode_absolute_with_index_write(false, &Self::instruction_sha),
+                0x9B => self.addressing_mode_absolute_with_index_write(false, &Self::instruction_tas),
+                0x9C => self.addressing_mode_absolute_with_index_write(true, &Self::instruction_shy),
+                0x9E => self.addressing_mode_absolute_with_index_write(false, &Self::instruction_shx),
+                0xA3 => self.addressing_mode_indirect_x_read(&Self::instruction_lax),
+                0xA7 => self.addressing_mode_zero_page_read(&Self::instruction_lax),
+                0xAF => self.addressing_mode_absolute_read(&Self::instruction_lax),
+                0xB3 => self.addressing_mode_indirect_y_read(&Self::instruction_lax),
+                0xB7 => self.addressing_mode_zero_page_with_index_read(false, &Self::instruction_lax),
+                0xBF => self.addressing_mode_absolute_with_index_read(false, &Self::instruction_lax),
+                0xBB => self.addressing_mode_absolute_with_index_read(false, &Self::instruction_las),
+                0x0B | 0x2B => self.addressing_mode_immediate(&Self::instruction_anc),
+                0x4B => self.addressing_mode_immediate(&Self::instruction_alr),
+                0x6B => self.addressing_mode_immediate(&Self::instruction_arr),
+                0x8B => self.addressing_mode_immediate(&Self::instruction_xaa),
+                0xAB => self.addressing_mode_immediate(&Self::instruction_lxa),
+                0xCB => self.addressing_mode_immediate(&Self::instruction_axs),
+                0xEB => self.addressing_mode_immediate(&Self::instruction_sbc),
+                0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => self.addressing_mode_immediate(&Self::instruction_nop_read),
+                0x04 | 0x44 | 0x64 => self.addressing_mode_zero_page_read(&Self::instruction_nop_read),
+                0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => self.addressing_mode_zero_page_with_index_read(true, &Self::instruction_nop_read),
+                0x0C => self.addressing_mode_absolute_read(&Self::instruction_nop_read),
+                0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => self.addressing_mode_absolute_with_index_read(true, &Self::instruction_nop_read),
+                0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => self.addressing_mode_implied_or_accumulator(&Self::instruction_nop),
+                _ => {
+                    // KIL, the CPU stops fetching until reset
+                    self.cycle = 1;
+                }
+            },
+        }
     }
 
     fn new_instruction(&mut self, instruction: u8) {
@@ -1030,21 +1281,21 @@ impl Processor {
      */
     // and with accumulator
     fn instruction_and(&mut self, byte: u8) {
-        self.AC &= self.ram.get_instruction(byte as usize);
+        self.AC &= byte;
         self.set_flag_1st_bit_zero(self.AC);
         self.set_flag_7th_bit_nagetive(self.AC);
     }
 
     // or with accumulator
     fn instruction_or(&mut self, byte: u8) {
-        self.AC |= self.ram.get_instruction(byte as usize);
+        self.AC |= byte;
         self.set_flag_1st_bit_zero(self.AC);
         self.set_flag_7th_bit_nagetive(self.AC);
     }
 
     // xor with accumulator
     fn instruction_xor(&mut self, byte: u8) {
-        self.AC ^= self.ram.get_instruction(byte as usize);
+        self.AC ^= byte;
         self.set_flag_1st_bit_zero(self.AC);
         self.set_flag_7th_bit_nagetive(self.AC);
     }
@@ -1053,7 +1304,9 @@ impl Processor {
     fn instruction_adc(&mut self, byte: u8) {
         let sum:i16 = (self.AC as i16) + (byte as i16) + ((self.SR & 0x1) as i16);
         let sum_as_u8 = sum as u8;
-        println!("sum: {:#b} {} {}", sum_as_u8, self.SR & 0x1, sum as i8);
+        if self.trace {
+            println!("sum: {:#b} {} {}", sum_as_u8, self.SR & 0x1, sum as i8);
+        }
         self.set_flag_0th_bit_carry(sum as u16);
         self.set_flag_1st_bit_zero(sum_as_u8);
         self.set_flag_6th_bit_overflow(self.AC as u16, byte as u16, sum as u16);
@@ -1103,9 +1356,9 @@ impl Processor {
     // arithmetic shift left
     fn instruction_asl_accumulator(&mut self) {
         if self.AC & 0x80 > 0 {
-            self.SR |= 0x80;
+            self.SR |= 0x01;
         } else {
-            self.SR &= 0x7F;
+            self.SR &= 0xFE;
         }
         self.AC <<= 1;
         self.set_flag_7th_bit_nagetive(self.AC);
@@ -1114,9 +1367,9 @@ impl Processor {
 
     fn instruction_asl_memory(&mut self, byte: u8) -> u8 {
         if byte & 0x80 > 0 {
-            self.SR |= 0x80;
+            self.SR |= 0x01;
         } else {
-            self.SR &= 0x7F;
+            self.SR &= 0xFE;
         }
         let byte = byte << 1;
         self.set_flag_7th_bit_nagetive(byte);
@@ -1125,11 +1378,11 @@ impl Processor {
     }
 
     fn instruction_rol_accumulator(&mut self) {
-        let carry = (self.SR & 0x80) >> 7;
+        let carry = self.SR & 0x01;
         if self.AC & 0x80 > 0 {
-            self.SR |= 0x80;
+            self.SR |= 0x01;
         } else {
-            self.SR &= 0x7F;
+            self.SR &= 0xFE;
         }
         self.AC <<= 1;
         self.AC |= carry;
@@ -1138,11 +1391,11 @@ impl Processor {
     }
 
     fn instruction_rol_memory(&mut self, byte: u8) -> u8 {
-        let carry = (self.SR & 0x80) >> 7;
+        let carry = self.SR & 0x01;
         if byte & 0x80 > 0 {
-            self.SR |= 0x80;
+            self.SR |= 0x01;
         } else {
-            self.SR &= 0x7F;
+            self.SR &= 0xFE;
         }
         let byte = (byte << 1) | carry;
         self.set_flag_7th_bit_nagetive(byte);
@@ -1151,15 +1404,9 @@ impl Processor {
     }
 
     // subtract with borrow
+    // the carry is the inverted borrow, A - M - (1 - C) = A + !M + C
     fn instruction_sbc(&mut self, byte: u8) {
-        let sum:i16 = (self.AC as i16) - (byte as i16) - ((self.SR & 0x1) as i16);
-        let sum_as_u8 = sum as u8;
-        println!("sum: {:#b} {} {}", sum_as_u8, self.SR & 0x1, sum as i8);
-        self.set_flag_0th_bit_carry(sum as u16);
-        self.set_flag_1st_bit_zero(sum_as_u8);
-        self.set_flag_6th_bit_overflow(self.AC as u16, byte as u16, sum as u16);
-        self.set_flag_7th_bit_nagetive(sum_as_u8);
-        self.AC = sum_as_u8;
+        self.instruction_adc(!byte);
     }
 
     fn instruction_cmp(&mut self, byte: u8) {
@@ -1177,7 +1424,9 @@ impl Processor {
     fn instruction_cpx(&mut self, byte: u8) {
         let diff:i16 = (self.X as i16) - (byte as i16);
         let diff_as_u8 = diff as u8;
-        println!("cpx:: byte {}", byte);
+        if self.trace {
+            println!("cpx:: byte {}", byte);
+        }
         if self.X >= byte {
             self.SR |= 0x01;
         } else {
@@ -1210,13 +1459,13 @@ impl Processor {
     }
 
     fn instruction_dex(&mut self) {
-        self.X -= 1;
+        self.X = self.X.wrapping_sub(1);
         self.set_flag_1st_bit_zero(self.X);
         self.set_flag_7th_bit_nagetive(self.X);
     }
 
     fn instruction_dey(&mut self) {
-        self.Y -= 1;
+        self.Y = self.Y.wrapping_sub(1);
         self.set_flag_1st_bit_zero(self.Y);
         self.set_flag_7th_bit_nagetive(self.Y);
     }
@@ -1306,7 +1555,7 @@ impl Processor {
     fn instruction_ror_accumulator(&mut self) {
         let carry = self.AC & 0x01;
         self.AC >>= 1;
-        self.AC |= carry << 7;
+        self.AC |= (self.SR & 0x01) << 7;
         if carry > 0 {
             self.SR |= 0x01;
         } else {
@@ -1319,7 +1568,7 @@ impl Processor {
     fn instruction_ror_memory(&mut self, byte: u8) -> u8 {
         let carry = byte & 0x01;
         let byte =  byte >> 1;
-        let byte = byte | (carry << 7);
+        let byte = byte | ((self.SR & 0x01) << 7);
         if carry > 0 {
             self.SR |= 0x01;
         } else {
@@ -1331,19 +1580,146 @@ impl Processor {
     }
 
     fn instruction_dec(&mut self, byte: u8) -> u8 {
-        let byte = byte-1;
+        let byte = byte.wrapping_sub(1);
         self.set_flag_7th_bit_nagetive(byte);
         self.set_flag_1st_bit_zero(byte);
         byte
     }
 
     fn instruction_inc(&mut self, byte: u8) -> u8 {
-        let byte = byte+1;
+        let byte = byte.wrapping_add(1);
         self.set_flag_7th_bit_nagetive(byte);
         self.set_flag_1st_bit_zero(byte);
         byte
     }
 
+    /**
+     * unofficial instructions
+     */
+    fn instruction_nop(&mut self) {
+    }
+
+    fn instruction_nop_read(&mut self, _: u8) {
+    }
+
+    // ASL then ORA
+    fn instruction_slo(&mut self, byte: u8) -> u8 {
+        let byte = self.instruction_asl_memory(byte);
+        self.instruction_or(byte);
+        byte
+    }
+
+    // ROL then AND
+    fn instruction_rla(&mut self, byte: u8) -> u8 {
+        let byte = self.instruction_rol_memory(byte);
+        self.instruction_and(byte);
+        byte
+    }
+
+    // LSR then EOR
+    fn instruction_sre(&mut self, byte: u8) -> u8 {
+        let byte = self.instruction_lsr_memory(byte);
+        self.instruction_xor(byte);
+        byte
+    }
+
+    // ROR then ADC
+    fn instruction_rra(&mut self, byte: u8) -> u8 {
+        let byte = self.instruction_ror_memory(byte);
+        self.instruction_adc(byte);
+        byte
+    }
+
+    // DEC then CMP
+    fn instruction_dcp(&mut self, byte: u8) -> u8 {
+        let byte = self.instruction_dec(byte);
+        self.instruction_cmp(byte);
+        byte
+    }
+
+    // INC then SBC
+    fn instruction_isb(&mut self, byte: u8) -> u8 {
+        let byte = self.instruction_inc(byte);
+        self.instruction_sbc(byte);
+        byte
+    }
+
+    fn instruction_sax(&mut self, _: u8) -> u8 {
+        self.AC & self.X
+    }
+
+    // the stores below AND the value with the high byte of the base address + 1
+    fn instruction_sha(&mut self, high: u8) -> u8 {
+        self.AC & self.X & high.wrapping_add(1)
+    }
+
+    fn instruction_shx(&mut self, high: u8) -> u8 {
+        self.X & high.wrapping_add(1)
+    }
+
+    fn instruction_shy(&mut self, high: u8) -> u8 {
+        self.Y & high.wrapping_add(1)
+    }
+
+    fn instruction_tas(&mut self, high: u8) -> u8 {
+        self.SP = self.AC & self.X;
+        self.SP & high.wrapping_add(1)
+    }
+
+    fn instruction_lax(&mut self, byte: u8) {
+        self.instruction_lda(byte);
+        self.X = byte;
+    }
+
+    fn instruction_las(&mut self, byte: u8) {
+        let byte = byte & self.SP;
+        self.instruction_lda(byte);
+        self.X = byte;
+        self.SP = byte;
+    }
+
+    // AND, carry = negative
+    fn instruction_anc(&mut self, byte: u8) {
+        self.instruction_and(byte);
+        self.SR = (self.SR & 0xFE) | (self.AC >> 7);
+    }
+
+    // AND then LSR
+    fn instruction_alr(&mut self, byte: u8) {
+        self.instruction_and(byte);
+        self.instruction_lsr_accumulator();
+    }
+
+    // AND then ROR, carry from bit 6 and overflow from bit 6 xor bit 5
+    fn instruction_arr(&mut self, byte: u8) {
+        self.AC = ((self.AC & byte) >> 1) | ((self.SR & 0x01) << 7);
+        self.set_flag_1st_bit_zero(self.AC);
+        self.set_flag_7th_bit_nagetive(self.AC);
+        self.SR = (self.SR & 0xBE) | ((self.AC >> 6) & 0x01) | ((self.AC ^ (self.AC << 1)) & 0x40);
+    }
+
+    // X = A & X - immediate, without borrow
+    fn instruction_axs(&mut self, byte: u8) {
+        let value = self.AC & self.X;
+        if value >= byte {
+            self.SR |= 0x01;
+        } else {
+            self.SR &= 0xFE;
+        }
+        self.X = value.wrapping_sub(byte);
+        self.set_flag_1st_bit_zero(self.X);
+        self.set_flag_7th_bit_nagetive(self.X);
+    }
+
+    // unstable, the constant ORed into A depends on the chip
+    fn instruction_xaa(&mut self, byte: u8) {
+        self.instruction_lda((self.AC | 0xEE) & self.X & byte);
+    }
+
+    fn instruction_lxa(&mut self, byte: u8) {
+        self.instruction_lax((self.AC | 0xFF) & byte);
+    }
+
     /**
      * set flags
      * function's name starts with `set_flag_`
@@ -1390,7 +1766,7 @@ impl Processor {
      * addressing modes
      * function's name starts with `addressing_mode_`
      */
-    fn addressing_mode_immediate(&mut self, instruction: &Fn(&mut Self, u8)) {
+    fn addressing_mode_immediate(&mut self, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1406,7 +1782,7 @@ impl Processor {
     }
 
     // Read instructions (LDA, LDX, LDY, EOR, AND, ORA, ADC, SBC, CMP, BIT, LAX, NOP)
-    fn addressing_mode_zero_page_read(&mut self, instruction: &Fn(&mut Self, u8)) {
+    fn addressing_mode_zero_page_read(&mut self, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1417,7 +1793,8 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                instruction(self, self.ram.get_instruction(self.arg as usize));
+                let operand = self.ram.get_instruction(self.arg as usize);
+                instruction(self, operand);
                 self.reset_instruction();
             },
             _ => {}
@@ -1425,7 +1802,7 @@ impl Processor {
     }
 
     // Read-Modify-Write instructions (ASL, LSR, ROL, ROR, INC, DEC, SLO, SRE, RLA, RRA, ISB, DCP)
-    fn addressing_mode_zero_page_read_write(&mut self, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_zero_page_read_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1435,23 +1812,12 @@ impl Processor {
                 self.PC += 1;
                 self.cycle += 1;
             },
-            0x2 => {
-                self.arg = self.ram.get_instruction(self.arg as usize) as u16;
-                self.cycle += 1;
-            },
-            0x3 => {
-                self.cycle += 1;
-            },
-            0x4 => {
-                let byte = instruction(self, self.arg as u8);
-                self.ram.set_address(byte, self.arg as usize);
-                self.reset_instruction();
-            },
-            _ => {}
+            _ => self.read_modify_write(2, instruction),
         }
     }
 
-    fn addressing_mode_zero_page_write(&mut self, instruction: &Fn(&mut Self, u8) -> u8) {
+    // Write instructions (STA, STX, STY, SAX), they get the high byte of the base address
+    fn addressing_mode_zero_page_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1462,7 +1828,7 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
+                let byte = instruction(self, 0x00);
                 self.ram.set_address(byte, self.arg as usize);
                 self.reset_instruction();
             },
@@ -1472,7 +1838,7 @@ impl Processor {
 
 
     // Read instructions (LDA, LDX, LDY, EOR, AND, ORA, ADC, SBC, CMP, BIT, LAX, NOP)
-    fn addressing_mode_zero_page_with_index_read(&mut self, is_x: bool, instruction: &Fn(&mut Self, u8)) {
+    fn addressing_mode_zero_page_with_index_read(&mut self, is_x: bool, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1483,7 +1849,7 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                self.arg += (if is_x {self.X} else {self.Y}) as u16;
+                self.zero_page_index(is_x);
                 self.cycle += 1;
             },
             0x3 => {
@@ -1495,7 +1861,7 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_zero_page_with_index_read_write(&mut self, is_x: bool, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_zero_page_with_index_read_write(&mut self, is_x: bool, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1506,25 +1872,14 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                self.arg += (if is_x {self.X} else {self.Y}) as u16;
-                self.cycle += 1;
-            },
-            0x3 => {
+                self.zero_page_index(is_x);
                 self.cycle += 1;
             },
-            0x4 => {
-                self.cycle += 1;
-            },
-            0x5 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
-                self.ram.set_address(byte, self.arg as usize);
-                self.reset_instruction();
-            },
-            _ => {}
+            _ => self.read_modify_write(3, instruction),
         }
     }
 
-    fn addressing_mode_zero_page_with_index_write(&mut self, is_x: bool, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_zero_page_with_index_write(&mut self, is_x: bool, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1535,11 +1890,11 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                self.arg += (if is_x {self.X} else {self.Y}) as u16;
+                self.zero_page_index(is_x);
                 self.cycle += 1;
             },
             0x3 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
+                let byte = instruction(self, 0x00);
                 self.ram.set_address(byte, self.arg as usize);
                 self.reset_instruction();
             },
@@ -1547,7 +1902,7 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_absolute_read(&mut self, instruction: &Fn(&mut Self, u8)) {
+    fn addressing_mode_absolute_read(&mut self, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1571,7 +1926,7 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_absolute_read_write(&mut self, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_absolute_read_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1586,22 +1941,11 @@ impl Processor {
                 self.PC += 1;
                 self.cycle += 1;
             },
-            0x3 => {
-                self.cycle += 1;
-            },
-            0x4 => {
-                self.cycle += 1;
-            },
-            0x5 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
-                self.ram.set_address(byte, self.arg as usize);
-                self.reset_instruction();
-            }
-            _ => {}
+            _ => self.read_modify_write(3, instruction),
         }
     }
 
-    fn addressing_mode_absolute_write(&mut self, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_absolute_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1617,7 +1961,7 @@ impl Processor {
                 self.cycle += 1;
             },
             0x3 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
+                let byte = instruction(self, (self.arg >> 8) as u8);
                 self.ram.set_address(byte, self.arg as usize);
                 self.reset_instruction();
             },
@@ -1625,7 +1969,8 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_absolute_with_index_read(&mut self, is_x:bool, instruction: &Fn(&mut Self, u8)) {
+    // one cycle less when the index doesn't cross a page
+    fn addressing_mode_absolute_with_index_read(&mut self, is_x:bool, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1638,11 +1983,18 @@ impl Processor {
             0x2 => {
                 self.arg |= (self.ram.get_instruction(self.PC as usize) as u16)<<8;
                 self.PC += 1;
+                self.absolute_index(is_x);
                 self.cycle += 1;
             },
             0x3 => {
-                self.arg += (if is_x {self.X} else {self.Y}) as u16;
-                self.cycle += 1;
+                // read before the high byte is fixed, the real one when the page was crossed
+                let operand = self.ram.get_instruction(self.uncorrected_address() as usize);
+                if self.page_crossed {
+                    self.cycle += 1;
+                } else {
+                    instruction(self, operand);
+                    self.reset_instruction();
+                }
             },
             0x4 => {
                 let operand = self.ram.get_instruction(self.arg as usize);
@@ -1653,7 +2005,7 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_absolute_with_index_read_write(&mut self, is_x:bool, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_absolute_with_index_read_write(&mut self, is_x:bool, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1666,29 +2018,19 @@ impl Processor {
             0x2 => {
                 self.arg |= (self.ram.get_instruction(self.PC as usize) as u16)<<8;
                 self.PC += 1;
+                self.absolute_index(is_x);
                 self.cycle += 1;
             },
             0x3 => {
-                self.arg += (if is_x {self.X} else {self.Y}) as u16;
-                self.cycle += 1;
-            },
-            0x4 => {
+                self.ram.get_instruction(self.uncorrected_address() as usize);
                 self.cycle += 1;
             },
-            0x5 => {
-                self.cycle += 1;
-            },
-            0x6 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
-                self.ram.set_address(byte, self.arg as usize);
-                self.reset_instruction();
-            },
-            _ => {}
+            _ => self.read_modify_write(4, instruction),
         }
     }
 
 
-    fn addressing_mode_absolute_with_index_write(&mut self, is_x:bool, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_absolute_with_index_write(&mut self, is_x:bool, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1701,22 +2043,22 @@ impl Processor {
             0x2 => {
                 self.arg |= (self.ram.get_instruction(self.PC as usize) as u16)<<8;
                 self.PC += 1;
+                self.absolute_index(is_x);
                 self.cycle += 1;
             },
             0x3 => {
-                self.arg += (if is_x {self.X} else {self.Y}) as u16;
+                self.ram.get_instruction(self.uncorrected_address() as usize);
                 self.cycle += 1;
             },
             0x4 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
-                self.ram.set_address(byte, self.arg as usize);
+                self.indexed_write(instruction);
                 self.reset_instruction();
             },
             _ => {}
         }
     }
 
-    fn addressing_mode_indirect_x_read(&mut self, instruction: &Fn(&mut Self, u8)) {
+    fn addressing_mode_indirect_x_read(&mut self, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1727,14 +2069,15 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                self.arg += self.X as u16;
+                self.zero_page_index(true);
                 self.cycle += 1;
             },
             0x3 => {
+                self.operand = self.ram.get_instruction(self.arg as usize);
                 self.cycle += 1;
             },
             0x4 => {
-                self.arg = ((self.ram.get_instruction(self.arg as usize) as u16) | (self.ram.get_instruction((self.arg+1) as usize) as u16)<<8) as u16;
+                self.arg = self.read_pointer_high();
                 self.cycle += 1;
             },
             0x5 => {
@@ -1746,8 +2089,33 @@ impl Processor {
         }
     }
 
+    fn addressing_mode_indirect_x_read_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
+        match self.cycle {
+            0x0 => {
+                self.cycle = 1;
+            },
+            0x1 => {
+                self.arg = self.ram.get_instruction(self.PC as usize) as u16;
+                self.PC += 1;
+                self.cycle += 1;
+            },
+            0x2 => {
+                self.zero_page_index(true);
+                self.cycle += 1;
+            },
+            0x3 => {
+                self.operand = self.ram.get_instruction(self.arg as usize);
+                self.cycle += 1;
+            },
+            0x4 => {
+                self.arg = self.read_pointer_high();
+                self.cycle += 1;
+            },
+            _ => self.read_modify_write(5, instruction),
+        }
+    }
 
-    fn addressing_mode_indirect_x_write(&mut self, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_indirect_x_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1758,18 +2126,19 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
-                self.arg += self.X as u16;
+                self.zero_page_index(true);
                 self.cycle += 1;
             },
             0x3 => {
+                self.operand = self.ram.get_instruction(self.arg as usize);
                 self.cycle += 1;
             },
             0x4 => {
-                self.arg = ((self.ram.get_instruction(self.arg as usize) as u16) | (self.ram.get_instruction((self.arg+1) as usize) as u16)<<8) as u16;
+                self.arg = self.read_pointer_high();
                 self.cycle += 1;
             },
             0x5 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
+                let byte = instruction(self, (self.arg >> 8) as u8);
                 self.ram.set_address(byte, self.arg as usize);
                 self.reset_instruction();
             },
@@ -1777,7 +2146,8 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_indirect_y_read(&mut self, instruction: &Fn(&mut Self, u8)) {
+    // one cycle less when the index doesn't cross a page
+    fn addressing_mode_indirect_y_read(&mut self, instruction: &dyn Fn(&mut Self, u8)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1788,15 +2158,22 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
+                self.operand = self.ram.get_instruction(self.arg as usize);
                 self.cycle += 1;
             },
             0x3 => {
-                self.arg = ((self.ram.get_instruction(self.arg as usize) as u16) | (self.ram.get_instruction((self.arg+1) as usize) as u16)<<8) as u16;
+                self.arg = self.read_pointer_high();
+                self.absolute_index(false);
                 self.cycle += 1;
             },
             0x4 => {
-                self.arg += self.Y as u16;
-                self.cycle += 1;
+                let operand = self.ram.get_instruction(self.uncorrected_address() as usize);
+                if self.page_crossed {
+                    self.cycle += 1;
+                } else {
+                    instruction(self, operand);
+                    self.reset_instruction();
+                }
             },
             0x5 => {
                 let operand = self.ram.get_instruction(self.arg as usize);
@@ -1807,7 +2184,7 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_indirect_y_write(&mut self, instruction: &Fn(&mut Self, u8) -> u8) {
+    fn addressing_mode_indirect_y_read_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1818,26 +2195,107 @@ impl Processor {
                 self.cycle += 1;
             },
             0x2 => {
+                self.operand = self.ram.get_instruction(self.arg as usize);
                 self.cycle += 1;
             },
             0x3 => {
-                self.arg = ((self.ram.get_instruction(self.arg as usize) as u16) | (self.ram.get_instruction((self.arg+1) as usize) as u16)<<8) as u16;
+                self.arg = self.read_pointer_high();
+                self.absolute_index(false);
                 self.cycle += 1;
             },
             0x4 => {
-                self.arg += self.Y as u16;
+                self.ram.get_instruction(self.uncorrected_address() as usize);
+                self.cycle += 1;
+            },
+            _ => self.read_modify_write(5, instruction),
+        }
+    }
+
+    fn addressing_mode_indirect_y_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
+        match self.cycle {
+            0x0 => {
+                self.cycle = 1;
+            },
+            0x1 => {
+                self.arg = self.ram.get_instruction(self.PC as usize) as u16;
+                self.PC += 1;
+                self.cycle += 1;
+            },
+            0x2 => {
+                self.operand = self.ram.get_instruction(self.arg as usize);
+                self.cycle += 1;
+            },
+            0x3 => {
+                self.arg = self.read_pointer_high();
+                self.absolute_index(false);
+                self.cycle += 1;
+            },
+            0x4 => {
+                self.ram.get_instruction(self.uncorrected_address() as usize);
                 self.cycle += 1;
             },
             0x5 => {
-                let byte = instruction(self, self.ram.get_instruction(self.arg as usize));
-                self.ram.set_address(byte, self.arg as usize);
+                self.indexed_write(instruction);
                 self.reset_instruction();
             },
             _ => {}
         }
     }
 
-    fn addressing_mode_implied_or_accumulator(&mut self, instruction: &Fn(&mut Self)) {
+    // zero page index, staying in the zero page
+    fn zero_page_index(&mut self, is_x: bool) {
+        self.ram.get_instruction(self.arg as usize);
+        self.arg = (self.arg + (if is_x {self.X} else {self.Y}) as u16) & 0xFF;
+    }
+
+    // high byte of a zero page pointer whose low byte was read into `operand`, the pointer
+    // wraps inside the zero page
+    fn read_pointer_high(&mut self) -> u16 {
+        let high = self.ram.get_instruction(((self.arg + 1) & 0xFF) as usize) as u16;
+        (high << 8) | self.operand as u16
+    }
+
+    fn absolute_index(&mut self, is_x: bool) {
+        let address = self.arg.wrapping_add((if is_x {self.X} else {self.Y}) as u16);
+        self.page_crossed = address & 0xFF00 != self.arg & 0xFF00;
+        self.arg = address;
+    }
+
+    // indexed address before the carry into the high byte
+    fn uncorrected_address(&self) -> u16 {
+        if self.page_crossed { self.arg.wrapping_sub(0x100) } else { self.arg }
+    }
+
+    // the write of indexed stores, the unofficial SHA/SHX/SHY/TAS mix the high byte of the
+    // base address into the value and put the value on the high address lines when the page
+    // is crossed
+    fn indexed_write(&mut self, instruction: &dyn Fn(&mut Self, u8) -> u8) {
+        let base_high = (self.uncorrected_address() >> 8) as u8;
+        let byte = instruction(self, base_high);
+        let mut address = self.arg;
+        if self.page_crossed && matches!(self.current_instruction, 0x93 | 0x9B | 0x9C | 0x9E | 0x9F) {
+            address = ((byte as u16) << 8) | (address & 0xFF);
+        }
+        self.ram.set_address(byte, address as usize);
+    }
+
+    // the last three cycles of read-modify-write instructions from `first`: read, write back
+    // the unmodified value while modifying it, write the result
+    fn read_modify_write(&mut self, first: usize, instruction: &dyn Fn(&mut Self, u8) -> u8) {
+        if self.cycle == first {
+            self.operand = self.ram.get_instruction(self.arg as usize);
+            self.cycle += 1;
+        } else if self.cycle == first + 1 {
+            self.ram.set_address(self.operand, self.arg as usize);
+            self.operand = instruction(self, self.operand);
+            self.cycle += 1;
+        } else {
+            self.ram.set_address(self.operand, self.arg as usize);
+            self.reset_instruction();
+        }
+    }
+
+    fn addressing_mode_implied_or_accumulator(&mut self, instruction: &dyn Fn(&mut Self)) {
         match self.cycle {
             0x0 => {
                 self.cycle = 1;
@@ -1850,7 +2308,7 @@ impl Processor {
         }
     }
 
-    fn addressing_mode_relative(&mut self, instruction: &Fn(&mut Self) -> bool) {
+    fn addressing_mode_relative(&mut self, instruction: &dyn Fn(&mut Self) -> bool) {
         // BME change branch if result was negative
         match self.cycle {
             0x0 => {
@@ -1865,22 +2323,23 @@ impl Processor {
                 }
             },
             0x2 => {
-                println!("PC1: argI16: {}", self.arg as i8);
-                let high = (self.PC >> 8) as u8;
-                let low = (self.PC & 0xFF) as u8;
-                println!("low : {}", low as i8);
-                println!("high: {}", (high as u16));
-                println!("signed {}", self.arg as i8 + low as i8);
-                //println!("high+low {}",((high as u16) <<8) |(low+(self.arg as u8)) as u16);
-                self.PC = (high as u16)<<8 | (low as i8 + self.arg as i8) as u16;
-
-                println!("PC2: argI16: {}", self.arg as i16);
-
-                // TODO:: fix PC high byte
-                self.cycle += 1;
-                self.reset_instruction();
+                // the offset is added to the low byte first, the high byte is fixed one cycle
+                // later when the branch crosses a page
+                let target = self.PC.wrapping_add(self.arg as u8 as i8 as u16);
+                if self.trace {
+                    println!("branch offset: {} target: {:#06X}", self.arg as u8 as i8, target);
+                }
+                self.PC = (self.PC & 0xFF00) | (target & 0x00FF);
+                if self.PC == target {
+                    self.branch_skips_poll = true;
+                    self.reset_instruction();
+                } else {
+                    self.arg = target;
+                    self.cycle += 1;
+                }
             },
             0x3 => {
+                self.PC = self.arg;
                 self.reset_instruction();
             }
             _ => {}
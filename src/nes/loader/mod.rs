@@ -1,9 +1,12 @@
 extern crate walkdir;
 
+#[cfg(feature="debug")]
 use walkdir::WalkDir;
 use std::{fs, io};
+use std::path::{Path, PathBuf};
 
 // Only for `.nes` file
+#[cfg(feature="debug")]
 pub fn list_test_roms(path: &str) -> Vec<walkdir::DirEntry>{
     let mut roms = WalkDir::new(path)
         .follow_links(true)
@@ -18,4 +21,22 @@ pub fn list_test_roms(path: &str) -> Vec<walkdir::DirEntry>{
 
 pub fn load_rom(path: &str) -> Result<Vec<u8>, io::Error> {
     fs::read(String::from(path).clone())
-}
\ No newline at end of file
+}
+
+// Resolve `relative` (with `/` separators) under `base`. Names that only differ in case are
+// accepted as well, test_roms.xml was written on a case insensitive file system.
+pub fn find_file(base: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = base.to_path_buf();
+    for component in relative.split('/').filter(|x| !x.is_empty()) {
+        let exact = path.join(component);
+        path = if exact.exists() {
+            exact
+        } else {
+            fs::read_dir(&path).ok()?
+                .filter_map(|e| e.ok())
+                .find(|e| e.file_name().to_string_lossy().eq_ignore_ascii_case(component))?
+                .path()
+        };
+    }
+    Some(path)
+}
@@ -0,0 +1,279 @@
+// Discrete logic boards, a single latch written through $8000-$FFFF
+//
+// Mapper  Board         Latch      PRG                          CHR
+// 2       UxROM         ---- PPPP  16K at $8000, last at $C000  8K RAM
+// 3       CNROM         ---- --CC  32K fixed (or 16K mirrored)  8K bank
+// 7       AxROM         ---S -PPP  32K                          8K RAM, S: single screen
+// 11      Color Dreams  CCCC --PP  32K                          8K bank
+// 34      BNROM         ---- --PP  32K                          8K RAM
+// 66      GxROM         --PP --CC  32K                          8K bank
+//
+// NINA-001 also uses mapper 34 (told apart by its CHR ROM): the latches are at $7FFD (32K
+// PRG bank), $7FFE and $7FFF (4K CHR banks at $0000 and $1000), under 8K of PRG RAM.
+//
+// Boards whose ROM keeps driving the data bus during the write see both values at once, the
+// latch gets the AND of the written byte and the ROM byte at that address (bus conflict).
+// NES 2.0 submapper 1 means no bus conflicts, 2 means bus conflicts; otherwise the usual
+// board of the mapper number decides (AxROM and NINA-001 have none).
+//
+// All latches are 0 at power on.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::ppu::Mirroring;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Board {
+    UxRom,
+    CnRom,
+    AxRom,
+    ColorDreams,
+    BnRom,
+    Nina001,
+    GxRom,
+}
+
+impl Board {
+    pub fn from_mapper(mapper: u16, has_chr_rom: bool) -> Option<Board> {
+        match mapper {
+            2 => Some(Board::UxRom),
+            3 => Some(Board::CnRom),
+            7 => Some(Board::AxRom),
+            11 => Some(Board::ColorDreams),
+            34 if has_chr_rom => Some(Board::Nina001),
+            34 => Some(Board::BnRom),
+            66 => Some(Board::GxRom),
+            _ => None,
+        }
+    }
+
+    fn has_bus_conflicts(&self) -> bool {
+        !matches!(self, Board::AxRom | Board::Nina001)
+    }
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    // 4K banks at $0000 and $1000, both halves of one 8K bank on most boards
+    chr_banks: [usize; 2],
+}
+
+impl Discrete {
+    pub fn new(board: Board, cartridge: Cartridge) -> Discrete {
+        let bus_conflicts = match cartridge.submapper {
+            1 => false,
+            2 => true,
+            _ => board.has_bus_conflicts(),
+        };
+        let mirroring = if board == Board::AxRom { Mirroring::SingleScreenLower } else { cartridge.mirroring };
+        Discrete {
+            board,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: if board == Board::Nina001 { cartridge.prg_ram } else { vec![] },
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(mirroring),
+            bus_conflicts,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn write_latch(&mut self, data: u8) {
+        let chr_8k = |bank: u8| [bank as usize * 2, bank as usize * 2 + 1];
+        match self.board {
+            Board::UxRom => self.prg_bank = data as usize,
+            Board::CnRom => self.chr_banks = chr_8k(data & 0x03),
+            Board::AxRom => {
+                self.prg_bank = (data & 0x07) as usize;
+                self.nametables.set_mirroring(if data & 0x10 > 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                });
+            },
+            Board::ColorDreams => {
+                self.prg_bank = (data & 0x03) as usize;
+                self.chr_banks = chr_8k(data >> 4);
+            },
+            Board::BnRom => self.prg_bank = (data & 0x03) as usize,
+            Board::Nina001 => {},
+            Board::GxRom => {
+                self.prg_bank = ((data >> 4) & 0x03) as usize;
+                self.chr_banks = chr_8k(data & 0x03);
+            },
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let addr = addr as usize - 0x8000;
+        let idx = if self.board == Board::UxRom {
+            let last = (self.prg_rom.len() / 0x4000).max(1) - 1;
+            let bank = if addr < 0x4000 { self.prg_bank } else { last };
+            bank * 0x4000 + (addr & 0x3FFF)
+        } else {
+            self.prg_bank * 0x8000 + addr
+        };
+        idx % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 12) as usize & 0x01];
+        (bank * 0x1000 + (addr as usize & 0x0FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Discrete {
+    fn get_name(&self) -> &'static str {
+        match self.board {
+            Board::UxRom => "UxROM",
+            Board::CnRom => "CNROM",
+            Board::AxRom => "AxROM",
+            Board::ColorDreams => "Color Dreams",
+            Board::BnRom => "BNROM",
+            Board::Nina001 => "NINA-001",
+            Board::GxRom => "GxROM",
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+            },
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = data;
+                if self.board == Board::Nina001 {
+                    match addr {
+                        0x7FFD => self.prg_bank = (data & 0x01) as usize,
+                        0x7FFE => self.chr_banks[0] = (data & 0x0F) as usize,
+                        0x7FFF => self.chr_banks[1] = (data & 0x0F) as usize,
+                        _ => {}
+                    }
+                }
+            },
+            0x8000..=0xFFFF if self.board != Board::Nina001 => {
+                let data = if self.bus_conflicts {
+                    data & self.cpu_peek(addr).unwrap_or(0xFF)
+                } else {
+                    data
+                };
+                self.write_latch(data);
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_index(addr)],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    let idx = self.chr_index(addr);
+                    self.chr[idx] = data;
+                }
+            },
+            _ => self.nametables.write(addr, data, ciram),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every byte of a 16K PRG bank holds the bank number, every byte of a 4K CHR bank as well
+    fn discrete(mapper: u16, submapper: u8, prg_banks: usize, chr_banks: usize) -> Discrete {
+        let cartridge = Cartridge {
+            mapper,
+            submapper,
+            prg_rom: (0..prg_banks * 0x4000).map(|x| (x / 0x4000) as u8).collect(),
+            chr: (0..chr_banks.max(2) * 0x1000).map(|x| (x / 0x1000) as u8).collect(),
+            chr_ram: chr_banks == 0,
+            prg_ram: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        };
+        Discrete::new(Board::from_mapper(mapper, chr_banks > 0).unwrap(), cartridge)
+    }
+
+    #[test]
+    fn uxrom_switches_8000_and_fixes_the_last_bank() {
+        let mut uxrom = discrete(2, 1, 8, 0);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(0));
+        assert_eq!(uxrom.cpu_peek(0xC000), Some(7));
+        uxrom.cpu_write(0x8000, 0x05);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(5));
+        assert_eq!(uxrom.cpu_peek(0xFFFF), Some(7));
+    }
+
+    #[test]
+    fn bus_conflicts_and_the_written_value_with_rom() {
+        // the fixed bank reads 3 at $C000, so writing 6 there selects bank 2
+        let mut uxrom = discrete(2, 0, 4, 0);
+        uxrom.cpu_write(0xC000, 0x06);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(2));
+        let mut no_conflicts = discrete(2, 1, 8, 0);
+        no_conflicts.cpu_write(0xC000, 0x06);
+        assert_eq!(no_conflicts.cpu_peek(0x8000), Some(6));
+    }
+
+    #[test]
+    fn cnrom_and_gxrom_switch_8k_chr() {
+        let ciram = [0; CIRAM_SIZE];
+        let mut cnrom = discrete(3, 1, 2, 8);
+        cnrom.cpu_write(0x8000, 0x02);
+        assert_eq!(cnrom.ppu_peek(0x0000, &ciram), 4);
+        assert_eq!(cnrom.ppu_peek(0x1000, &ciram), 5);
+        let mut gxrom = discrete(66, 1, 8, 8);
+        gxrom.cpu_write(0x8000, 0x13);
+        assert_eq!(gxrom.cpu_peek(0x8000), Some(2));
+        assert_eq!(gxrom.ppu_peek(0x1FFF, &ciram), 7);
+    }
+
+    #[test]
+    fn axrom_selects_one_screen() {
+        let mut ciram = [0; CIRAM_SIZE];
+        let mut axrom = discrete(7, 0, 8, 0);
+        axrom.ppu_write(0x2000, 0x11, &mut ciram);
+        assert_eq!(axrom.ppu_peek(0x2C00, &ciram), 0x11);
+        axrom.cpu_write(0x8000, 0x13);
+        assert_eq!(axrom.cpu_peek(0x8000), Some(6));
+        assert_eq!(axrom.ppu_peek(0x2400, &ciram), 0x00);
+        axrom.ppu_write(0x2400, 0x22, &mut ciram);
+        assert_eq!(axrom.ppu_peek(0x2000, &ciram), 0x22);
+    }
+
+    #[test]
+    fn nina_001_latches_sit_under_prg_ram() {
+        let ciram = [0; CIRAM_SIZE];
+        let mut nina = discrete(34, 0, 4, 16);
+        nina.cpu_write(0x7FFD, 0x01);
+        nina.cpu_write(0x7FFE, 0x05);
+        nina.cpu_write(0x7FFF, 0x09);
+        assert_eq!(nina.cpu_peek(0x8000), Some(2));
+        assert_eq!(nina.cpu_peek(0x7FFE), Some(0x05));
+        assert_eq!(nina.ppu_peek(0x0000, &ciram), 5);
+        assert_eq!(nina.ppu_peek(0x1000, &ciram), 9);
+    }
+}
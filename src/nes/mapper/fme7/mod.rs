@@ -0,0 +1,246 @@
+// Sunsoft FME-7 (mapper 69), and the Sunsoft 5B which adds audio to it
+//
+// $8000-$9FFF ---- CCCC  command
+// $A000-$BFFF DDDD DDDD  parameter of the command:
+//
+// $0-$7  1K CHR banks 0-7
+// $8     ERPP PPPP  8K bank at $6000, R: RAM (else ROM), E: RAM enable (else open bus)
+// $9-$B  --PP PPPP  8K PRG banks at $8000, $A000, $C000
+// $C     ---- --MM  mirroring: vertical, horizontal, one screen lower, upper
+// $D     C--- ---I  IRQ control, C: counter enable, I: IRQ enable, acknowledges the IRQ
+// $E/$F  IRQ counter low and high byte
+//
+// $E000-$FFFF is the last 8K bank. $C000-$FFFF writes also reach the 5B audio.
+//
+// The 16 bit IRQ counter counts CPU cycles down while enabled, wrapping from $0000 to $FFFF
+// raises the IRQ (when enabled).
+//
+// Mapper 69 doesn't tell FME-7 from 5B, every board gets the audio which stays silent unless
+// written.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::cpu::apu::expansion::sunsoft5b::Sunsoft5bAudio;
+use crate::nes::cpu::apu::expansion::ExpansionAudio;
+use crate::nes::ppu::Mirroring;
+
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+    command: u8,
+    chr_banks: [u8; 8],
+    // $6000, $8000, $A000, $C000
+    prg_banks: [u8; 4],
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Option<Box<dyn ExpansionAudio>>,
+}
+
+impl Fme7 {
+    pub fn new(cartridge: Cartridge) -> Fme7 {
+        Fme7 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(cartridge.mirroring),
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Some(Box::new(Sunsoft5bAudio::new())),
+        }
+    }
+
+    fn prg_rom_index(&self, bank: usize, addr: u16) -> usize {
+        (bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        (bank * 0x400 + (addr as usize & 0x3FF)) % self.chr.len()
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => self.prg_banks[0] = data,
+            0x9..=0xB => self.prg_banks[self.command as usize - 0x8] = data & 0x3F,
+            0xC => self.nametables.set_mirroring(match data & 0x03 {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenLower,
+                _ => Mirroring::SingleScreenUpper,
+            }),
+            0xD => {
+                self.irq_control = data;
+                self.irq_pending = false;
+            },
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn get_name(&self) -> &'static str {
+        "FME-7"
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => {
+                let register = self.prg_banks[0];
+                let bank = (register & 0x3F) as usize;
+                if register & 0x40 == 0 {
+                    Some(self.prg_rom[self.prg_rom_index(bank, addr)])
+                } else if register & 0x80 > 0 && !self.prg_ram.is_empty() {
+                    Some(self.prg_ram[(bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_ram.len()])
+                } else {
+                    None
+                }
+            },
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x6000) >> 13) as usize] as usize;
+                Some(self.prg_rom[self.prg_rom_index(bank, addr)])
+            },
+            0xE000..=0xFFFF => {
+                let last = (self.prg_rom.len() / 0x2000).max(1) - 1;
+                Some(self.prg_rom[self.prg_rom_index(last, addr)])
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.write_register(addr, data);
+        }
+        match addr {
+            0x6000..=0x7FFF => {
+                let register = self.prg_banks[0];
+                if register & 0xC0 == 0xC0 && !self.prg_ram.is_empty() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[((register & 0x3F) as usize * 0x2000 + (addr as usize & 0x1FFF)) % len] = data;
+                }
+            },
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_index(addr)],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    let idx = self.chr_index(addr);
+                    self.chr[idx] = data;
+                }
+            },
+            _ => self.nametables.write(addr, data, ciram),
+        }
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_control & 0x80 == 0 {
+            return;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && self.irq_control & 0x01 > 0 {
+            self.irq_pending = true;
+        }
+    }
+
+    fn take_expansion_audio(&mut self) -> Option<Box<dyn ExpansionAudio>> {
+        self.audio.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every byte of an 8K PRG bank and a 1K CHR bank holds the bank number
+    fn fme7() -> Fme7 {
+        Fme7::new(Cartridge {
+            mapper: 69,
+            submapper: 0,
+            prg_rom: (0..0x40000).map(|x| (x / 0x2000) as u8).collect(),
+            chr: (0..0x40000).map(|x| (x / 0x400) as u8).collect(),
+            chr_ram: false,
+            prg_ram: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        })
+    }
+
+    fn command(fme7: &mut Fme7, command: u8, parameter: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    fn commands_switch_prg_and_chr() {
+        let ciram = [0; CIRAM_SIZE];
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x9, 0x03);
+        command(&mut fme7, 0xA, 0x04);
+        command(&mut fme7, 0xB, 0x45);
+        command(&mut fme7, 0x7, 0x21);
+        assert_eq!(fme7.cpu_peek(0x8000), Some(3));
+        assert_eq!(fme7.cpu_peek(0xA000), Some(4));
+        assert_eq!(fme7.cpu_peek(0xC000), Some(5));
+        assert_eq!(fme7.cpu_peek(0xE000), Some(31));
+        assert_eq!(fme7.ppu_peek(0x1C00, &ciram), 0x21);
+    }
+
+    #[test]
+    fn bank_6000_is_rom_ram_or_open_bus() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x8, 0x06);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(6));
+        command(&mut fme7, 0x8, 0x40);
+        assert_eq!(fme7.cpu_peek(0x6000), None);
+        fme7.cpu_write(0x6000, 0x77);
+        command(&mut fme7, 0x8, 0xC0);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(0x00));
+        fme7.cpu_write(0x6000, 0x77);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(0x77));
+    }
+
+    #[test]
+    fn irq_fires_when_the_counter_wraps() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0xE, 0x01);
+        command(&mut fme7, 0xF, 0x00);
+        command(&mut fme7, 0xD, 0x81);
+        fme7.clock_cpu();
+        assert!(!fme7.irq_line());
+        fme7.clock_cpu();
+        assert!(fme7.irq_line());
+        command(&mut fme7, 0xD, 0x80);
+        assert!(!fme7.irq_line());
+        // counting without the IRQ enabled
+        for _ in 0..0x10000 {
+            fme7.clock_cpu();
+        }
+        assert!(!fme7.irq_line());
+    }
+}
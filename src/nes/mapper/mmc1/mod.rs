@@ -0,0 +1,308 @@
+// MMC1 (mapper 1), SxROM boards
+//
+// Registers are loaded through a 5 bit shift register, one bit per write to $8000-$FFFF:
+//
+// 7654 3210
+// R--- ---D  R: reset the shift register and set PRG mode 3, D: next bit (LSB first)
+//
+// The 5th write copies the value to the register picked by bits 13-14 of its address:
+//
+// $8000-$9FFF control   ---C PPMM  C: 4K CHR banks, P: PRG mode, M: mirroring
+//                                  (one screen lower, one screen upper, vertical, horizontal)
+// $A000-$BFFF CHR bank 0 (4K at PPU $0000, or 8K with the low bit ignored)
+// $C000-$DFFF CHR bank 1 (4K at PPU $1000, unused in 8K mode)
+// $E000-$FFFF PRG bank  ---R PPPP  R: PRG RAM disabled
+//
+// PRG modes: 0/1 32K at $8000 (low bit ignored), 2 first bank fixed at $8000 and 16K switched
+// at $C000, 3 16K switched at $8000 and the last bank fixed at $C000 (power on).
+//
+// A write on the cycle right after another one is ignored, so only the first write of a
+// read-modify-write instruction reaches the shift register.
+//
+// Larger boards take the upper CHR bank bits for other uses, from the CHR bank register that
+// is active (bank 1 while PPU A12 is high in 4K mode, bank 0 otherwise):
+//
+// SNROM  ---E ----  E: PRG RAM disabled (8K CHR RAM)
+// SOROM  ---- R---  R: 8K PRG RAM bank of 16K
+// SUROM  ---P ----  P: 256K PRG bank of 512K
+// SXROM  ---P RR--  P: 256K PRG bank, R: 8K PRG RAM bank of 32K
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::ppu::Mirroring;
+
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8,
+    // PPU A12 of the last pattern table access
+    chr_a12: bool,
+    cycle: usize,
+    last_write: Option<usize>,
+}
+
+impl Mmc1 {
+    pub fn new(cartridge: Cartridge) -> Mmc1 {
+        Mmc1 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(cartridge.mirroring),
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_banks: [0; 2],
+            prg_bank: 0,
+            chr_a12: false,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr & 0xE000 {
+            0x8000 => {
+                self.control = data;
+                self.nametables.set_mirroring(match data & 0x03 {
+                    0 => Mirroring::SingleScreenLower,
+                    1 => Mirroring::SingleScreenUpper,
+                    2 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                });
+            },
+            0xA000 => self.chr_banks[0] = data,
+            0xC000 => self.chr_banks[1] = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    fn chr_4k_mode(&self) -> bool {
+        self.control & 0x10 > 0
+    }
+
+    // CHR bank register driving the board lines right now
+    fn active_chr_bank(&self) -> u8 {
+        if self.chr_4k_mode() && self.chr_a12 {
+            self.chr_banks[1]
+        } else {
+            self.chr_banks[0]
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        if self.prg_ram.is_empty() || self.prg_bank & 0x10 > 0 {
+            return false;
+        }
+        !(self.is_snrom() && self.active_chr_bank() & 0x10 > 0)
+    }
+
+    // 8K CHR RAM, 8K PRG RAM and up to 256K PRG ROM, SUROM has 512K and uses the bit for the
+    // PRG bank, SOROM/SXROM have more PRG RAM and use it for the RAM bank
+    fn is_snrom(&self) -> bool {
+        self.chr_ram && self.chr.len() == 0x2000 && self.prg_ram.len() == 0x2000 && self.prg_rom.len() <= 0x40000
+    }
+
+    fn prg_ram_index(&self, addr: u16) -> usize {
+        let bank = match self.prg_ram.len() {
+            0x4000 => (self.active_chr_bank() >> 3) & 0x01,
+            0x8000 => (self.active_chr_bank() >> 2) & 0x03,
+            _ => 0,
+        } as usize;
+        (bank * 0x2000 + (addr as usize - 0x6000)) % self.prg_ram.len()
+    }
+
+    fn prg_rom_index(&self, addr: u16) -> usize {
+        // SUROM/SXROM: 256K outer bank, the fixed banks stay inside it
+        let outer = if self.prg_rom.len() > 0x40000 {
+            (self.active_chr_bank() as usize & 0x10) << 14
+        } else {
+            0
+        };
+        let inner_banks = (self.prg_rom.len().min(0x40000) / 0x4000).max(1);
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let bank_16k = match (self.control >> 2) & 0x03 {
+            0 | 1 => (bank & 0x0E) | ((addr as usize >> 14) & 0x01),
+            2 => if addr < 0xC000 { 0 } else { bank },
+            _ => if addr < 0xC000 { bank } else { inner_banks - 1 },
+        };
+        (outer + (bank_16k % inner_banks) * 0x4000 + (addr as usize & 0x3FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank_4k = if self.chr_4k_mode() {
+            self.chr_banks[(addr >> 12) as usize & 0x01] as usize
+        } else {
+            (self.chr_banks[0] as usize & 0x1E) | ((addr as usize >> 12) & 0x01)
+        };
+        (bank_4k * 0x1000 + (addr as usize & 0x0FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn get_name(&self) -> &'static str {
+        "MMC1"
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => Some(self.prg_ram[self.prg_ram_index(addr)]),
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_rom_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let idx = self.prg_ram_index(addr);
+                self.prg_ram[idx] = data;
+            },
+            0x8000..=0xFFFF => {
+                let consecutive = self.last_write.is_some_and(|x| x + 1 == self.cycle);
+                self.last_write = Some(self.cycle);
+                if consecutive {
+                    return;
+                }
+                if data & 0x80 > 0 {
+                    self.shift = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+                self.shift |= (data & 0x01) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(addr, self.shift);
+                    self.shift = 0;
+                    self.shift_count = 0;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_index(addr)],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    let idx = self.chr_index(addr);
+                    self.chr[idx] = data;
+                }
+            },
+            _ => self.nametables.write(addr, data, ciram),
+        }
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        if addr < 0x2000 {
+            self.chr_a12 = addr & 0x1000 > 0;
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.cycle += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every byte of a 16K PRG bank holds the bank number
+    fn mmc1(prg_banks: usize, chr_ram_size: usize, prg_ram_size: usize) -> Mmc1 {
+        Mmc1::new(Cartridge {
+            mapper: 1,
+            submapper: 0,
+            prg_rom: (0..prg_banks * 0x4000).map(|x| (x / 0x4000) as u8).collect(),
+            chr: vec![0; chr_ram_size],
+            chr_ram: true,
+            prg_ram: vec![0; prg_ram_size],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        })
+    }
+
+    // five serial writes, a cycle apart so none is ignored
+    fn write(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.clock_cpu();
+            mmc1.clock_cpu();
+            mmc1.cpu_write(addr, (value >> bit) & 0x01);
+        }
+    }
+
+    fn ram_works(mmc1: &mut Mmc1) -> bool {
+        mmc1.cpu_write(0x6000, 0x5A);
+        mmc1.cpu_peek(0x6000) == Some(0x5A)
+    }
+
+    #[test]
+    fn serial_writes_switch_prg_banks() {
+        let mut mmc1 = mmc1(8, 0x2000, 0x2000);
+        assert_eq!(mmc1.cpu_peek(0xC000), Some(7));
+        write(&mut mmc1, 0xE000, 0x03);
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(3));
+        write(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_peek(0xC000), Some(3));
+    }
+
+    #[test]
+    fn consecutive_writes_are_ignored() {
+        let mut mmc1 = mmc1(8, 0x2000, 0x2000);
+        // the dummy write and the write of a read-modify-write instruction
+        for _ in 0..5 {
+            mmc1.clock_cpu();
+            mmc1.clock_cpu();
+            mmc1.cpu_write(0xE000, 0x01);
+            mmc1.clock_cpu();
+            mmc1.cpu_write(0xE000, 0x00);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(0x1F % 8));
+    }
+
+    #[test]
+    fn snrom_disables_prg_ram_with_chr_bit_4() {
+        let mut snrom = mmc1(16, 0x2000, 0x2000);
+        write(&mut snrom, 0xA000, 0x10);
+        assert!(!ram_works(&mut snrom));
+        write(&mut snrom, 0xA000, 0x00);
+        assert!(ram_works(&mut snrom));
+
+        // SUROM uses the bit for the 256K PRG bank
+        let mut surom = mmc1(32, 0x2000, 0x2000);
+        write(&mut surom, 0xA000, 0x10);
+        assert!(ram_works(&mut surom));
+        assert_eq!(surom.cpu_peek(0xC000), Some(31));
+
+        // more CHR RAM than SNROM has
+        let mut large_chr = mmc1(16, 0x8000, 0x2000);
+        write(&mut large_chr, 0xA000, 0x10);
+        assert!(ram_works(&mut large_chr));
+    }
+
+    #[test]
+    fn sxrom_banks_prg_ram() {
+        let mut sxrom = mmc1(32, 0x2000, 0x8000);
+        write(&mut sxrom, 0xA000, 0x0C);
+        sxrom.cpu_write(0x6000, 0x33);
+        write(&mut sxrom, 0xA000, 0x00);
+        assert_eq!(sxrom.cpu_peek(0x6000), Some(0x00));
+        write(&mut sxrom, 0xA000, 0x0C);
+        assert_eq!(sxrom.cpu_peek(0x6000), Some(0x33));
+    }
+}
@@ -0,0 +1,205 @@
+// MMC2 (mapper 9, PxROM) and MMC4 (mapper 10, FxROM)
+//
+// $A000-$AFFF ---- PPPP  MMC2: 8K PRG bank at $8000, the last three 8K banks are fixed
+//                        MMC4: 16K PRG bank at $8000, the last 16K bank is fixed
+// $B000-$BFFF 4K CHR bank at $0000 while latch 0 is $FD
+// $C000-$CFFF 4K CHR bank at $0000 while latch 0 is $FE
+// $D000-$DFFF 4K CHR bank at $1000 while latch 1 is $FD
+// $E000-$EFFF 4K CHR bank at $1000 while latch 1 is $FE
+// $F000-$FFFF ---- ---M  mirroring, 0 vertical, 1 horizontal
+//
+// The latches follow the pattern fetches, after the fetch that sets them (the tile itself still
+// comes from the old bank):
+//
+// latch 0  $0FD8 -> $FD, $0FE8 -> $FE (MMC4: $0FD8-$0FDF, $0FE8-$0FEF)
+// latch 1  $1FD8-$1FDF -> $FD, $1FE8-$1FEF -> $FE
+//
+// MMC4 boards also have 8K of PRG RAM at $6000. Both latches start at $FE.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::ppu::Mirroring;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip {
+    Mmc2,
+    Mmc4,
+}
+
+pub struct Mmc2 {
+    chip: Chip,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    nametables: Nametables,
+    prg_bank: u8,
+    // [FD, FE] banks for $0000 and $1000
+    chr_banks: [[u8; 2]; 2],
+    // true for $FE
+    latches: [bool; 2],
+}
+
+impl Mmc2 {
+    pub fn new(chip: Chip, cartridge: Cartridge) -> Mmc2 {
+        Mmc2 {
+            chip,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: if chip == Chip::Mmc4 { cartridge.prg_ram } else { vec![] },
+            chr: cartridge.chr,
+            nametables: Nametables::new(cartridge.mirroring),
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [true; 2],
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let addr = addr as usize - 0x8000;
+        let idx = match self.chip {
+            Chip::Mmc2 => {
+                let banks = (self.prg_rom.len() / 0x2000).max(4);
+                let bank = match addr / 0x2000 {
+                    0 => self.prg_bank as usize,
+                    n => banks - 4 + n,
+                };
+                bank * 0x2000 + (addr & 0x1FFF)
+            },
+            Chip::Mmc4 => {
+                let last = (self.prg_rom.len() / 0x4000).max(1) - 1;
+                let bank = if addr < 0x4000 { self.prg_bank as usize } else { last };
+                bank * 0x4000 + (addr & 0x3FFF)
+            },
+        };
+        idx % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let half = (addr >> 12) as usize & 0x01;
+        let bank = self.chr_banks[half][self.latches[half] as usize] as usize;
+        (bank * 0x1000 + (addr as usize & 0x0FFF)) % self.chr.len()
+    }
+
+    fn update_latches(&mut self, addr: u16) {
+        let exact_0 = self.chip == Chip::Mmc2;
+        match addr {
+            0x0FD8 => self.latches[0] = false,
+            0x0FE8 => self.latches[0] = true,
+            0x0FD9..=0x0FDF if !exact_0 => self.latches[0] = false,
+            0x0FE9..=0x0FEF if !exact_0 => self.latches[0] = true,
+            0x1FD8..=0x1FDF => self.latches[1] = false,
+            0x1FE8..=0x1FEF => self.latches[1] = true,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn get_name(&self) -> &'static str {
+        match self.chip {
+            Chip::Mmc2 => "MMC2",
+            Chip::Mmc4 => "MMC4",
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+            },
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = data;
+            },
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => {
+                self.nametables.set_mirroring(if data & 0x01 > 0 { Mirroring::Horizontal } else { Mirroring::Vertical });
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        let data = self.ppu_peek(addr, ciram);
+        self.update_latches(addr);
+        data
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_index(addr)],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        if addr >= 0x2000 {
+            self.nametables.write(addr, data, ciram);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::console::System;
+    use crate::nes::ppu::Ppu;
+
+    // every byte of a 4K CHR bank holds the bank number
+    fn mmc2() -> Mmc2 {
+        let cartridge = Cartridge {
+            mapper: 9,
+            submapper: 0,
+            prg_rom: vec![0; 0x20000],
+            chr: (0..0x20000).map(|x| (x / 0x1000) as u8).collect(),
+            chr_ram: false,
+            prg_ram: vec![],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        };
+        let mut mmc2 = Mmc2::new(Chip::Mmc2, cartridge);
+        mmc2.cpu_write(0xB000, 1);
+        mmc2.cpu_write(0xC000, 2);
+        mmc2
+    }
+
+    #[test]
+    fn latch_changes_after_the_fetch() {
+        let mut mmc2 = mmc2();
+        let ciram = [0; CIRAM_SIZE];
+        assert_eq!(mmc2.ppu_read(0x0FD8, &ciram), 2);
+        assert_eq!(mmc2.ppu_read(0x0000, &ciram), 1);
+        assert_eq!(mmc2.ppu_read(0x0FE8, &ciram), 1);
+        assert_eq!(mmc2.ppu_read(0x0000, &ciram), 2);
+    }
+
+    #[test]
+    fn rendering_fetches_switch_the_latch() {
+        let mut mmc2 = mmc2();
+        let mut ppu = Ppu::new(System::Ntsc);
+        // nametable 0 full of tile $FD, background from $0000
+        ppu.write_register(0x2006, 0x20, &mut mmc2);
+        ppu.write_register(0x2006, 0x00, &mut mmc2);
+        for _ in 0..0x3C0 {
+            ppu.write_register(0x2007, 0xFD, &mut mmc2);
+        }
+        ppu.write_register(0x2000, 0x00, &mut mmc2);
+        ppu.write_register(0x2006, 0x00, &mut mmc2);
+        ppu.write_register(0x2006, 0x00, &mut mmc2);
+        assert!(mmc2.latches[0]);
+        ppu.write_register(0x2001, 0x08, &mut mmc2);
+        for _ in 0..341 * 262 {
+            ppu.clock(&mut mmc2);
+        }
+        assert!(!mmc2.latches[0]);
+    }
+}
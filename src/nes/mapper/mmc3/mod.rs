@@ -0,0 +1,322 @@
+// MMC3 (mapper 4), TxROM boards
+//
+// $8000 even  CP-- -RRR  bank select: C: CHR A12 inversion, P: PRG mode, R: register for $8001
+// $8001 odd   bank data for the selected register:
+//             R0/R1 2K CHR banks (low bit ignored), R2-R5 1K CHR banks, R6/R7 8K PRG banks
+// $A000 even  ---- ---M  mirroring, 0 vertical, 1 horizontal (ignored on four screen boards)
+// $A001 odd   EW-- ----  PRG RAM enable, write protect
+// $C000 even  IRQ latch
+// $C001 odd   IRQ reload, the counter is reloaded at the next clock
+// $E000 even  IRQ disable, acknowledges a pending IRQ
+// $E001 odd   IRQ enable
+//
+// PRG, mode 0: $8000 R6, $A000 R7, $C000 second to last, $E000 last
+//      mode 1: $8000 second to last, $A000 R7, $C000 R6, $E000 last
+// CHR: $0000 R0, $0800 R1, $1000 R2, $1400 R3, $1800 R4, $1C00 R5, the halves are swapped
+//      with inversion
+//
+// The scanline counter is clocked by rising edges of PPU A12, after A12 stayed low for about
+// 3 CPU cycles. The filter drops the fast toggles of the sprite fetches and the gap between
+// the last background fetch of a line and the first of the next when both use $1000. When clocked, a zero
+// counter or a pending reload loads the latch, otherwise the counter goes down. The IRQ fires
+// when the counter is 0 after that and IRQs are enabled. The old Sharp chips (MMC3A, NES 2.0
+// submapper 4) and the MMC6 (submapper 1) only fire when the counter got to 0 by decrementing
+// or by a $C001 reload, so a latch of 0 gives a single IRQ instead of one per scanline.
+// iNES 1.0 files get the later behaviour.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::ppu::Mirroring;
+
+// PPU dots A12 has to stay low before a rising edge counts, the 9 dots from the unused
+// nametable fetch at 337 to the next pattern fetch at 5 are not enough
+const A12_FILTER_DOTS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IrqRevision {
+    // MMC3A, old behaviour
+    Sharp,
+    // MMC3B/C, new behaviour
+    Nec,
+    // old behaviour as well
+    Mmc6,
+}
+
+impl IrqRevision {
+    // everything but the MMC3A and MMC6 submappers behaves like the later chips
+    pub fn from_submapper(submapper: u8) -> IrqRevision {
+        match submapper {
+            1 => IrqRevision::Mmc6,
+            4 => IrqRevision::Sharp,
+            _ => IrqRevision::Nec,
+        }
+    }
+}
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+    bank_select: u8,
+    banks: [u8; 8],
+    prg_ram_protect: u8,
+    irq_revision: IrqRevision,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+    a12_low_dot: usize,
+    dot: usize,
+}
+
+impl Mmc3 {
+    pub fn new(irq_revision: IrqRevision, cartridge: Cartridge) -> Mmc3 {
+        Mmc3 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(cartridge.mirroring),
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            // enabled and writable, games that never touch $A001 still get their RAM
+            prg_ram_protect: 0x80,
+            irq_revision,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_dot: 0,
+            dot: 0,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let second_last = banks.saturating_sub(2);
+        let prg_mode = self.bank_select & 0x40 > 0;
+        let bank = match (addr >> 13) & 0x03 {
+            0 => if prg_mode { second_last } else { self.banks[6] as usize },
+            1 => self.banks[7] as usize,
+            2 => if prg_mode { self.banks[6] as usize } else { second_last },
+            _ => banks - 1,
+        };
+        ((bank % banks) * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0x80 > 0 { addr ^ 0x1000 } else { addr } as usize;
+        let bank_1k = match addr >> 10 {
+            0 | 1 => (self.banks[0] as usize & 0xFE) | (addr >> 10),
+            2 | 3 => (self.banks[1] as usize & 0xFE) | ((addr >> 10) & 0x01),
+            n => self.banks[n - 2] as usize,
+        };
+        (bank_1k * 0x400 + (addr & 0x3FF)) % self.chr.len()
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_ram_protect & 0x80 > 0
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let counter = self.irq_counter;
+        let reload = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+        let fire = match self.irq_revision {
+            IrqRevision::Sharp | IrqRevision::Mmc6 => self.irq_counter == 0 && (counter > 0 || reload),
+            IrqRevision::Nec => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn get_name(&self) -> &'static str {
+        "MMC3"
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+            },
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let odd = addr & 0x01 > 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() && self.prg_ram_protect & 0x40 == 0 => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = data;
+            },
+            0x8000..=0x9FFF => {
+                if odd {
+                    self.banks[(self.bank_select & 0x07) as usize] = data;
+                } else {
+                    self.bank_select = data;
+                }
+            },
+            0xA000..=0xBFFF => {
+                if odd {
+                    self.prg_ram_protect = data;
+                } else {
+                    self.nametables.set_mirroring(if data & 0x01 > 0 { Mirroring::Horizontal } else { Mirroring::Vertical });
+                }
+            },
+            0xC000..=0xDFFF => {
+                if odd {
+                    self.irq_counter = 0;
+                    self.irq_reload = true;
+                } else {
+                    self.irq_latch = data;
+                }
+            },
+            0xE000..=0xFFFF => {
+                self.irq_enabled = odd;
+                if !odd {
+                    self.irq_pending = false;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_index(addr)],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    let idx = self.chr_index(addr);
+                    self.chr[idx] = data;
+                }
+            },
+            _ => self.nametables.write(addr, data, ciram),
+        }
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 > 0;
+        if a12 && !self.a12 && self.dot - self.a12_low_dot >= A12_FILTER_DOTS {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_low_dot = self.dot;
+        }
+        self.a12 = a12;
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_ppu(&mut self) {
+        self.dot += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmc3(irq_revision: IrqRevision) -> Mmc3 {
+        Mmc3::new(irq_revision, Cartridge {
+            mapper: 4,
+            submapper: 0,
+            prg_rom: vec![0; 0x8000],
+            chr: vec![0; 0x2000],
+            chr_ram: true,
+            prg_ram: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        })
+    }
+
+    // latch, $C001 and IRQ enable, then whether each clock raised the IRQ (acknowledged after)
+    fn irqs(irq_revision: IrqRevision, latch: u8, clocks: usize) -> Vec<bool> {
+        let mut mmc3 = mmc3(irq_revision);
+        mmc3.cpu_write(0xC000, latch);
+        mmc3.cpu_write(0xC001, 0x00);
+        mmc3.cpu_write(0xE001, 0x00);
+        (0..clocks).map(|_| {
+            mmc3.clock_irq_counter();
+            let irq = mmc3.irq_line();
+            mmc3.cpu_write(0xE000, 0x00);
+            mmc3.cpu_write(0xE001, 0x00);
+            irq
+        }).collect()
+    }
+
+    #[test]
+    fn latch_0_fires_every_clock_on_nec_and_once_on_sharp() {
+        assert_eq!(irqs(IrqRevision::Nec, 0, 3), [true, true, true]);
+        assert_eq!(irqs(IrqRevision::Sharp, 0, 3), [true, false, false]);
+        assert_eq!(irqs(IrqRevision::Mmc6, 0, 3), [true, false, false]);
+    }
+
+    #[test]
+    fn latch_1_fires_every_other_clock() {
+        for revision in [IrqRevision::Nec, IrqRevision::Sharp, IrqRevision::Mmc6] {
+            assert_eq!(irqs(revision, 1, 5), [false, true, false, true, false]);
+        }
+    }
+
+    #[test]
+    fn reloading_0_after_counting_down_depends_on_the_revision() {
+        for (revision, irq) in [(IrqRevision::Nec, true), (IrqRevision::Sharp, false), (IrqRevision::Mmc6, false)] {
+            let mut mmc3 = mmc3(revision);
+            mmc3.cpu_write(0xC000, 0x01);
+            mmc3.cpu_write(0xC001, 0x00);
+            mmc3.cpu_write(0xE001, 0x00);
+            mmc3.clock_irq_counter();
+            mmc3.cpu_write(0xC000, 0x00);
+            mmc3.clock_irq_counter();
+            assert!(mmc3.irq_line());
+            mmc3.cpu_write(0xE000, 0x00);
+            mmc3.cpu_write(0xE001, 0x00);
+            mmc3.clock_irq_counter();
+            assert_eq!(mmc3.irq_line(), irq);
+        }
+    }
+
+    #[test]
+    fn a12_rises_close_together_are_filtered() {
+        let mut mmc3 = mmc3(IrqRevision::Nec);
+        mmc3.cpu_write(0xC000, 0x00);
+        mmc3.cpu_write(0xE001, 0x00);
+        let mut rise_after = |dots: usize| {
+            mmc3.ppu_address(0x0000);
+            for _ in 0..dots {
+                mmc3.clock_ppu();
+            }
+            mmc3.ppu_address(0x1000);
+            let irq = mmc3.irq_line();
+            mmc3.cpu_write(0xE000, 0x00);
+            mmc3.cpu_write(0xE001, 0x00);
+            irq
+        };
+        assert!(rise_after(A12_FILTER_DOTS));
+        assert!(!rise_after(9));
+        assert!(!rise_after(4));
+        assert!(rise_after(68));
+    }
+}
@@ -0,0 +1,573 @@
+// MMC5 (mapper 5), ExROM boards
+//
+// $5100       ---- --PP  PRG mode: 0 32K, 1 16K+16K, 2 16K+8K+8K, 3 8K*4
+// $5101       ---- --CC  CHR mode: 0 8K, 1 4K, 2 2K, 3 1K
+// $5102/$5103 PRG RAM write protect, writes are allowed with $5102 = 2 and $5103 = 1
+// $5104       ---- --XX  ExRAM mode: 0 nametable, 1 extended attributes, 2 CPU RAM, 3 CPU ROM
+// $5105       DDCC BBAA  nametable of each quarter: 0/1 CIRAM page, 2 ExRAM, 3 fill mode
+// $5106/$5107 fill mode tile and attribute
+// $5113       8K PRG RAM bank at $6000
+// $5114-$5117 R--- BBBB  8K/16K/32K banks at $8000-$FFFF by PRG mode, R: ROM (else RAM),
+//                        $5117 is always ROM
+// $5120-$5127 CHR banks, set A: sprites (every fetch with 8x8 sprites)
+// $5128-$512B CHR banks, set B: background with 8x16 sprites, for $0000-$0FFF and $1000-$1FFF
+// $5130       upper 2 bits of the CHR banks written next
+// $5200       ES-T TTTT  vertical split: E: enable, S: right side, T: tile where it starts/ends
+// $5201/$5202 split Y scroll and split 4K CHR bank
+// $5203       IRQ scanline
+// $5204       write: E--- ---- IRQ enable, read: PI-- ----  P: IRQ pending (read acknowledges),
+//             I: in frame
+// $5205/$5206 write: 8 bit factors, read: 16 bit product
+// $5C00-$5FFF ExRAM (1K)
+//
+// PRG by mode (registers $5114-$5117 as 4-7):
+// 0: $8000 32K 7, 1: $8000 16K 5, $C000 16K 7, 2: $8000 16K 5, $C000 8K 6, $E000 8K 7,
+// 3: 8K each from 4 to 7. 16K and 32K banks ignore the low bank bits.
+//
+// The board has no scanline input, it follows the PPU reads instead. Three reads of the same
+// nametable address in a row are the two dummy fetches at the end of a scanline plus the first
+// tile fetch of the next one, from there the reads of a scanline come in a fixed order:
+//
+// 0-127    background tiles 2-33 (nametable, attribute, pattern low, pattern high)
+// 128-159  sprites (two dummy nametable reads, pattern low, pattern high)
+// 160-167  background tiles 0-1 of the next scanline
+//
+// The first scanline found sets "in frame", the following ones count up and raise the IRQ at
+// the compare value. The PPU going quiet for a few CPU cycles, rendering being turned off
+// through $2001 or the CPU reading the NMI vector ends the frame. $2000 and $2001 writes are
+// snooped for the sprite size and rendering state.
+//
+// Extended attributes: the ExRAM byte of each background tile gives its 4K CHR bank (bits 0-5,
+// with $5130) and palette (bits 6-7). Vertical split: the background tiles on the split side
+// come from ExRAM as a nametable, with their own Y scroll and CHR bank.
+//
+// The pulses and PCM are the expansion audio of the board.
+
+use super::{Cartridge, Mapper, CIRAM_SIZE};
+use crate::nes::cpu::apu::expansion::mmc5::Mmc5Audio;
+use crate::nes::cpu::apu::expansion::ExpansionAudio;
+
+// CPU cycles without PPU reads before the frame is considered over
+const IDLE_CYCLES: usize = 3;
+
+enum Fetch {
+    Background { tile: usize, next_line: bool },
+    Sprite,
+    // CPU access through $2007, or outside of a frame
+    Other,
+}
+
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    exram: [u8; 0x400],
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    chr_banks_a: [usize; 8],
+    chr_banks_b: [usize; 4],
+    chr_upper: u8,
+    last_set_b: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    factors: [u8; 2],
+    sprite_8x16: bool,
+    rendering_enabled: bool,
+    last_read: u16,
+    same_reads: usize,
+    fetch_index: usize,
+    idle_cycles: usize,
+    // ExRAM byte of the tile being fetched (extended attributes), split tile state
+    ex_attribute: u8,
+    split_tile: bool,
+    split_y: usize,
+    audio: Option<Box<dyn ExpansionAudio>>,
+}
+
+impl Mmc5 {
+    pub fn new(cartridge: Cartridge) -> Mmc5 {
+        Mmc5 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            exram: [0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            last_set_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            factors: [0xFF; 2],
+            sprite_8x16: false,
+            rendering_enabled: false,
+            last_read: 0,
+            same_reads: 0,
+            fetch_index: 0,
+            idle_cycles: 0,
+            ex_attribute: 0,
+            split_tile: false,
+            split_y: 0,
+            audio: Some(Box::new(Mmc5Audio::new())),
+        }
+    }
+
+    // (ROM, 8K bank) mapped at a CPU address in $6000-$FFFF
+    fn prg_bank(&self, addr: u16) -> (bool, usize) {
+        if addr < 0x8000 {
+            return (false, self.prg_banks[0] as usize & 0x07);
+        }
+        let window = (addr as usize - 0x8000) / 0x2000;
+        let (register, mask, offset) = match self.prg_mode {
+            0 => (4, 0x7C, window),
+            1 => (if window < 2 { 2 } else { 4 }, 0x7E, window & 0x01),
+            2 if window < 2 => (2, 0x7E, window & 0x01),
+            _ => (window + 1, 0x7F, 0),
+        };
+        let value = self.prg_banks[register];
+        let rom = register == 4 || value & 0x80 > 0;
+        (rom, ((value & mask) as usize) | offset)
+    }
+
+    fn prg_ram_index(&self, bank: usize, addr: u16) -> usize {
+        (bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_ram.len()
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01] && !self.prg_ram.is_empty()
+    }
+
+    // 1K CHR bank of a pattern table address
+    fn chr_bank(&self, addr: u16, use_set_b: bool) -> usize {
+        let addr = addr as usize;
+        if use_set_b {
+            let addr = addr & 0x0FFF;
+            match self.chr_mode {
+                0 => self.chr_banks_b[3] * 8 + addr / 0x400,
+                1 => self.chr_banks_b[3] * 4 + addr / 0x400,
+                2 => self.chr_banks_b[(addr / 0x800) * 2 + 1] * 2 + ((addr / 0x400) & 0x01),
+                _ => self.chr_banks_b[addr / 0x400],
+            }
+        } else {
+            match self.chr_mode {
+                0 => self.chr_banks_a[7] * 8 + addr / 0x400,
+                1 => self.chr_banks_a[(addr / 0x1000) * 4 + 3] * 4 + ((addr & 0x0FFF) / 0x400),
+                2 => self.chr_banks_a[(addr / 0x800) * 2 + 1] * 2 + ((addr / 0x400) & 0x01),
+                _ => self.chr_banks_a[addr / 0x400],
+            }
+        }
+    }
+
+    fn chr_index(&self, addr: u16, use_set_b: bool) -> usize {
+        (self.chr_bank(addr, use_set_b) * 0x400 + (addr as usize & 0x3FF)) % self.chr.len()
+    }
+
+    // set B is only used with 8x16 sprites: for the background while rendering, otherwise when
+    // it was written last
+    fn uses_set_b(&self, fetch: &Fetch) -> bool {
+        if !self.sprite_8x16 {
+            return false;
+        }
+        match fetch {
+            Fetch::Background { .. } => true,
+            Fetch::Sprite => false,
+            Fetch::Other => self.last_set_b,
+        }
+    }
+
+    fn read_nametable(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        let offset = addr as usize & 0x3FF;
+        let quarter = (addr as usize >> 10) & 0x03;
+        match (self.nametable_mapping >> (quarter * 2)) & 0x03 {
+            0 => ciram[offset],
+            1 => ciram[0x400 + offset],
+            2 => if self.exram_mode <= 1 { self.exram[offset] } else { 0x00 },
+            _ => if offset < 0x3C0 { self.fill_tile } else { (self.fill_attribute & 0x03) * 0x55 },
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        let offset = addr as usize & 0x3FF;
+        let quarter = (addr as usize >> 10) & 0x03;
+        match (self.nametable_mapping >> (quarter * 2)) & 0x03 {
+            0 => ciram[offset] = data,
+            1 => ciram[0x400 + offset] = data,
+            2 if self.exram_mode <= 1 => self.exram[offset] = data,
+            _ => {}
+        }
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare && self.irq_compare != 0 {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+        self.fetch_index = 0;
+    }
+
+    fn end_frame(&mut self) {
+        self.in_frame = false;
+        self.last_read = 0;
+        self.same_reads = 0;
+    }
+
+    // follows the PPU reads, see the top of the file
+    fn track_fetch(&mut self, addr: u16) -> Fetch {
+        self.idle_cycles = 0;
+        if addr >= 0x2000 && addr == self.last_read {
+            self.same_reads += 1;
+            if self.same_reads == 2 {
+                self.detect_scanline();
+            }
+        } else {
+            self.same_reads = 0;
+        }
+        self.last_read = addr;
+        if !self.in_frame || !self.rendering_enabled {
+            return Fetch::Other;
+        }
+        let index = self.fetch_index;
+        self.fetch_index += 1;
+        match index {
+            0..=127 => Fetch::Background { tile: index / 4 + 2, next_line: false },
+            128..=159 => Fetch::Sprite,
+            160..=167 => Fetch::Background { tile: (index - 160) / 4, next_line: true },
+            _ => Fetch::Other,
+        }
+    }
+
+    fn in_split(&self, tile: usize) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let start = (self.split_control & 0x1F) as usize;
+        if self.split_control & 0x40 > 0 { tile >= start } else { tile < start }
+    }
+
+    fn read_background(&mut self, addr: u16, tile: usize, next_line: bool, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        let is_nametable = addr >= 0x2000 && addr & 0x3FF < 0x3C0;
+        let is_attribute = addr >= 0x2000 && !is_nametable;
+        // tiles 32 and 33 are the first ones of the next nametable
+        let column = tile % 32;
+        if is_nametable {
+            // the nametable read starts a new tile
+            self.split_tile = self.in_split(tile);
+            if self.split_tile {
+                let line = self.scanline as usize + if next_line { 1 } else { 0 };
+                self.split_y = (self.split_scroll as usize + line) % 240;
+                return self.exram[(self.split_y / 8) * 32 + column];
+            }
+            self.ex_attribute = self.exram[addr as usize & 0x3FF];
+            return self.read_nametable(addr, ciram);
+        }
+        if self.split_tile {
+            if is_attribute {
+                let coarse_y = self.split_y / 8;
+                let byte = self.exram[0x3C0 + (coarse_y / 4) * 8 + column / 4];
+                let shift = ((coarse_y & 0x02) << 1) | (column & 0x02);
+                return ((byte >> shift) & 0x03) * 0x55;
+            }
+            let idx = self.split_bank as usize * 0x1000 + (addr as usize & 0x0FF8) + (self.split_y & 0x07);
+            return self.chr[idx % self.chr.len()];
+        }
+        if self.exram_mode == 1 {
+            if is_attribute {
+                return (self.ex_attribute >> 6) * 0x55;
+            }
+            let bank = ((self.chr_upper as usize) << 6) | (self.ex_attribute & 0x3F) as usize;
+            return self.chr[(bank * 0x1000 + (addr as usize & 0x0FFF)) % self.chr.len()];
+        }
+        if addr >= 0x2000 {
+            self.read_nametable(addr, ciram)
+        } else {
+            self.chr[self.chr_index(addr, self.uses_set_b(&Fetch::Background { tile, next_line }))]
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn get_name(&self) -> &'static str {
+        "MMC5"
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let data = self.cpu_peek(addr);
+        match addr {
+            0x5204 => self.irq_pending = false,
+            // the CPU fetching the NMI vector means vblank
+            0xFFFA | 0xFFFB => self.end_frame(),
+            _ => {}
+        }
+        data
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => {
+                let mut status = 0x00;
+                if self.irq_pending {
+                    status |= 0x80;
+                }
+                if self.in_frame {
+                    status |= 0x40;
+                }
+                Some(status)
+            },
+            0x5205 => Some((self.factors[0] as u16 * self.factors[1] as u16) as u8),
+            0x5206 => Some(((self.factors[0] as u16 * self.factors[1] as u16) >> 8) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[addr as usize - 0x5C00]),
+            0x6000..=0xFFFF => {
+                let (rom, bank) = self.prg_bank(addr);
+                if rom {
+                    if self.prg_rom.is_empty() {
+                        return None;
+                    }
+                    Some(self.prg_rom[(bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()])
+                } else if self.prg_ram.is_empty() {
+                    None
+                } else {
+                    Some(self.prg_ram[self.prg_ram_index(bank, addr)])
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000..=0x3FFF => match addr & 0x07 {
+                0x00 => self.sprite_8x16 = data & 0x20 > 0,
+                0x01 => {
+                    self.rendering_enabled = data & 0x18 > 0;
+                    if !self.rendering_enabled {
+                        self.end_frame();
+                    }
+                },
+                _ => {}
+            },
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 => self.prg_ram_protect[0] = data & 0x03,
+            0x5103 => self.prg_ram_protect[1] = data & 0x03,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[(addr - 0x5120) as usize] = ((self.chr_upper as usize) << 8) | data as usize;
+                self.last_set_b = false;
+            },
+            0x5128..=0x512B => {
+                self.chr_banks_b[(addr - 0x5128) as usize] = ((self.chr_upper as usize) << 8) | data as usize;
+                self.last_set_b = true;
+            },
+            0x5130 => self.chr_upper = data & 0x03,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 > 0,
+            0x5205 => self.factors[0] = data,
+            0x5206 => self.factors[1] = data,
+            0x5C00..=0x5FFF if self.exram_mode != 3 => {
+                self.exram[addr as usize - 0x5C00] = data;
+            },
+            0x6000..=0xFFFF => {
+                let (rom, bank) = self.prg_bank(addr);
+                if !rom && self.prg_ram_writable() {
+                    let idx = self.prg_ram_index(bank, addr);
+                    self.prg_ram[idx] = data;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match self.track_fetch(addr) {
+            Fetch::Background { tile, next_line } => self.read_background(addr, tile, next_line, ciram),
+            fetch => {
+                if addr >= 0x2000 {
+                    self.read_nametable(addr, ciram)
+                } else {
+                    self.chr[self.chr_index(addr, self.uses_set_b(&fetch))]
+                }
+            },
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        if addr >= 0x2000 {
+            self.read_nametable(addr, ciram)
+        } else {
+            self.chr[self.chr_index(addr, self.uses_set_b(&Fetch::Other))]
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        if addr >= 0x2000 {
+            self.write_nametable(addr, data, ciram);
+        } else if self.chr_ram {
+            let idx = self.chr_index(addr, self.uses_set_b(&Fetch::Other));
+            self.chr[idx] = data;
+        }
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn clock_cpu(&mut self) {
+        self.idle_cycles += 1;
+        if self.idle_cycles == IDLE_CYCLES {
+            self.end_frame();
+        }
+    }
+
+    fn take_expansion_audio(&mut self) -> Option<Box<dyn ExpansionAudio>> {
+        self.audio.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::console::System;
+    use crate::nes::ppu::{Mirroring, Ppu};
+
+    // every byte of a 4K CHR bank holds the bank number
+    fn mmc5() -> Mmc5 {
+        Mmc5::new(Cartridge {
+            mapper: 5,
+            submapper: 0,
+            prg_rom: vec![0; 0x8000],
+            chr: (0..0x40000).map(|x| (x / 0x1000) as u8).collect(),
+            chr_ram: false,
+            prg_ram: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        })
+    }
+
+    // register writes as the console does them, the board snoops $2000-$2007
+    fn write(ppu: &mut Ppu, mmc5: &mut Mmc5, addr: u16, data: u8) {
+        ppu.write_register(addr, data, mmc5);
+        mmc5.cpu_write(addr, data);
+    }
+
+    // one CPU cycle every 3 dots
+    fn run_until(ppu: &mut Ppu, mmc5: &mut Mmc5, scanline: usize) {
+        let mut dots = 0;
+        while ppu.get_scanline() != scanline || ppu.get_dot() != 0 {
+            ppu.clock(mmc5);
+            dots += 1;
+            if dots % 3 == 0 {
+                mmc5.clock_cpu();
+            }
+        }
+    }
+
+    #[test]
+    fn irq_on_the_compare_scanline() {
+        let mut mmc5 = mmc5();
+        let mut ppu = Ppu::new(System::Ntsc);
+        write(&mut ppu, &mut mmc5, 0x2001, 0x08);
+        run_until(&mut ppu, &mut mmc5, 261);
+        mmc5.cpu_write(0x5203, 100);
+        mmc5.cpu_write(0x5204, 0x80);
+        mmc5.cpu_read(0x5204);
+        run_until(&mut ppu, &mut mmc5, 99);
+        assert!(!mmc5.irq_line());
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x40));
+        run_until(&mut ppu, &mut mmc5, 101);
+        assert!(mmc5.irq_line());
+        run_until(&mut ppu, &mut mmc5, 245);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x80));
+    }
+
+    #[test]
+    fn extended_attributes() {
+        let mut mmc5 = mmc5();
+        let mut ppu = Ppu::new(System::Ntsc);
+        mmc5.cpu_write(0x5104, 0x01);
+        // first tile: palette 3, 4K bank 5 (pattern bytes $05)
+        mmc5.cpu_write(0x5C00, 0xC5);
+        write(&mut ppu, &mut mmc5, 0x2006, 0x3F);
+        write(&mut ppu, &mut mmc5, 0x2006, 0x00);
+        for color in [0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30].iter() {
+            write(&mut ppu, &mut mmc5, 0x2007, *color);
+        }
+        write(&mut ppu, &mut mmc5, 0x2006, 0x00);
+        write(&mut ppu, &mut mmc5, 0x2006, 0x00);
+        write(&mut ppu, &mut mmc5, 0x2001, 0x0A);
+        run_until(&mut ppu, &mut mmc5, 261);
+        run_until(&mut ppu, &mut mmc5, 241);
+        let frame = ppu.get_frame();
+        assert_eq!(frame.get_pixel(5, 4), 0x30);
+        assert_eq!(frame.get_pixel(6, 4), 0x0F);
+        assert_eq!(frame.get_pixel(7, 4), 0x30);
+        assert_eq!(frame.get_pixel(15, 4), 0x0F);
+    }
+
+    #[test]
+    fn vertical_split() {
+        let mut mmc5 = mmc5();
+        let mut ppu = Ppu::new(System::Ntsc);
+        // tiles 0 and 1 from the split, tile 0 of 4K bank 7 (pattern bytes $07)
+        mmc5.cpu_write(0x5200, 0x82);
+        mmc5.cpu_write(0x5202, 0x07);
+        write(&mut ppu, &mut mmc5, 0x2006, 0x3F);
+        write(&mut ppu, &mut mmc5, 0x2006, 0x00);
+        for color in [0x0F, 0x00, 0x00, 0x16].iter() {
+            write(&mut ppu, &mut mmc5, 0x2007, *color);
+        }
+        write(&mut ppu, &mut mmc5, 0x2006, 0x00);
+        write(&mut ppu, &mut mmc5, 0x2006, 0x00);
+        write(&mut ppu, &mut mmc5, 0x2001, 0x0A);
+        run_until(&mut ppu, &mut mmc5, 261);
+        run_until(&mut ppu, &mut mmc5, 241);
+        let frame = ppu.get_frame();
+        // the first two tiles of scanline 0 are fetched before the board finds the frame start
+        for y in [1, 100, 239].iter() {
+            assert_eq!(frame.get_pixel(4, *y), 0x0F);
+            assert_eq!(frame.get_pixel(5, *y), 0x16);
+            assert_eq!(frame.get_pixel(15, *y), 0x16);
+            assert_eq!(frame.get_pixel(23, *y), 0x0F);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Cartridge boards (mappers)
+//
+// A cartridge sits on both buses:
+//
+// CPU $4020-$FFFF  PRG ROM, PRG RAM and the mapper registers
+// PPU $0000-$1FFF  CHR ROM/RAM
+// PPU $2000-$3EFF  nametables, the board decides which 1K of the console's 2K (CIRAM) is seen
+//                  in each quarter (mirroring), or puts its own memory there
+//
+// Boards can pull the IRQ line, some watch the CPU clock to count cycles and some watch the
+// PPU address bus (A12 for scanline counters, pattern fetches for CHR latches). A board carrying
+// an expansion audio chip hands it over to the APU once, see `take_expansion_audio`.
+//
+// Mapper numbers are the iNES ones, up to 12 bits with NES 2.0 which also gives submappers.
+
+pub mod discrete;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod vrc4;
+
+use self::discrete::{Board, Discrete};
+use self::fme7::Fme7;
+use self::mmc1::Mmc1;
+use self::mmc2::{Chip, Mmc2};
+use self::mmc3::{IrqRevision, Mmc3};
+use self::mmc5::Mmc5;
+use self::namco163::{Model, Namco163};
+use self::nrom::Nrom;
+use self::vrc4::{Variant, Vrc4};
+use crate::nes::cpu::apu::expansion::ExpansionAudio;
+use crate::nes::ppu::Mirroring;
+use crate::nes::rom::{Rom, RomV1};
+
+pub const CIRAM_SIZE: usize = 0x800;
+
+pub trait Mapper {
+    fn get_name(&self) -> &'static str;
+
+    // CPU read of $4020-$FFFF, None where the board doesn't drive the bus (open bus)
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    // CPU read without side effects
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    // CPU write of $4020-$FFFF, and of the PPU registers at $2000-$3FFF which some boards snoop
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    // PPU read of $0000-$3EFF, made by the PPU itself (rendering fetches, $2007)
+    fn ppu_read(&mut self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        self.ppu_peek(addr, ciram)
+    }
+
+    // PPU read without side effects
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8;
+
+    // PPU write of $0000-$3EFF
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]);
+
+    // every address the PPU puts on its bus, before the access if there is one
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    // IRQ output, level triggered
+    fn irq_line(&self) -> bool {
+        false
+    }
+
+    // once per CPU cycle
+    fn clock_cpu(&mut self) {}
+
+    // once per PPU dot, after the accesses of that dot
+    fn clock_ppu(&mut self) {}
+
+    // the expansion audio chip of the board, if any, handed out once
+    fn take_expansion_audio(&mut self) -> Option<Box<dyn ExpansionAudio>> {
+        None
+    }
+}
+
+// Everything a board is built from, taken from the ROM file
+pub struct Cartridge {
+    mapper: u16,
+    // NES 2.0 only, 0 otherwise
+    submapper: u8,
+    prg_rom: Vec<u8>,
+    // CHR ROM, or CHR RAM when the file has none
+    chr: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+    battery: bool,
+}
+
+impl Cartridge {
+    pub fn new(rom: &RomV1) -> Cartridge {
+        let header = rom.get_header();
+        let chr_ram = rom.get_chr_rom_data().is_empty();
+        // boards index their RAM without checking for it, they get at least 8K
+        let prg_ram_size = (header.get_prg_ram_size() + header.get_prg_nvram_size()).max(0x2000);
+        let chr_ram_size = (header.get_chr_ram_size() + header.get_chr_nvram_size()).max(0x2000);
+        let mut prg_ram = vec![0; prg_ram_size];
+        // the trainer goes to $7000
+        if let Some(trainer) = rom.get_trainer() {
+            prg_ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+        }
+        Cartridge {
+            mapper: header.get_mapper(),
+            submapper: header.get_submapper(),
+            prg_rom: rom.get_prg_rom_data().clone(),
+            chr: if chr_ram { vec![0; chr_ram_size] } else { rom.get_chr_rom_data().clone() },
+            chr_ram,
+            prg_ram,
+            mirroring: header.get_mirroring(),
+            battery: header.has_battery(),
+        }
+    }
+}
+
+// builds the board of the cartridge
+pub fn new_mapper(cartridge: Cartridge) -> Result<Box<dyn Mapper>, String> {
+    match cartridge.mapper {
+        0 => Ok(Box::new(Nrom::new(cartridge))),
+        1 => Ok(Box::new(Mmc1::new(cartridge))),
+        4 => Ok(Box::new(Mmc3::new(IrqRevision::from_submapper(cartridge.submapper), cartridge))),
+        5 => Ok(Box::new(Mmc5::new(cartridge))),
+        9 => Ok(Box::new(Mmc2::new(Chip::Mmc2, cartridge))),
+        10 => Ok(Box::new(Mmc2::new(Chip::Mmc4, cartridge))),
+        21 | 22 | 23 | 25 => {
+            let variant = Variant::new(cartridge.mapper, cartridge.submapper).ok_or("unsupported VRC variant")?;
+            Ok(Box::new(Vrc4::new(variant, cartridge)))
+        },
+        19 | 210 => {
+            let model = Model::from_mapper(cartridge.mapper, cartridge.submapper).ok_or("unsupported Namco board")?;
+            Ok(Box::new(Namco163::new(model, cartridge)))
+        },
+        69 => Ok(Box::new(Fme7::new(cartridge))),
+        n => match Board::from_mapper(n, !cartridge.chr_ram) {
+            Some(board) => Ok(Box::new(Discrete::new(board, cartridge))),
+            None => Err(format!("unsupported mapper {}", n)),
+        },
+    }
+}
+
+// Nametables of boards that only select a mirroring of CIRAM. Four screen boards carry the
+// other 2K themselves.
+pub struct Nametables {
+    mirroring: Mirroring,
+    extra: Vec<u8>,
+}
+
+impl Nametables {
+    pub fn new(mirroring: Mirroring) -> Nametables {
+        Nametables {
+            mirroring,
+            extra: if mirroring == Mirroring::FourScreen { vec![0; CIRAM_SIZE] } else { vec![] },
+        }
+    }
+
+    // four screen stays four screen, the board has no way out of it
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        if self.mirroring != Mirroring::FourScreen {
+            self.mirroring = mirroring;
+        }
+    }
+
+    pub fn read(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        let idx = self.mirroring.nametable_index(addr);
+        if idx < CIRAM_SIZE { ciram[idx] } else { self.extra[idx - CIRAM_SIZE] }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        let idx = self.mirroring.nametable_index(addr);
+        if idx < CIRAM_SIZE {
+            ciram[idx] = data;
+        } else {
+            self.extra[idx - CIRAM_SIZE] = data;
+        }
+    }
+}
+
+// Empty cartridge slot: open bus on the CPU side, CIRAM with horizontal mirroring
+pub struct NoCartridge;
+
+impl Mapper for NoCartridge {
+    fn get_name(&self) -> &'static str {
+        "none"
+    }
+
+    fn cpu_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x2000..=0x3EFF => ciram[Mirroring::Horizontal.nametable_index(addr)],
+            _ => 0x00,
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        if let 0x2000..=0x3EFF = addr {
+            ciram[Mirroring::Horizontal.nametable_index(addr)] = data;
+        }
+    }
+}
@@ -0,0 +1,349 @@
+// Namco 163 (mapper 19) and its cut down relatives Namco 175 and 340 (mapper 210, submapper 1
+// and 2)
+//
+// $4800-$4FFF DDDD DDDD  N163: internal RAM data port, see the N163 audio
+// $5000-$57FF LLLL LLLL  N163: IRQ counter bits 0-7
+// $5800-$5FFF ECCC CCCC  N163: IRQ counter bits 8-14, E: enable. Both counter writes
+//                        acknowledge the IRQ, both halves read back.
+// $8000-$BFFF 1K CHR banks 0-7, one register every $800
+// $C000-$DFFF N163: 1K nametable banks for $2000, $2400, $2800, $2C00, one every $800
+// $C000-$C7FF N175: ---- ---E  PRG RAM enable
+// $E000-$E7FF MMPP PPPP  8K PRG bank at $8000, N340: M mirroring (one screen lower, vertical,
+//                        one screen upper, horizontal), N163: bit 6 is the sound disable
+// $E800-$EFFF HLPP PPPP  8K PRG bank at $A000, N163: H/L: CIRAM disabled for $1000/$0000
+// $F000-$F7FF --PP PPPP  8K PRG bank at $C000
+// $F800-$FFFF KKKK DCBA  N163: PRG RAM write protect, writes go through with K = 0100 and the
+//                        bit of the 2K window (A: $6000-$67FF ... D: $7800-$7FFF) clear. Also
+//                        the RAM address of the audio port.
+//
+// $E000-$FFFF is the last 8K bank.
+//
+// N163 CHR and nametable banks $E0-$FF select a CIRAM page (bit 0) instead of a CHR ROM bank,
+// for the pattern tables only where the $E800 bit allows it. Nametables can also come from CHR
+// ROM this way, which some games use for fixed screens.
+//
+// The IRQ counter counts CPU cycles up while enabled and stops at $7FFF with the IRQ.
+//
+// The 128 bytes of internal RAM belong to the audio, handed to the APU which then answers the
+// $4800 port. Mapper 19 doesn't tell N163 from the silent N129, both get the audio.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::cpu::apu::expansion::n163::N163Audio;
+use crate::nes::cpu::apu::expansion::ExpansionAudio;
+use crate::nes::ppu::Mirroring;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    N163,
+    N175,
+    N340,
+}
+
+impl Model {
+    pub fn from_mapper(mapper: u16, submapper: u8) -> Option<Model> {
+        match (mapper, submapper) {
+            (19, _) => Some(Model::N163),
+            (210, 2) => Some(Model::N340),
+            // iNES 1.0 files can't tell, N175 keeps the header mirroring
+            (210, _) => Some(Model::N175),
+            _ => None,
+        }
+    }
+}
+
+pub struct Namco163 {
+    model: Model,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    // $0000-$0FFF and $1000-$1FFF can't use CIRAM
+    ciram_disabled: [bool; 2],
+    prg_ram_enabled: bool,
+    write_protect: u8,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio: Option<Box<dyn ExpansionAudio>>,
+}
+
+// where a PPU address ends up
+enum PpuTarget {
+    Chr(usize),
+    Ciram(usize),
+    Nametables,
+}
+
+impl Namco163 {
+    pub fn new(model: Model, cartridge: Cartridge) -> Namco163 {
+        Namco163 {
+            model,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(cartridge.mirroring),
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0, 0xE1, 0xE0, 0xE1],
+            ciram_disabled: [false; 2],
+            prg_ram_enabled: false,
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: if model == Model::N163 { Some(Box::new(N163Audio::new())) } else { None },
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let bank = match (addr >> 13) & 0x03 {
+            3 => banks - 1,
+            window => self.prg_banks[window as usize] as usize,
+        };
+        (bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        match self.model {
+            Model::N163 => {
+                let window = (addr as usize - 0x6000) / 0x800;
+                self.write_protect & 0xF0 == 0x40 && self.write_protect & (0x01 << window) == 0
+            },
+            Model::N175 => self.prg_ram_enabled,
+            Model::N340 => false,
+        }
+    }
+
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        let (bank, ciram_allowed) = match addr {
+            0x0000..=0x1FFF => {
+                let bank = self.chr_banks[(addr >> 10) as usize];
+                (bank, !self.ciram_disabled[(addr >> 12) as usize])
+            },
+            _ if self.model != Model::N163 => return PpuTarget::Nametables,
+            _ => (self.nametable_banks[((addr >> 10) & 0x03) as usize], true),
+        };
+        let offset = addr as usize & 0x3FF;
+        if self.model == Model::N163 && ciram_allowed && bank >= 0xE0 {
+            PpuTarget::Ciram((bank as usize & 0x01) * 0x400 + offset)
+        } else {
+            PpuTarget::Chr((bank as usize * 0x400 + offset) % self.chr.len())
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn get_name(&self) -> &'static str {
+        match self.model {
+            Model::N163 => "Namco 163",
+            Model::N175 => "Namco 175",
+            Model::N340 => "Namco 340",
+        }
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => self.audio.as_mut().and_then(|audio| audio.read_register(addr)),
+            _ => self.cpu_peek(addr),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5000..=0x57FF if self.model == Model::N163 => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF if self.model == Model::N163 => {
+                Some((self.irq_counter >> 8) as u8 | if self.irq_enabled { 0x80 } else { 0x00 })
+            },
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.model != Model::N340 => {
+                if self.model == Model::N175 && !self.prg_ram_enabled {
+                    return None;
+                }
+                Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+            },
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        // the audio port, while the board still owns the chip
+        if let Some(audio) = self.audio.as_mut() {
+            audio.write_register(addr, data);
+        }
+        match addr {
+            0x5000..=0x57FF if self.model == Model::N163 => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq_pending = false;
+            },
+            0x5800..=0x5FFF if self.model == Model::N163 => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16 & 0x7F) << 8);
+                self.irq_enabled = data & 0x80 > 0;
+                self.irq_pending = false;
+            },
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.prg_ram_writable(addr) => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = data;
+            },
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = data,
+            0xC000..=0xC7FF if self.model == Model::N175 => self.prg_ram_enabled = data & 0x01 > 0,
+            0xC000..=0xDFFF if self.model == Model::N163 => {
+                self.nametable_banks[((addr - 0xC000) >> 11) as usize] = data;
+            },
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data & 0x3F;
+                if self.model == Model::N340 {
+                    self.nametables.set_mirroring(match data >> 6 {
+                        0 => Mirroring::SingleScreenLower,
+                        1 => Mirroring::Vertical,
+                        2 => Mirroring::SingleScreenUpper,
+                        _ => Mirroring::Horizontal,
+                    });
+                }
+            },
+            0xE800..=0xEFFF => {
+                self.prg_banks[1] = data & 0x3F;
+                self.ciram_disabled = [data & 0x40 > 0, data & 0x80 > 0];
+            },
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+            0xF800..=0xFFFF => self.write_protect = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match self.ppu_target(addr) {
+            PpuTarget::Chr(idx) => self.chr[idx],
+            PpuTarget::Ciram(idx) => ciram[idx],
+            PpuTarget::Nametables => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match self.ppu_target(addr) {
+            PpuTarget::Chr(idx) => {
+                if self.chr_ram {
+                    self.chr[idx] = data;
+                }
+            },
+            PpuTarget::Ciram(idx) => ciram[idx] = data,
+            PpuTarget::Nametables => self.nametables.write(addr, data, ciram),
+        }
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn take_expansion_audio(&mut self) -> Option<Box<dyn ExpansionAudio>> {
+        self.audio.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every byte of an 8K PRG bank and a 1K CHR bank holds the bank number
+    fn namco(mapper: u16, submapper: u8) -> Namco163 {
+        let cartridge = Cartridge {
+            mapper,
+            submapper,
+            prg_rom: (0..0x20000).map(|x| (x / 0x2000) as u8).collect(),
+            chr: (0..0x40000).map(|x| (x / 0x400) as u8).collect(),
+            chr_ram: false,
+            prg_ram: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        };
+        Namco163::new(Model::from_mapper(mapper, submapper).unwrap(), cartridge)
+    }
+
+    #[test]
+    fn irq_counts_up_to_7fff() {
+        let mut n163 = namco(19, 0);
+        n163.cpu_write(0x5000, 0xFD);
+        n163.cpu_write(0x5800, 0xFF);
+        assert_eq!(n163.cpu_peek(0x5800), Some(0xFF));
+        n163.clock_cpu();
+        assert!(!n163.irq_line());
+        n163.clock_cpu();
+        assert!(n163.irq_line());
+        // stopped, until a write acknowledges it
+        n163.clock_cpu();
+        assert_eq!(n163.cpu_peek(0x5000), Some(0xFF));
+        n163.cpu_write(0x5000, 0x00);
+        assert!(!n163.irq_line());
+    }
+
+    #[test]
+    fn prg_ram_needs_the_write_protect_key() {
+        let mut n163 = namco(19, 0);
+        n163.cpu_write(0x6000, 0x11);
+        assert_eq!(n163.cpu_peek(0x6000), Some(0x00));
+        // key $4, $6800-$6FFF protected
+        n163.cpu_write(0xF800, 0x42);
+        n163.cpu_write(0x6000, 0x11);
+        n163.cpu_write(0x6800, 0x22);
+        assert_eq!(n163.cpu_peek(0x6000), Some(0x11));
+        assert_eq!(n163.cpu_peek(0x6800), Some(0x00));
+    }
+
+    #[test]
+    fn banks_from_e0_select_ciram() {
+        let mut ciram = [0; CIRAM_SIZE];
+        let mut n163 = namco(19, 0);
+        n163.cpu_write(0x8000, 0xE1);
+        n163.ppu_write(0x0000, 0x33, &mut ciram);
+        assert_eq!(ciram[0x400], 0x33);
+        // once $0000-$0FFF can't use CIRAM the bank is CHR ROM again
+        n163.cpu_write(0xE800, 0x40);
+        assert_eq!(n163.ppu_peek(0x0000, &ciram), 0xE1);
+        // nametables from CHR ROM
+        n163.cpu_write(0xC800, 0x05);
+        assert_eq!(n163.ppu_peek(0x2400, &ciram), 0x05);
+        assert_eq!(n163.ppu_peek(0x2800, &ciram), 0x00);
+    }
+
+    #[test]
+    fn prg_banks_and_the_fixed_last_bank() {
+        let mut n163 = namco(19, 0);
+        n163.cpu_write(0xE000, 0x47);
+        n163.cpu_write(0xE800, 0x08);
+        n163.cpu_write(0xF000, 0x09);
+        assert_eq!(n163.cpu_peek(0x8000), Some(7));
+        assert_eq!(n163.cpu_peek(0xA000), Some(8));
+        assert_eq!(n163.cpu_peek(0xC000), Some(9));
+        assert_eq!(n163.cpu_peek(0xE000), Some(15));
+    }
+
+    #[test]
+    fn n175_enables_ram_and_n340_mirrors() {
+        let mut ciram = [0; CIRAM_SIZE];
+        let mut n175 = namco(210, 1);
+        assert_eq!(n175.cpu_peek(0x6000), None);
+        n175.cpu_write(0xC000, 0x01);
+        n175.cpu_write(0x6000, 0x44);
+        assert_eq!(n175.cpu_peek(0x6000), Some(0x44));
+        let mut n340 = namco(210, 2);
+        assert_eq!(n340.cpu_peek(0x6000), None);
+        n340.cpu_write(0xE000, 0xC0);
+        n340.ppu_write(0x2000, 0x55, &mut ciram);
+        assert_eq!(n340.ppu_peek(0x2400, &ciram), 0x55);
+        assert_eq!(n340.ppu_peek(0x2800, &ciram), 0x00);
+    }
+}
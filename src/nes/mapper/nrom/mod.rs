@@ -0,0 +1,85 @@
+// NROM (mapper 0), no bank switching
+//
+// $6000-$7FFF 8K PRG RAM (Family Basic, test ROMs report through it)
+// $8000-$BFFF first 16K of PRG ROM
+// $C000-$FFFF last 16K of PRG ROM, a mirror of $8000 on NROM-128
+// PPU $0000-$1FFF 8K CHR ROM, or CHR RAM
+//
+// NROM-368 is a homebrew variant with 48K of PRG ROM mapped at $4000-$FFFF. $4000-$47FF
+// stays hidden behind the APU and I/O registers, so PRG RAM is gone and the ROM is visible
+// from $4800.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+}
+
+impl Nrom {
+    pub fn new(cartridge: Cartridge) -> Nrom {
+        Nrom {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(cartridge.mirroring),
+        }
+    }
+
+    fn is_nrom_368(&self) -> bool {
+        self.prg_rom.len() > 0x8000
+    }
+}
+
+impl Mapper for Nrom {
+    fn get_name(&self) -> &'static str {
+        if self.is_nrom_368() { "NROM-368" } else { "NROM" }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        if self.prg_rom.is_empty() {
+            return None;
+        }
+        let len = self.prg_rom.len();
+        match addr {
+            0x4800..=0xFFFF if self.is_nrom_368() => {
+                Some(self.prg_rom[(addr as usize - (0x10000 - len)) % len])
+            },
+            0x6000..=0x7FFF => Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0xFFFF => Some(self.prg_rom[(addr as usize - 0x8000) % len]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if !self.is_nrom_368() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = data;
+            }
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[addr as usize % self.chr.len()],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    let len = self.chr.len();
+                    self.chr[addr as usize % len] = data;
+                }
+            },
+            _ => self.nametables.write(addr, data, ciram),
+        }
+    }
+}
@@ -0,0 +1,377 @@
+// Konami VRC2 and VRC4 (mappers 21, 22, 23 and 25)
+//
+// Registers sit at $8000-$F000 in steps of $1000, each with 4 sub registers picked by two CPU
+// address lines that depend on the board:
+//
+// Mapper  Submapper  Chip   A0 line  A1 line
+// 21      1          VRC4a  A1       A2
+// 21      2          VRC4c  A6       A7
+// 22      0          VRC2a  A1       A0
+// 23      1          VRC4f  A0       A1
+// 23      2          VRC4e  A2       A3
+// 23      3          VRC2b  A0       A1
+// 25      1          VRC4b  A1       A0
+// 25      2          VRC4d  A3       A2
+// 25      3          VRC2c  A1       A0
+//
+// iNES 1.0 files don't say which, both lines of a mapper are ORed together (the games only
+// ever write one of them) and VRC4 is assumed for 21, 23 and 25.
+//
+// $8000-$8003 ---P PPPP  8K PRG bank at $8000 (or $C000 in swap mode)
+// $9000-$9001 ---- --MM  mirroring: vertical, horizontal, one screen lower, upper (VRC2: 1 bit)
+// $9002-$9003 ---- --S-  VRC4: PRG swap mode
+// $A000-$A003 ---P PPPP  8K PRG bank at $A000
+// $B000-$E003 1K CHR banks 0-7, each written as a low nibble (even) and a high part (odd):
+//             $B000/$B001 bank 0, $B002/$B003 bank 1, $C000-$C003 banks 2-3, ...
+//             VRC2a drops the lowest bank bit
+// $F000/$F001 VRC4: IRQ latch, low and high nibble
+// $F002       ---- -MEA  VRC4: IRQ control, M: cycle mode, E: enable, A: enable after acknowledge
+// $F003       VRC4: IRQ acknowledge
+//
+// PRG: $8000 bank 0, $A000 bank 1, $C000 second to last, $E000 last; swap mode exchanges
+// $8000 and $C000.
+//
+// The IRQ counter counts up and reloads from the latch with an IRQ when passing $FF. In
+// scanline mode a prescaler divides the CPU clock by 113.667 (341 / 3), in cycle mode every
+// CPU cycle counts.
+//
+// VRC2 boards without PRG RAM have a 1 bit latch at $6000-$6FFF, meant for a microwire EEPROM
+// that was never fitted; games use it as a copy protection check.
+
+use super::{Cartridge, Mapper, Nametables, CIRAM_SIZE};
+use crate::nes::ppu::Mirroring;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Variant {
+    vrc2: bool,
+    // address bits selecting the sub register, ORed
+    a0: u16,
+    a1: u16,
+    // VRC2a: CHR banks in 2K steps
+    chr_shift: bool,
+}
+
+impl Variant {
+    pub fn new(mapper: u16, submapper: u8) -> Option<Variant> {
+        let variant = |vrc2, a0, a1| Some(Variant { vrc2, a0, a1, chr_shift: false });
+        match (mapper, submapper) {
+            (21, 1) => variant(false, 0x02, 0x04),
+            (21, 2) => variant(false, 0x40, 0x80),
+            (21, _) => variant(false, 0x42, 0x84),
+            (22, _) => Some(Variant { vrc2: true, a0: 0x02, a1: 0x01, chr_shift: true }),
+            (23, 1) => variant(false, 0x01, 0x02),
+            (23, 2) => variant(false, 0x04, 0x08),
+            (23, 3) => variant(true, 0x01, 0x02),
+            (23, _) => variant(false, 0x05, 0x0A),
+            (25, 1) => variant(false, 0x02, 0x01),
+            (25, 2) => variant(false, 0x08, 0x04),
+            (25, 3) => variant(true, 0x02, 0x01),
+            (25, _) => variant(false, 0x0A, 0x05),
+            _ => None,
+        }
+    }
+
+    // register address as $x000-$x003
+    fn translate(&self, addr: u16) -> u16 {
+        let mut register = addr & 0xF000;
+        if addr & self.a0 > 0 {
+            register |= 0x01;
+        }
+        if addr & self.a1 > 0 {
+            register |= 0x02;
+        }
+        register
+    }
+}
+
+pub struct Vrc4 {
+    variant: Variant,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    nametables: Nametables,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    microwire: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_control: u8,
+    irq_pending: bool,
+}
+
+impl Vrc4 {
+    pub fn new(variant: Variant, cartridge: Cartridge) -> Vrc4 {
+        // VRC2 boards only get RAM when the header says it is battery backed
+        let prg_ram = if variant.vrc2 && !cartridge.battery { vec![] } else { cartridge.prg_ram };
+        Vrc4 {
+            variant,
+            prg_rom: cartridge.prg_rom,
+            prg_ram,
+            chr: cartridge.chr,
+            chr_ram: cartridge.chr_ram,
+            nametables: Nametables::new(cartridge.mirroring),
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            microwire: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: 341,
+            irq_control: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / 0x2000).max(2);
+        let second_last = banks - 2;
+        let bank = match (addr >> 13) & 0x03 {
+            0 => if self.prg_swap { second_last } else { self.prg_banks[0] as usize },
+            1 => self.prg_banks[1] as usize,
+            2 => if self.prg_swap { self.prg_banks[0] as usize } else { second_last },
+            _ => banks - 1,
+        };
+        (bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let mut bank = self.chr_banks[(addr >> 10) as usize & 0x07] as usize;
+        if self.variant.chr_shift {
+            bank >>= 1;
+        }
+        (bank * 0x400 + (addr as usize & 0x3FF)) % self.chr.len()
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x1F,
+            0x9000..=0x9001 => {
+                let mirroring = if self.variant.vrc2 { data & 0x01 } else { data & 0x03 };
+                self.nametables.set_mirroring(match mirroring {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                });
+            },
+            0x9002..=0x9003 if !self.variant.vrc2 => {
+                self.prg_swap = data & 0x02 > 0;
+            },
+            0xA000..=0xA003 => self.prg_banks[1] = data & 0x1F,
+            0xB000..=0xE003 => {
+                let bank = (((register - 0xB000) >> 12) * 2 + ((register >> 1) & 0x01)) as usize;
+                let value = data as u16;
+                self.chr_banks[bank] = if register & 0x01 == 0 {
+                    (self.chr_banks[bank] & 0x1F0) | (value & 0x0F)
+                } else {
+                    (self.chr_banks[bank] & 0x0F) | ((value & 0x1F) << 4)
+                };
+            },
+            0xF000 if !self.variant.vrc2 => self.irq_latch = (self.irq_latch & 0xF0) | (data & 0x0F),
+            0xF001 if !self.variant.vrc2 => self.irq_latch = (self.irq_latch & 0x0F) | (data << 4),
+            0xF002 if !self.variant.vrc2 => {
+                self.irq_control = data & 0x07;
+                if data & 0x02 > 0 {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = 341;
+                }
+                self.irq_pending = false;
+            },
+            0xF003 if !self.variant.vrc2 => {
+                self.irq_pending = false;
+                // enable after acknowledge moves into enable
+                let enable = (self.irq_control & 0x01) << 1;
+                self.irq_control = (self.irq_control & 0x05) | enable;
+            },
+            _ => {}
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn get_name(&self) -> &'static str {
+        if self.variant.vrc2 { "VRC2" } else { "VRC4" }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+            },
+            // the other bits are open bus, the high byte of the address on absolute reads
+            0x6000..=0x6FFF if self.variant.vrc2 => Some(((addr >> 8) as u8 & 0xFE) | self.microwire),
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = data;
+            },
+            0x6000..=0x6FFF if self.variant.vrc2 => self.microwire = data & 0x01,
+            0x8000..=0xFFFF => {
+                let register = self.variant.translate(addr);
+                self.write_register(register, data);
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16, ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_index(addr)],
+            _ => self.nametables.read(addr, ciram),
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8, ciram: &mut [u8; CIRAM_SIZE]) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    let idx = self.chr_index(addr);
+                    self.chr[idx] = data;
+                }
+            },
+            _ => self.nametables.write(addr, data, ciram),
+        }
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_control & 0x02 == 0 {
+            return;
+        }
+        if self.irq_control & 0x04 > 0 {
+            self.clock_irq_counter();
+        } else {
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += 341;
+                self.clock_irq_counter();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every byte of an 8K PRG bank and a 1K CHR bank holds the bank number
+    fn vrc4(mapper: u16, submapper: u8, battery: bool) -> Vrc4 {
+        let cartridge = Cartridge {
+            mapper,
+            submapper,
+            prg_rom: (0..0x20000).map(|x| (x / 0x2000) as u8).collect(),
+            chr: (0..0x40000).map(|x| (x / 0x400) as u8).collect(),
+            chr_ram: false,
+            prg_ram: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical,
+            battery,
+        };
+        Vrc4::new(Variant::new(mapper, submapper).unwrap(), cartridge)
+    }
+
+    #[test]
+    fn address_lines_pick_the_sub_register() {
+        assert_eq!(Variant::new(21, 2).unwrap().translate(0xB0C0), 0xB003);
+        assert_eq!(Variant::new(23, 2).unwrap().translate(0xC004), 0xC001);
+        assert_eq!(Variant::new(25, 1).unwrap().translate(0xD001), 0xD002);
+        // iNES 1.0 ORs both candidates
+        assert_eq!(Variant::new(23, 0).unwrap().translate(0xE008), 0xE002);
+        assert_eq!(Variant::new(23, 0).unwrap().translate(0xE001), 0xE001);
+    }
+
+    #[test]
+    fn swap_mode_exchanges_8000_and_c000() {
+        let mut vrc = vrc4(23, 1, false);
+        vrc.cpu_write(0x8000, 0x03);
+        vrc.cpu_write(0xA000, 0x04);
+        assert_eq!(vrc.cpu_peek(0x8000), Some(3));
+        assert_eq!(vrc.cpu_peek(0xA000), Some(4));
+        assert_eq!(vrc.cpu_peek(0xC000), Some(14));
+        assert_eq!(vrc.cpu_peek(0xE000), Some(15));
+        vrc.cpu_write(0x9002, 0x02);
+        assert_eq!(vrc.cpu_peek(0x8000), Some(14));
+        assert_eq!(vrc.cpu_peek(0xC000), Some(3));
+    }
+
+    #[test]
+    fn chr_banks_are_written_in_nibbles() {
+        let ciram = [0; CIRAM_SIZE];
+        let mut vrc = vrc4(23, 1, false);
+        vrc.cpu_write(0xC002, 0x05);
+        vrc.cpu_write(0xC003, 0x01);
+        assert_eq!(vrc.ppu_peek(0x0C00, &ciram), 0x15);
+        // VRC2a ignores the lowest bank bit
+        let mut vrc2a = vrc4(22, 0, false);
+        vrc2a.cpu_write(0xB000, 0x05);
+        assert_eq!(vrc2a.ppu_peek(0x0000, &ciram), 0x02);
+    }
+
+    #[test]
+    fn cycle_mode_irq_reloads_from_the_latch() {
+        let mut vrc = vrc4(23, 1, false);
+        vrc.cpu_write(0xF000, 0x0D);
+        vrc.cpu_write(0xF001, 0x0F);
+        vrc.cpu_write(0xF002, 0x07);
+        vrc.clock_cpu();
+        vrc.clock_cpu();
+        assert!(!vrc.irq_line());
+        vrc.clock_cpu();
+        assert!(vrc.irq_line());
+        // acknowledge copies the A bit into enable
+        vrc.cpu_write(0xF003, 0x00);
+        assert!(!vrc.irq_line());
+        vrc.clock_cpu();
+        vrc.clock_cpu();
+        vrc.clock_cpu();
+        assert!(vrc.irq_line());
+    }
+
+    #[test]
+    fn scanline_mode_irq_counts_every_341_thirds() {
+        // VRC4a: sub registers on A1 and A2
+        let mut vrc = vrc4(21, 1, false);
+        vrc.cpu_write(0xF000, 0x0F);
+        vrc.cpu_write(0xF002, 0x0F);
+        vrc.cpu_write(0xF004, 0x02);
+        for _ in 0..113 {
+            vrc.clock_cpu();
+        }
+        assert!(!vrc.irq_line());
+        vrc.clock_cpu();
+        assert!(vrc.irq_line());
+    }
+
+    #[test]
+    fn vrc2_without_ram_latches_one_bit() {
+        let mut vrc2 = vrc4(23, 3, false);
+        vrc2.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc2.cpu_peek(0x6000), Some(0x61));
+        vrc2.cpu_write(0x6000, 0x00);
+        assert_eq!(vrc2.cpu_peek(0x6100), Some(0x60));
+        assert_eq!(vrc2.cpu_peek(0x7000), None);
+        let mut battery = vrc4(23, 3, true);
+        battery.cpu_write(0x7000, 0x42);
+        assert_eq!(battery.cpu_peek(0x7000), Some(0x42));
+    }
+}
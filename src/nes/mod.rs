@@ -1,3 +1,11 @@
+pub mod audio;
+pub mod console;
+pub mod controller;
 pub mod cpu;
+pub mod digest;
 pub mod loader;
-pub mod rom;
\ No newline at end of file
+pub mod mapper;
+pub mod nsf;
+pub mod ppu;
+pub mod rom;
+pub mod testsuite;
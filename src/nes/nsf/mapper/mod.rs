@@ -0,0 +1,144 @@
+// Board of the NSF player
+//
+// $5FF8-$5FFF bank registers, write the 4K bank number shown in $8000 + n * $1000
+// $6000-$7FFF 8K RAM
+// $8000-$FFFF PRG in eight 4K windows
+// $FFFE-$FFFF become writable for NSF2 tunes using IRQs, the tune points the vector at RAM
+//
+// Tunes without bankswitch init values are loaded as a whole at the load address, which is
+// the same as banks 0-7 of an image starting at $8000. Banked tunes are padded in front by the
+// low 12 bits of the load address, bank n starts at n * $1000 of the padded data.
+//
+// Tunes for the FDS run from the RAM adapter instead:
+//
+// $5FF6-$5FF7 two more bank registers, for $6000 and $7000
+// $6000-$DFFF 40K RAM, a bank register write copies the 4K bank into its window
+// $E000-$FFFF PRG in two 4K windows
+//
+// Their image starts at $6000 when they aren't banked, so load addresses below $8000 work.
+// $5FF6/$5FF7 start out with the init values of $5FFE/$5FFF.
+
+use crate::nes::mapper::{Mapper, CIRAM_SIZE};
+use crate::nes::nsf::{Nsf, BANK_SIZE, EXPANSION_FDS};
+
+pub const BANK_REGISTERS: u16 = 0x5FF6;
+pub const BANK_REGISTER_COUNT: usize = 10;
+
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    // windows $6000-$FFFF, the first two are only used by FDS tunes
+    banks: [u8; BANK_REGISTER_COUNT],
+    banked: bool,
+    fds: bool,
+    ram: Vec<u8>,
+    irq_vector: Option<[u8; 2]>,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> NsfMapper {
+        let fds = nsf.get_expansion() & EXPANSION_FDS > 0;
+        let base = if fds { 0x6000 } else { 0x8000 };
+        let load_address = nsf.get_load_address() as usize;
+        let padding = if nsf.is_banked() { load_address & 0x0FFF } else { load_address.saturating_sub(base) };
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(nsf.get_data());
+        let banks = prg.len().div_ceil(BANK_SIZE);
+        prg.resize(banks.max(BANK_REGISTER_COUNT) * BANK_SIZE, 0);
+        let mut mapper = NsfMapper {
+            prg,
+            banks: [0; BANK_REGISTER_COUNT],
+            banked: nsf.is_banked(),
+            fds,
+            ram: vec![0; if fds { 0x8000 } else { 0x2000 }],
+            irq_vector: None,
+        };
+        for (idx, bank) in Self::get_initial_banks(nsf).iter().enumerate() {
+            mapper.set_bank(idx, *bank);
+        }
+        mapper
+    }
+
+    // values of $5FF6-$5FFF when a song starts
+    pub fn get_initial_banks(nsf: &Nsf) -> [u8; BANK_REGISTER_COUNT] {
+        let mut banks = [0; BANK_REGISTER_COUNT];
+        if nsf.is_banked() {
+            let init = nsf.get_bankswitch_init();
+            banks[2..].copy_from_slice(&init);
+            banks[0] = init[6];
+            banks[1] = init[7];
+        } else if nsf.get_expansion() & EXPANSION_FDS > 0 {
+            banks.iter_mut().enumerate().for_each(|(idx, bank)| *bank = idx as u8);
+        } else {
+            banks[2..].iter_mut().enumerate().for_each(|(idx, bank)| *bank = idx as u8);
+        }
+        banks
+    }
+
+    // IRQ vector writes are kept instead of being ignored
+    pub fn set_irq_vector_writable(&mut self, writable: bool) {
+        self.irq_vector = if writable { Some([0; 2]) } else { None };
+    }
+
+    fn bank_count(&self) -> usize {
+        self.prg.len() / BANK_SIZE
+    }
+
+    fn bank_offset(&self, bank: u8) -> usize {
+        (bank as usize % self.bank_count()) * BANK_SIZE
+    }
+
+    fn set_bank(&mut self, window: usize, bank: u8) {
+        self.banks[window] = bank;
+        // the RAM windows of the FDS are loaded, not mapped
+        if self.fds && window < 8 {
+            let offset = self.bank_offset(bank);
+            self.ram[window * BANK_SIZE..(window + 1) * BANK_SIZE]
+                .copy_from_slice(&self.prg[offset..offset + BANK_SIZE]);
+        }
+    }
+
+    fn ram_end(&self) -> u16 {
+        0x6000 + self.ram.len() as u16 - 1
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn get_name(&self) -> &'static str {
+        "NSF"
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0xFFFF if addr <= self.ram_end() => Some(self.ram[addr as usize - 0x6000]),
+            0xFFFE..=0xFFFF if self.irq_vector.is_some() => {
+                self.irq_vector.map(|x| x[addr as usize - 0xFFFE])
+            },
+            0x8000..=0xFFFF => {
+                let window = (addr as usize - 0x6000) / BANK_SIZE;
+                Some(self.prg[self.bank_offset(self.banks[window]) + (addr as usize & 0x0FFF)])
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5FF6..=0x5FF7 if self.fds => self.set_bank((addr - BANK_REGISTERS) as usize, data),
+            0x5FF8..=0x5FFF if self.banked || self.fds => self.set_bank((addr - BANK_REGISTERS) as usize, data),
+            0x6000..=0xFFFF if addr <= self.ram_end() => self.ram[addr as usize - 0x6000] = data,
+            0xFFFE..=0xFFFF => {
+                if let Some(vector) = self.irq_vector.as_mut() {
+                    vector[addr as usize - 0xFFFE] = data;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // there is no PPU
+    fn ppu_peek(&self, _addr: u16, _ciram: &[u8; CIRAM_SIZE]) -> u8 {
+        0x00
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8, _ciram: &mut [u8; CIRAM_SIZE]) {}
+}
@@ -0,0 +1,294 @@
+// NSF (NES Sound Format) files
+//
+// Header (128 bytes):
+//
+// $00-$04 "NESM" $1A
+// $05     version
+// $06     total songs
+// $07     starting song (1 based)
+// $08-$09 load address (little endian, $8000-$FFFF, from $6000 for FDS tunes)
+// $0A-$0B init address
+// $0C-$0D play address
+// $0E-$2D song name, $2E-$4D artist, $4E-$6D copyright (NUL padded strings)
+// $6E-$6F NTSC play speed, in 1/1000000 seconds
+// $70-$77 bankswitch init values for $5FF8-$5FFF, all 0 means the tune is not banked
+// $78-$79 PAL play speed
+// $7A     bit 0: PAL, bit 1: dual PAL/NTSC
+// $7B     expansion chips: bit 0 VRC6, 1 VRC7, 2 FDS, 3 MMC5, 4 Namco 163, 5 Sunsoft 5B
+// $7C     NSF2 flags: bit 4 IRQ support, bit 5 non-returning INIT, bit 6 no PLAY,
+//         bit 7 metadata is mandatory, the tune can't be played without reading it
+// $7D-$7F NSF2 program data length (24 bit), 0 = up to the end of the file
+//
+// NSF2 (version 2) may append NSFe style chunks after the program data. NSFe files
+// ("NSFE" magic) carry the whole header as chunks instead, see nsfe.
+//
+// The program data follows the header. Without banking it is loaded at the load address.
+// With banking the data is split in 4K banks, the first bank being padded in front by the
+// low 12 bits of the load address, and $5FF8-$5FFF select the bank seen at $8000-$FFFF
+// (one 4K window each).
+
+pub mod mapper;
+pub mod nsfe;
+pub mod player;
+
+use self::nsfe::{Chunk, Metadata};
+use crate::nes::console::System;
+
+pub const HEADER_SIZE: usize = 0x80;
+pub const BANK_SIZE: usize = 0x1000;
+
+pub const EXPANSION_VRC6: u8 = 0x01;
+pub const EXPANSION_VRC7: u8 = 0x02;
+pub const EXPANSION_FDS: u8 = 0x04;
+pub const EXPANSION_MMC5: u8 = 0x08;
+pub const EXPANSION_N163: u8 = 0x10;
+pub const EXPANSION_5B: u8 = 0x20;
+
+// used when the metadata has no track time, or a time without a fade (milliseconds)
+pub const DEFAULT_TRACK_TIME: u32 = 120000;
+pub const DEFAULT_FADE: u32 = 5000;
+
+pub const FLAG_IRQ: u8 = 0x10;
+pub const FLAG_NON_RETURNING_INIT: u8 = 0x20;
+pub const FLAG_NO_PLAY: u8 = 0x40;
+pub const FLAG_MANDATORY_METADATA: u8 = 0x80;
+
+pub struct Nsf {
+    total_songs: u8,
+    starting_song: u8,
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+    name: String,
+    artist: String,
+    copyright: String,
+    ntsc_speed: u16,
+    pal_speed: u16,
+    bankswitch_init: [u8; 8],
+    region: u8,
+    expansion: u8,
+    flags: u8,
+    data: Vec<u8>,
+    metadata: Metadata,
+}
+
+impl Nsf {
+    // NSF, NSF2 or NSFe
+    pub fn new(data: &[u8]) -> Result<Nsf, String> {
+        if data.starts_with(b"NSFE") {
+            return Self::from_nsfe(&data[4..]);
+        }
+        if data.len() < HEADER_SIZE || &data[0..5] != b"NESM\x1A" {
+            return Err(String::from("not an NSF file"));
+        }
+        let word = |idx: usize| data[idx] as u16 | (data[idx + 1] as u16) << 8;
+        let mut bankswitch_init = [0; 8];
+        bankswitch_init.copy_from_slice(&data[0x70..0x78]);
+        let version = data[0x05];
+        let program_length = data[0x7D] as usize | (data[0x7E] as usize) << 8 | (data[0x7F] as usize) << 16;
+        let program_end = if version >= 2 && program_length > 0 {
+            (HEADER_SIZE + program_length).min(data.len())
+        } else {
+            data.len()
+        };
+        let mut nsf = Nsf {
+            total_songs: data[0x06],
+            starting_song: data[0x07].max(1),
+            load_address: word(0x08),
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            name: Self::read_string(&data[0x0E..0x2E]),
+            artist: Self::read_string(&data[0x2E..0x4E]),
+            copyright: Self::read_string(&data[0x4E..0x6E]),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            bankswitch_init,
+            region: data[0x7A],
+            expansion: data[0x7B],
+            flags: if version >= 2 { data[0x7C] } else { 0 },
+            data: data[HEADER_SIZE..program_end].to_vec(),
+            metadata: Metadata::default(),
+        };
+        // metadata a player can't read is skipped, unless the flags say the tune needs it
+        let metadata = nsfe::read_chunks(&data[program_end..])
+            .and_then(|chunks| chunks.iter().try_for_each(|x| nsf.read_chunk(x)));
+        if nsf.flags & FLAG_MANDATORY_METADATA > 0 {
+            metadata?;
+        }
+        // FDS tunes can load into the RAM from $6000
+        let lowest = if nsf.expansion & EXPANSION_FDS > 0 { 0x6000 } else { 0x8000 };
+        if !nsf.is_banked() && nsf.load_address < lowest {
+            return Err(format!("load address {:#06X} below ${:04X}", nsf.load_address, lowest));
+        }
+        Ok(nsf)
+    }
+
+    fn from_nsfe(data: &[u8]) -> Result<Nsf, String> {
+        let chunks = nsfe::read_chunks(data)?;
+        let info = chunks.iter().find(|x| &x.id == b"INFO").ok_or("NSFe without INFO chunk")?.data;
+        if info.len() < 8 {
+            return Err(String::from("NSFe INFO chunk too short"));
+        }
+        let word = |idx: usize| info[idx] as u16 | (info[idx + 1] as u16) << 8;
+        let starting_song = info.get(9).cloned().unwrap_or(0).checked_add(1).ok_or("NSFe starting song out of range")?;
+        let mut nsf = Nsf {
+            total_songs: info.get(8).cloned().unwrap_or(1),
+            starting_song,
+            load_address: word(0),
+            init_address: word(2),
+            play_address: word(4),
+            name: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ntsc_speed: 0,
+            pal_speed: 0,
+            bankswitch_init: [0; 8],
+            region: info[6],
+            expansion: info[7],
+            flags: 0,
+            data: vec![],
+            metadata: Metadata::default(),
+        };
+        for chunk in chunks.iter() {
+            match &chunk.id {
+                b"INFO" => {},
+                b"DATA" => nsf.data = chunk.data.to_vec(),
+                b"BANK" => {
+                    for (idx, bank) in chunk.data.iter().take(8).enumerate() {
+                        nsf.bankswitch_init[idx] = *bank;
+                    }
+                },
+                b"RATE" => {
+                    let speed = |idx: usize| chunk.data.get(idx..idx + 2).map(|x| x[0] as u16 | (x[1] as u16) << 8);
+                    nsf.ntsc_speed = speed(0).unwrap_or(0);
+                    nsf.pal_speed = speed(2).unwrap_or(0);
+                },
+                _ => nsf.read_chunk(chunk)?,
+            }
+        }
+        if nsf.data.is_empty() {
+            return Err(String::from("NSFe without DATA chunk"));
+        }
+        Ok(nsf)
+    }
+
+    // metadata chunk of an NSFe or NSF2 file
+    fn read_chunk(&mut self, chunk: &Chunk) -> Result<(), String> {
+        if &chunk.id == b"auth" {
+            let mut strings = nsfe::read_strings(chunk.data).into_iter();
+            self.name = strings.next().unwrap_or_default();
+            self.artist = strings.next().unwrap_or_default();
+            self.copyright = strings.next().unwrap_or_default();
+            self.metadata.ripper = strings.next();
+        } else if !self.metadata.read_chunk(chunk) && chunk.is_mandatory() {
+            return Err(format!("unsupported mandatory chunk {}", chunk.id_as_str()));
+        }
+        Ok(())
+    }
+
+    fn read_string(bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|x| *x == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+
+    pub fn get_total_songs(&self) -> u8 {
+        self.total_songs
+    }
+
+    // 1 based
+    pub fn get_starting_song(&self) -> u8 {
+        self.starting_song
+    }
+
+    pub fn get_load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    pub fn get_init_address(&self) -> u16 {
+        self.init_address
+    }
+
+    pub fn get_play_address(&self) -> u16 {
+        self.play_address
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_artist(&self) -> &str {
+        &self.artist
+    }
+
+    pub fn get_copyright(&self) -> &str {
+        &self.copyright
+    }
+
+    pub fn get_bankswitch_init(&self) -> [u8; 8] {
+        self.bankswitch_init
+    }
+
+    pub fn is_banked(&self) -> bool {
+        self.bankswitch_init.iter().any(|x| *x != 0)
+    }
+
+    pub fn get_expansion(&self) -> u8 {
+        self.expansion
+    }
+
+    pub fn get_flags(&self) -> u8 {
+        self.flags
+    }
+
+    // `song` is 1 based
+    pub fn get_track_author(&self, song: u8) -> Option<&str> {
+        self.metadata.get_track_author((song as usize).saturating_sub(1))
+    }
+
+    // `song` is 1 based, falls back to the tune name
+    pub fn get_track_title(&self, song: u8) -> &str {
+        self.metadata.get_track_title((song as usize).saturating_sub(1)).unwrap_or(&self.name)
+    }
+
+    // time and fade in milliseconds, when the metadata knows the time
+    pub fn get_track_length(&self, song: u8) -> Option<(u32, u32)> {
+        let track = (song as usize).saturating_sub(1);
+        let time = self.metadata.get_track_time(track)?;
+        Some((time, self.metadata.get_track_fade(track).unwrap_or(DEFAULT_FADE)))
+    }
+
+    // 1 based song numbers in play order, the NSFe playlist when there is one, entries past the
+    // last song are left out
+    pub fn get_playlist(&self) -> Vec<u8> {
+        match &self.metadata.playlist {
+            Some(playlist) => playlist.iter().filter(|x| **x < self.total_songs).map(|x| x + 1).collect(),
+            None => (1..=self.total_songs).collect(),
+        }
+    }
+
+    pub fn get_data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn supports(&self, system: System) -> bool {
+        match system {
+            System::Ntsc => self.region & 0x03 != 0x01,
+            System::Pal => self.region & 0x03 != 0x00,
+        }
+    }
+
+    // NTSC unless the tune is PAL only
+    pub fn get_preferred_system(&self) -> System {
+        if self.supports(System::Ntsc) { System::Ntsc } else { System::Pal }
+    }
+
+    // PLAY period in microseconds, with the usual rates when the header leaves it at 0
+    pub fn get_play_speed(&self, system: System) -> u16 {
+        match system {
+            System::Ntsc if self.ntsc_speed == 0 => 16639,
+            System::Ntsc => self.ntsc_speed,
+            System::Pal if self.pal_speed == 0 => 19997,
+            System::Pal => self.pal_speed,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// NSFe chunks, used by .nsfe files and by the metadata block of NSF2 files
+//
+// Every chunk is <length: u32 little endian> <id: 4 chars> <length bytes of data>.
+// A chunk id starting with an upper case letter is mandatory: a player that does not know
+// it must refuse the file. Lower case ones can be skipped.
+//
+// INFO  load, init, play (u16 each), region, expansion, total songs, starting song (0 based)
+// DATA  program data
+// BANK  bankswitch init values, up to 8
+// RATE  NTSC, PAL (and Dendy) play speed, u16 each
+// NEND  end of the file
+// auth  game title, artist, copyright, ripper (NUL terminated strings)
+// tlbl  one NUL terminated title per track
+// taut  one NUL terminated author per track
+// time  one i32 per track, length in milliseconds, negative = unknown
+// fade  one i32 per track, fade out in milliseconds, negative = player default
+// plst  playlist, one track number (0 based) per byte
+// psfx  tracks that are sound effects, one track number per byte
+// text  free form NUL terminated text
+
+pub struct Chunk<'a> {
+    pub id: [u8; 4],
+    pub data: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    pub fn is_mandatory(&self) -> bool {
+        self.id[0].is_ascii_uppercase()
+    }
+
+    pub fn id_as_str(&self) -> String {
+        self.id.iter().map(|x| *x as char).collect()
+    }
+}
+
+// Splits `data` into chunks, stops at NEND or at the end of the data.
+pub fn read_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    let mut chunks = vec![];
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let length = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let id = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        pos += 8;
+        if &id == b"NEND" {
+            break;
+        }
+        if data.len() - pos < length {
+            return Err(format!("chunk {} truncated", id.iter().map(|x| *x as char).collect::<String>()));
+        }
+        chunks.push(Chunk { id, data: &data[pos..pos + length] });
+        pos += length;
+    }
+    Ok(chunks)
+}
+
+// NUL terminated strings, a missing final terminator is accepted
+pub fn read_strings(data: &[u8]) -> Vec<String> {
+    let mut strings = data.split(|x| *x == 0)
+        .map(|x| String::from_utf8_lossy(x).into_owned())
+        .collect::<Vec<String>>();
+    if data.last() == Some(&0) {
+        strings.pop();
+    }
+    strings
+}
+
+fn read_times(data: &[u8]) -> Vec<Option<u32>> {
+    data.chunks_exact(4)
+        .map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .map(|x| if x < 0 { None } else { Some(x as u32) })
+        .collect()
+}
+
+// Everything an NSF header has no room for
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    pub ripper: Option<String>,
+    pub track_titles: Vec<String>,
+    pub track_authors: Vec<String>,
+    // milliseconds
+    pub track_times: Vec<Option<u32>>,
+    pub track_fades: Vec<Option<u32>>,
+    pub playlist: Option<Vec<u8>>,
+    pub sound_effects: Vec<u8>,
+    pub text: Option<String>,
+}
+
+impl Metadata {
+    // takes the optional chunks it knows, returns false for the others
+    pub fn read_chunk(&mut self, chunk: &Chunk) -> bool {
+        match &chunk.id {
+            b"tlbl" => self.track_titles = read_strings(chunk.data),
+            b"taut" => self.track_authors = read_strings(chunk.data),
+            b"time" => self.track_times = read_times(chunk.data),
+            b"fade" => self.track_fades = read_times(chunk.data),
+            b"plst" => self.playlist = Some(chunk.data.to_vec()),
+            b"psfx" => self.sound_effects = chunk.data.to_vec(),
+            b"text" => self.text = read_strings(chunk.data).into_iter().next(),
+            _ => return false,
+        }
+        true
+    }
+
+    // `track` is 0 based
+    pub fn get_track_title(&self, track: usize) -> Option<&str> {
+        self.track_titles.get(track).map(|x| x.as_str()).filter(|x| !x.is_empty())
+    }
+
+    pub fn get_track_author(&self, track: usize) -> Option<&str> {
+        self.track_authors.get(track).map(|x| x.as_str()).filter(|x| !x.is_empty())
+    }
+
+    pub fn get_track_time(&self, track: usize) -> Option<u32> {
+        self.track_times.get(track).cloned().flatten()
+    }
+
+    pub fn get_track_fade(&self, track: usize) -> Option<u32> {
+        self.track_fades.get(track).cloned().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::nsf::Nsf;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn chunks_stop_at_nend() {
+        let mut data = chunk(b"INFO", &[1, 2, 3]);
+        data.extend(chunk(b"tlbl", b"a\0"));
+        data.extend(chunk(b"NEND", &[]));
+        data.extend(chunk(b"DATA", &[0xEA]));
+        let chunks = read_chunks(&data).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[0].id, b"INFO");
+        assert_eq!(chunks[0].data, &[1, 2, 3]);
+        assert!(chunks[0].is_mandatory());
+        assert_eq!(chunks[1].id_as_str(), "tlbl");
+        assert!(!chunks[1].is_mandatory());
+    }
+
+    #[test]
+    fn truncated_chunks_are_rejected() {
+        let mut data = chunk(b"DATA", &[0; 16]);
+        data.truncate(20);
+        assert!(read_chunks(&data).is_err());
+    }
+
+    #[test]
+    fn strings_accept_a_missing_terminator() {
+        assert_eq!(read_strings(b"one\0two\0"), vec!["one", "two"]);
+        assert_eq!(read_strings(b"one\0two"), vec!["one", "two"]);
+        assert_eq!(read_strings(b"\0two\0"), vec!["", "two"]);
+    }
+
+    #[test]
+    fn metadata_chunks() {
+        let mut metadata = Metadata::default();
+        let times = [1000i32, -1].iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>();
+        let chunks = [
+            (b"tlbl", b"Intro\0\0Boss\0".to_vec()),
+            (b"taut", b"Someone\0".to_vec()),
+            (b"time", times),
+            (b"plst", vec![2, 0]),
+        ];
+        for (id, data) in chunks.iter() {
+            assert!(metadata.read_chunk(&Chunk { id: **id, data }));
+        }
+        assert!(!metadata.read_chunk(&Chunk { id: *b"xyzw", data: &[] }));
+        assert_eq!(metadata.get_track_title(0), Some("Intro"));
+        assert_eq!(metadata.get_track_title(1), None);
+        assert_eq!(metadata.get_track_title(2), Some("Boss"));
+        assert_eq!(metadata.get_track_author(0), Some("Someone"));
+        assert_eq!(metadata.get_track_time(0), Some(1000));
+        assert_eq!(metadata.get_track_time(1), None);
+        assert_eq!(metadata.get_track_time(2), None);
+        assert_eq!(metadata.playlist, Some(vec![2, 0]));
+    }
+
+    fn nsfe(info: &[u8], extra: &[u8]) -> Vec<u8> {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(b"INFO", info));
+        data.extend(chunk(b"DATA", &[0x60]));
+        data.extend_from_slice(extra);
+        data.extend(chunk(b"NEND", &[]));
+        data
+    }
+
+    #[test]
+    fn nsfe_file() {
+        // load $8000, init $8000, play $8000, NTSC, no expansion, 3 songs, starting with song 2
+        let info = [0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x00, 0x03, 0x01];
+        let mut extra = chunk(b"auth", b"Game\0Artist\0Copyright\0Ripper\0");
+        extra.extend(chunk(b"plst", &[2, 7, 0]));
+        let nsf = Nsf::new(&nsfe(&info, &extra)).unwrap();
+        assert_eq!(nsf.get_total_songs(), 3);
+        assert_eq!(nsf.get_starting_song(), 2);
+        assert_eq!(nsf.get_load_address(), 0x8000);
+        assert_eq!(nsf.get_name(), "Game");
+        assert_eq!(nsf.get_artist(), "Artist");
+        assert_eq!(nsf.get_data(), &vec![0x60]);
+        // track 8 doesn't exist
+        assert_eq!(nsf.get_playlist(), vec![3, 1]);
+    }
+
+    #[test]
+    fn nsfe_file_errors() {
+        let info = [0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x00, 0x01, 0xFF];
+        assert!(Nsf::new(&nsfe(&info, &[])).is_err());
+        let info = [0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x00, 0x01, 0x00];
+        assert!(Nsf::new(&nsfe(&info, &chunk(b"ABCD", &[]))).is_err());
+        assert!(Nsf::new(&nsfe(&info, &chunk(b"abcd", &[]))).is_ok());
+    }
+}
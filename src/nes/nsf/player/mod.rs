@@ -0,0 +1,206 @@
+// NSF player driver
+//
+// Runs the CPU and the APU of the 2A03 without a PPU. Starting a song does what the NSF
+// spec asks of a player:
+//
+// - clear $0000-$07FF and $6000-$7FFF
+// - silence the APU: $4000-$4013 = 0, $4015 = 0 then $0F, $4017 = $40
+// - write the bankswitch init values to $5FF8-$5FFF for banked tunes, FDS tunes get
+//   $5FF6-$5FFF written either way, which reloads their RAM
+// - A = song number (0 based), X = 0 for NTSC / 1 for PAL, JSR INIT
+//
+// After INIT returns, PLAY is called every `play speed` microseconds. A call falling while
+// INIT or the previous PLAY is still running waits for it to return. Routines return to
+// RETURN_ADDRESS, where the CPU idles until the next call.
+//
+// NSF2 flags change this: with a non-returning INIT, PLAY interrupts INIT (between two
+// instructions) and returns into it. With PLAY suppressed it is never called. With IRQ
+// support the APU IRQ line reaches the CPU, the vector at $FFFE is RAM the tune sets up.
+//
+// Banking and RAM are done by the NSF board, see mapper.
+//
+// The expansion audio chips named in the header are plugged into the APU, they see every
+// write just like on a cartridge.
+
+use crate::nes::console::{System, DMC_DMA_CYCLES};
+use crate::nes::cpu::apu::Apu;
+use crate::nes::cpu::apu::expansion::ExpansionAudio;
+use crate::nes::cpu::apu::expansion::fds::FdsAudio;
+use crate::nes::cpu::apu::expansion::mmc5::Mmc5Audio;
+use crate::nes::cpu::apu::expansion::n163::N163Audio;
+use crate::nes::cpu::apu::expansion::sunsoft5b::Sunsoft5bAudio;
+use crate::nes::cpu::apu::expansion::vrc6::Vrc6;
+use crate::nes::cpu::apu::expansion::vrc7::Vrc7Audio;
+use crate::nes::cpu::processor::Processor;
+use crate::nes::nsf::mapper::{NsfMapper, BANK_REGISTERS};
+use crate::nes::nsf::{
+    Nsf, EXPANSION_5B, EXPANSION_FDS, EXPANSION_MMC5, EXPANSION_N163, EXPANSION_VRC6, EXPANSION_VRC7,
+    FLAG_IRQ, FLAG_NON_RETURNING_INIT, FLAG_NO_PLAY,
+};
+
+// RTS of INIT/PLAY lands here, never executed
+const RETURN_ADDRESS: u16 = 0x4100;
+
+type NewExpansion = fn(System) -> Box<dyn ExpansionAudio>;
+
+pub struct Player {
+    nsf: Nsf,
+    processor: Processor,
+    system: System,
+    song: u8,
+    cycle: usize,
+    play_period: f64,
+    next_play: f64,
+    play_pending: bool,
+    // return address of the running PLAY call
+    play_return: Option<u16>,
+    dma_stall: usize,
+}
+
+impl Player {
+    pub fn new(nsf: Nsf, system: System) -> Player {
+        let mut processor = Processor::new();
+        processor.set_trace(false);
+        let mut mapper = NsfMapper::new(&nsf);
+        mapper.set_irq_vector_writable(nsf.get_flags() & FLAG_IRQ > 0);
+        processor.get_memory_mut().set_mapper(Box::new(mapper));
+        processor.get_memory_mut().set_apu(Self::new_apu(&nsf, system));
+        let play_period = nsf.get_play_speed(system) as f64 * system.cpu_frequency() as f64 / 1_000_000.0;
+        let song = nsf.get_starting_song();
+        let mut player = Player {
+            nsf,
+            processor,
+            system,
+            song,
+            cycle: 0,
+            play_period,
+            next_play: 0.0,
+            play_pending: false,
+            play_return: None,
+            dma_stall: 0,
+        };
+        player.start_song(song);
+        player
+    }
+
+    fn new_apu(nsf: &Nsf, system: System) -> Apu {
+        let mut apu = Apu::new(system);
+        let expansion = nsf.get_expansion();
+        let chips: [(u8, NewExpansion); 6] = [
+            (EXPANSION_VRC6, |_| Box::new(Vrc6::new(false))),
+            (EXPANSION_VRC7, |system| Box::new(Vrc7Audio::new(system.cpu_frequency()))),
+            (EXPANSION_FDS, |_| Box::new(FdsAudio::new())),
+            (EXPANSION_MMC5, |_| Box::new(Mmc5Audio::new())),
+            (EXPANSION_N163, |_| Box::new(N163Audio::new())),
+            (EXPANSION_5B, |_| Box::new(Sunsoft5bAudio::new())),
+        ];
+        for (flag, chip) in chips.iter() {
+            if expansion & flag > 0 {
+                apu.add_expansion(chip(system));
+            }
+        }
+        apu
+    }
+
+    // 1 based, as in the header
+    pub fn start_song(&mut self, song: u8) {
+        self.song = song.clamp(1, self.nsf.get_total_songs().max(1));
+        let memory = self.processor.get_memory_mut();
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            memory.set_address(0x00, addr);
+        }
+        for addr in 0x4000..0x4014 {
+            memory.set_address(0x00, addr);
+        }
+        memory.set_address(0x00, 0x4015);
+        memory.set_address(0x0F, 0x4015);
+        memory.set_address(0x40, 0x4017);
+        if self.nsf.is_banked() || self.nsf.get_expansion() & EXPANSION_FDS > 0 {
+            for (idx, bank) in NsfMapper::get_initial_banks(&self.nsf).iter().enumerate() {
+                self.processor.get_memory_mut().set_address(*bank, BANK_REGISTERS as usize + idx);
+            }
+        }
+
+        self.processor.set_accumulator(self.song - 1);
+        self.processor.set_x(if self.system == System::Pal { 1 } else { 0 });
+        self.processor.call(self.nsf.get_init_address(), RETURN_ADDRESS);
+        self.next_play = self.cycle as f64 + self.play_period;
+        self.play_pending = false;
+        self.play_return = None;
+    }
+
+    pub fn get_song(&self) -> u8 {
+        self.song
+    }
+
+    pub fn get_nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn get_apu(&self) -> &Apu {
+        self.processor.get_memory().get_apu()
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut Apu {
+        self.processor.get_memory_mut().get_apu_mut()
+    }
+
+    fn has_returned_to(&self, addr: u16) -> bool {
+        self.processor.is_instruction_boundary() && self.processor.get_pc() == addr
+    }
+
+    fn is_idle(&self) -> bool {
+        self.has_returned_to(RETURN_ADDRESS)
+    }
+
+    // one CPU cycle
+    pub fn step(&mut self) {
+        let flags = self.nsf.get_flags();
+        if self.cycle as f64 >= self.next_play {
+            self.play_pending = flags & FLAG_NO_PLAY == 0;
+            self.next_play += self.play_period;
+        }
+        if let Some(addr) = self.play_return {
+            if self.has_returned_to(addr) {
+                self.play_return = None;
+            }
+        }
+        if self.play_pending && self.play_return.is_none() && self.processor.is_instruction_boundary() {
+            let return_addr = if self.is_idle() {
+                Some(RETURN_ADDRESS)
+            } else if flags & FLAG_NON_RETURNING_INIT > 0 {
+                Some(self.processor.get_pc())
+            } else {
+                None
+            };
+            if let Some(return_addr) = return_addr {
+                self.processor.call(self.nsf.get_play_address(), return_addr);
+                self.play_return = Some(return_addr);
+                self.play_pending = false;
+            }
+        }
+        if flags & FLAG_IRQ > 0 {
+            let irq = self.get_apu().irq_line();
+            self.processor.set_irq_line(irq);
+        }
+
+        if self.dma_stall > 0 {
+            self.dma_stall -= 1;
+        } else if let Some(addr) = self.get_apu().get_dma_request() {
+            let memory = self.processor.get_memory_mut();
+            let data = memory.get_instruction(addr as usize);
+            memory.get_apu_mut().complete_dma(data);
+            self.dma_stall = DMC_DMA_CYCLES - 1;
+        } else if !self.is_idle() {
+            self.processor.execute_next_instruction();
+        }
+        self.get_apu_mut().clock();
+        self.cycle += 1;
+    }
+
+    pub fn run_cycles(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// Output picture of the PPU.
+//
+// The PPU produces 256x240 pixels per frame, each one being a 6 bit index into the
+// system palette. The frame keeps those indices with the PPUMASK emphasis bits of the dot
+// and converts them to the "TV" image only when somebody asks for it. Emphasis scales the
+// emphasised channels by 1.1 and the others by 0.9.
+//
+// The `tvsha1` values in test_roms.xml were taken over 32 bit pixels laid out as
+// R, G, B, 0xFF, row by row, without any cropping. An all black screen in that layout hashes
+// to "FiAsKo3Df69PZWd5r9lcCTxzKvM=", the value recorded for cpu_reset/registers.nes, which
+// waits for a manual reset with the screen off. The dmc_tests ROMs never touch the palette and
+// show the power-up backdrop $09 (11, 72, 0) as "FgXL90wCmm5D08QDIiVjJz6igV8=", which fixes
+// the order of red and blue.
+
+use crate::nes::digest;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+pub const TV_BYTES_PER_PIXEL: usize = 4;
+
+// 2C02 palette, RGB, the colors the `tvsha1` references were taken with
+pub const PALETTE: [(u8, u8, u8); 64] = [
+    (102, 102, 102), (0, 42, 136), (20, 18, 167), (59, 0, 164), (92, 0, 126), (110, 0, 64), (108, 6, 0), (86, 29, 0),
+    (51, 53, 0), (11, 72, 0), (0, 82, 0), (0, 79, 8), (0, 64, 77), (0, 0, 0), (0, 0, 0), (0, 0, 0),
+    (173, 173, 173), (21, 95, 217), (66, 64, 255), (117, 39, 254), (160, 26, 204), (183, 30, 123), (181, 49, 32), (153, 78, 0),
+    (107, 109, 0), (56, 135, 0), (12, 147, 0), (0, 143, 50), (0, 124, 141), (0, 0, 0), (0, 0, 0), (0, 0, 0),
+    (255, 254, 255), (100, 176, 255), (146, 144, 255), (198, 118, 255), (243, 106, 255), (254, 110, 204), (254, 129, 112), (234, 158, 34),
+    (188, 190, 0), (136, 216, 0), (92, 228, 48), (69, 224, 130), (72, 205, 222), (79, 79, 79), (0, 0, 0), (0, 0, 0),
+    (255, 254, 255), (192, 223, 255), (211, 210, 255), (232, 200, 255), (251, 194, 255), (254, 196, 234), (254, 204, 197), (247, 216, 165),
+    (228, 229, 148), (207, 239, 150), (189, 244, 171), (179, 243, 204), (181, 235, 242), (184, 184, 184), (0, 0, 0), (0, 0, 0),
+];
+
+// palette index of a black pixel
+pub const BLACK: u8 = 0x0F;
+
+#[derive(Clone)]
+pub struct Frame {
+    // 6 bit color in bits 0-5, emphasis (red, green, blue) in bits 6-8
+    pixels: Vec<u16>,
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame {
+            pixels: vec![BLACK as u16; WIDTH * HEIGHT]
+        }
+    }
+
+    // `emphasis` is PPUMASK >> 5
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u8, emphasis: u8) {
+        self.pixels[y * WIDTH + x] = (color & 0x3F) as u16 | ((emphasis & 0x07) as u16) << 6;
+    }
+
+    #[cfg(test)]
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        (self.pixels[y * WIDTH + x] & 0x3F) as u8
+    }
+
+    // R, G, B, 0xFF for every pixel
+    pub fn to_tv(&self) -> Vec<u8> {
+        let mut tv = Vec::with_capacity(WIDTH * HEIGHT * TV_BYTES_PER_PIXEL);
+        for pixel in self.pixels.iter() {
+            let (r, g, b) = PALETTE[(*pixel & 0x3F) as usize];
+            let (r, g, b) = emphasize((r, g, b), (*pixel >> 6) as u8);
+            tv.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+        tv
+    }
+
+    // same format as the `tvsha1` entries of test_roms.xml
+    pub fn tv_sha1(&self) -> String {
+        digest::base64_encode(&digest::sha1(&self.to_tv()))
+    }
+}
+
+fn emphasize((r, g, b): (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+    let (mut r, mut g, mut b) = (r as f64, g as f64, b as f64);
+    if emphasis & 0x01 > 0 {
+        r *= 1.1;
+        g *= 0.9;
+        b *= 0.9;
+    }
+    if emphasis & 0x02 > 0 {
+        g *= 1.1;
+        r *= 0.9;
+        b *= 0.9;
+    }
+    if emphasis & 0x04 > 0 {
+        b *= 1.1;
+        r *= 0.9;
+        g *= 0.9;
+    }
+    (r.min(255.0) as u8, g.min(255.0) as u8, b.min(255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(color: u8) -> Frame {
+        let mut frame = Frame::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                frame.set_pixel(x, y, color, 0);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn solid_screens_match_the_references() {
+        assert_eq!(solid(BLACK).tv_sha1(), "FiAsKo3Df69PZWd5r9lcCTxzKvM=");
+        assert_eq!(solid(0x09).tv_sha1(), "FgXL90wCmm5D08QDIiVjJz6igV8=");
+    }
+
+    #[test]
+    fn emphasis_dims_the_other_channels() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, 0x00, 0x01);
+        frame.set_pixel(1, 0, 0x00, 0x06);
+        frame.set_pixel(2, 0, 0x20, 0x07);
+        let tv = frame.to_tv();
+        assert_eq!(tv[0..4], [112, 91, 91, 0xFF]);
+        assert_eq!(tv[4..8], [82, 100, 100, 0xFF]);
+        assert_eq!(tv[8..12], [227, 226, 227, 0xFF]);
+        assert_eq!(frame.get_pixel(2, 0), 0x20);
+    }
+}
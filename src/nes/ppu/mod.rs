@@ -0,0 +1,614 @@
+// Implementation of 2C02 chip used as NES PPU (picture processing unit)
+//
+// CPU side registers, mirrored every 8 bytes in $2008-$3FFF:
+//
+// $2000 PPUCTRL   .... NMI enable, sprite size, pattern table select, VRAM increment, nametable
+// $2001 PPUMASK   .... emphasis, sprite/background enable and left column clipping, greyscale
+// $2002 PPUSTATUS .... vblank, sprite 0 hit, sprite overflow (read clears vblank and the w latch)
+// $2003 OAMADDR
+// $2004 OAMDATA
+// $2005 PPUSCROLL .... two writes, x then y
+// $2006 PPUADDR   .... two writes, high then low byte
+// $2007 PPUDATA   .... reads are delayed by one through a buffer, except for the palette
+//
+// PPU address space:
+//
+// $0000-$1FFF pattern tables (cartridge CHR ROM/RAM)
+// $2000-$2FFF nametables, 2K inside the console (CIRAM) mirrored by the cartridge, $3000-$3EFF mirror
+// $3F00-$3F1F palette, $3F20-$3FFF mirrors
+//
+// Everything below $3F00 goes through the cartridge (see mapper), which is passed in by the
+// owner of the PPU on every access. The cartridge also sees the address bus itself: with
+// rendering off it holds v, so $2006 writes and $2007 accesses show up there (MMC3 counts
+// A12 edges from it).
+//
+// A frame is 341 dots * 262 scanlines (312 on PAL), vblank starts at scanline 241 dot 1 and
+// ends at the pre-render scanline. Reading $2002 on the dot before the flag is set keeps it
+// clear for that frame. On NTSC the pre-render scanline is one dot shorter on
+// odd frames when rendering is enabled by its dot 338.
+//
+// Rendering, on scanlines 0-239 and the pre-render one while background or sprites are on:
+//
+// 1-256    a pixel per dot from the background shifters and the sprites of the line, the
+//          background fetches a tile every 8 dots (nametable, attribute, pattern low, high)
+// 257      sprite evaluation for the next scanline (sprites past the 8th set the overflow flag)
+// 257-320  8 sprite slots: two garbage nametable reads, pattern low, pattern high, the slots
+//          without a sprite fetch tile $FF
+// 321-336  first two background tiles of the next scanline
+// 337-340  two nametable reads of the first tile again
+//
+// Every fetch puts its address on the bus first, the boards following A12 or the fetch order
+// (MMC3, MMC2/MMC4, MMC5) see the same sequence as on the console.
+//
+// The picture is complete when vblank starts, see `take_frame_complete`.
+
+pub mod frame;
+
+use self::frame::Frame;
+use crate::nes::console::System;
+use crate::nes::mapper::{Mapper, CIRAM_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    // $2000 = $2400, $2800 = $2C00
+    Horizontal,
+    // $2000 = $2800, $2400 = $2C00
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+impl Mirroring {
+    // index into 4K of nametable memory, only four screen uses more than the first 2K
+    pub fn nametable_index(&self, addr: u16) -> usize {
+        let addr = (addr as usize) & 0x0FFF;
+        let table = addr / 0x400;
+        let offset = addr & 0x3FF;
+        let page = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        page * 0x400 + offset
+    }
+}
+
+// palette RAM at power on, as found on real consoles
+const POWER_UP_PALETTE: [u8; 0x20] = [
+    0x09, 0x01, 0x00, 0x01, 0x00, 0x02, 0x02, 0x0D, 0x08, 0x10, 0x08, 0x24, 0x00, 0x00, 0x04, 0x2C,
+    0x09, 0x01, 0x34, 0x03, 0x00, 0x04, 0x00, 0x14, 0x08, 0x3A, 0x00, 0x02, 0x00, 0x20, 0x2C, 0x08,
+];
+
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    oam: [u8; 0x100],
+    v: u16,  // current VRAM address
+    t: u16,  // temporary VRAM address
+    x: u8,   // fine x scroll
+    w: bool, // first/second write toggle
+    read_buffer: u8,
+    open_bus: u8,
+
+    ciram: [u8; CIRAM_SIZE],
+    palette: [u8; 0x20],
+
+    scanlines: usize,
+    scanline: usize,
+    dot: usize,
+    odd_frame: bool,
+    // $2002 was read right before vblank starts, the flag isn't set this frame
+    suppress_vblank: bool,
+    skip_dot: bool,
+
+    // background: 4 bit pixels (palette, pattern) of two tiles, the current one on top
+    tile_data: u64,
+    nametable_byte: u8,
+    attribute_bits: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    // sprites of the scanline: OAM index, 4 bit pixels, attributes and X
+    sprite_count: usize,
+    sprite_indexes: [u8; 8],
+    sprite_patterns: [u32; 8],
+    sprite_attributes: [u8; 8],
+    sprite_positions: [u8; 8],
+
+    frame: Frame,
+    frame_complete: bool,
+}
+
+impl Ppu {
+    pub fn new(system: System) -> Ppu {
+        Ppu {
+            ctrl: 0x00,
+            mask: 0x00,
+            status: 0x00,
+            oam_addr: 0x00,
+            oam: [0; 0x100],
+            v: 0x0000,
+            t: 0x0000,
+            x: 0x00,
+            w: false,
+            read_buffer: 0x00,
+            open_bus: 0x00,
+            ciram: [0; CIRAM_SIZE],
+            palette: POWER_UP_PALETTE,
+            scanlines: match system {
+                System::Ntsc => 262,
+                System::Pal => 312,
+            },
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            suppress_vblank: false,
+            skip_dot: false,
+            tile_data: 0,
+            nametable_byte: 0x00,
+            attribute_bits: 0x00,
+            pattern_low: 0x00,
+            pattern_high: 0x00,
+            sprite_count: 0,
+            sprite_indexes: [0; 8],
+            sprite_patterns: [0; 8],
+            sprite_attributes: [0; 8],
+            sprite_positions: [0; 8],
+            frame: Frame::new(),
+            frame_complete: false,
+        }
+    }
+
+    // one PPU dot
+    pub fn clock(&mut self, mapper: &mut dyn Mapper) {
+        let pre_render = self.scanlines - 1;
+        if self.scanline < 240 || self.scanline == pre_render {
+            self.render(mapper);
+        }
+        if self.scanline == 241 && self.dot == 1 {
+            if !self.suppress_vblank {
+                self.status |= 0x80;
+            }
+            self.suppress_vblank = false;
+            self.frame_complete = true;
+        }
+        if self.scanline == pre_render && self.dot == 1 {
+            // vblank, sprite 0 hit and overflow
+            self.status &= 0x1F;
+        }
+
+        self.dot += 1;
+        if self.scanline == pre_render && self.dot == 339 {
+            self.skip_dot = self.scanlines == 262 && self.odd_frame && self.rendering_enabled();
+        }
+        if self.scanline == pre_render && self.dot == 340 && self.skip_dot {
+            self.dot += 1;
+        }
+        if self.dot > 340 {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > pre_render {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn render(&mut self, mapper: &mut dyn Mapper) {
+        let visible = self.scanline < 240;
+        let dot = self.dot;
+        if visible && (1..=256).contains(&dot) {
+            self.render_pixel();
+        }
+        if !self.rendering_enabled() {
+            return;
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            self.tile_data <<= 4;
+            match dot % 8 {
+                1 => self.fetch_nametable_byte(mapper),
+                3 => self.fetch_attribute_bits(mapper),
+                5 => self.pattern_low = self.fetch(self.background_pattern_address(), mapper),
+                7 => self.pattern_high = self.fetch(self.background_pattern_address() + 8, mapper),
+                0 => {
+                    self.store_tile();
+                    self.increment_coarse_x();
+                },
+                _ => {}
+            }
+        }
+        if dot == 256 {
+            self.increment_y();
+        }
+        if dot == 257 {
+            // horizontal position from t
+            self.v = (self.v & 0xFBE0) | (self.t & 0x041F);
+            self.evaluate_sprites(visible);
+        }
+        if (257..=320).contains(&dot) {
+            self.oam_addr = 0;
+            let slot = (dot - 257) / 8;
+            match (dot - 257) % 8 {
+                // garbage nametable reads in place of the background ones
+                0 | 2 => self.fetch_nametable_byte(mapper),
+                4 => {
+                    let addr = self.sprite_pattern_address(slot);
+                    self.pattern_low = self.fetch(addr, mapper);
+                },
+                6 => {
+                    let addr = self.sprite_pattern_address(slot);
+                    self.pattern_high = self.fetch(addr + 8, mapper);
+                    if slot < self.sprite_count {
+                        self.store_sprite(slot);
+                    }
+                },
+                _ => {}
+            }
+        }
+        if dot == 337 || dot == 339 {
+            // unused nametable reads, the first tile of the next scanline again
+            self.fetch_nametable_byte(mapper);
+        }
+        if self.scanline == self.scanlines - 1 && (280..=304).contains(&dot) {
+            // vertical position from t
+            self.v = (self.v & 0x841F) | (self.t & 0x7BE0);
+        }
+    }
+
+    fn render_pixel(&mut self) {
+        let x = self.dot - 1;
+        let color = if self.rendering_enabled() {
+            let background = self.background_pixel(x);
+            let (sprite, slot) = self.sprite_pixel(x);
+            let index = match (background & 0x03 > 0, sprite & 0x03 > 0) {
+                (false, false) => 0x00,
+                (false, true) => 0x10 | sprite,
+                (true, false) => background,
+                (true, true) => {
+                    if self.sprite_indexes[slot] == 0 && x != 255 {
+                        // sprite 0 hit
+                        self.status |= 0x40;
+                    }
+                    if self.sprite_attributes[slot] & 0x20 > 0 { background } else { 0x10 | sprite }
+                },
+            };
+            self.palette[Self::palette_index(0x3F00 | index as u16)]
+        } else if self.v & 0x3F00 == 0x3F00 {
+            // with rendering off the backdrop is replaced by the palette entry v points to
+            self.palette[Self::palette_index(self.v)]
+        } else {
+            self.palette[0]
+        };
+        let color = if self.mask & 0x01 > 0 { color & 0x30 } else { color };
+        self.frame.set_pixel(x, self.scanline, color, self.mask >> 5);
+    }
+
+    fn background_pixel(&self, x: usize) -> u8 {
+        if self.mask & 0x08 == 0 || (x < 8 && self.mask & 0x02 == 0) {
+            return 0x00;
+        }
+        let pixels = (self.tile_data >> 32) as u32;
+        ((pixels >> ((7 - self.x as u32) * 4)) & 0x0F) as u8
+    }
+
+    // 4 bit pixel and slot of the first opaque sprite at x
+    fn sprite_pixel(&self, x: usize) -> (u8, usize) {
+        if self.mask & 0x10 == 0 || (x < 8 && self.mask & 0x04 == 0) {
+            return (0x00, 0);
+        }
+        for slot in 0..self.sprite_count {
+            let offset = x as isize - self.sprite_positions[slot] as isize;
+            if !(0..8).contains(&offset) {
+                continue;
+            }
+            let pixel = ((self.sprite_patterns[slot] >> ((7 - offset) * 4)) & 0x0F) as u8;
+            if pixel & 0x03 > 0 {
+                return (pixel, slot);
+            }
+        }
+        (0x00, 0)
+    }
+
+    fn fetch_nametable_byte(&mut self, mapper: &mut dyn Mapper) {
+        self.nametable_byte = self.fetch(0x2000 | (self.v & 0x0FFF), mapper);
+    }
+
+    fn fetch_attribute_bits(&mut self, mapper: &mut dyn Mapper) {
+        let v = self.v;
+        let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        // quadrant of the 32x32 area
+        let shift = ((v >> 4) & 0x04) | (v & 0x02);
+        self.attribute_bits = ((self.fetch(addr, mapper) >> shift) & 0x03) << 2;
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        let table = (self.ctrl as u16 & 0x10) << 8;
+        table + self.nametable_byte as u16 * 16 + ((self.v >> 12) & 0x07)
+    }
+
+    fn store_tile(&mut self) {
+        let mut data: u32 = 0;
+        for bit in (0..8).rev() {
+            let pattern = ((self.pattern_low >> bit) & 0x01) | (((self.pattern_high >> bit) & 0x01) << 1);
+            data = (data << 4) | (self.attribute_bits | pattern) as u32;
+        }
+        self.tile_data |= data as u64;
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 0x1F {
+            // next horizontal nametable
+            self.v = (self.v & !0x001F) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = (self.v & 0x03E0) >> 5;
+        let coarse_y = match coarse_y {
+            29 => {
+                // next vertical nametable
+                self.v ^= 0x0800;
+                0
+            },
+            31 => 0,
+            _ => coarse_y + 1,
+        };
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    fn sprite_height(&self) -> usize {
+        if self.ctrl & 0x20 > 0 { 16 } else { 8 }
+    }
+
+    // the first 8 sprites on the next scanline, with the hardware bug that makes the overflow
+    // check look at the wrong bytes once 8 are found
+    fn evaluate_sprites(&mut self, visible: bool) {
+        self.sprite_count = 0;
+        if !visible {
+            return;
+        }
+        let height = self.sprite_height();
+        let in_range = |y: u8| (self.scanline as isize - y as isize) >= 0 && ((self.scanline as isize - y as isize) as usize) < height;
+        let mut n = 0;
+        let mut count = 0;
+        let mut found = [0u8; 8];
+        while n < 64 && count < 8 {
+            if in_range(self.oam[n * 4]) {
+                found[count] = n as u8;
+                count += 1;
+            }
+            n += 1;
+        }
+        let mut m = 0;
+        let mut overflow = false;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                overflow = true;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0x03;
+        }
+        if overflow {
+            self.status |= 0x20;
+        }
+        self.sprite_count = count;
+        self.sprite_indexes = found;
+    }
+
+    // empty slots fetch tile $FF, what the cleared secondary OAM holds
+    fn sprite_pattern_address(&self, slot: usize) -> u16 {
+        let height = self.sprite_height();
+        if slot >= self.sprite_count {
+            return if height == 16 { 0x1FF0 } else { ((self.ctrl as u16 & 0x08) << 9) | 0x0FF0 };
+        }
+        let entry = self.sprite_indexes[slot] as usize * 4;
+        let tile = self.oam[entry + 1] as u16;
+        let attributes = self.oam[entry + 2];
+        let mut row = (self.scanline - self.oam[entry] as usize) as u16;
+        if attributes & 0x80 > 0 {
+            row = height as u16 - 1 - row;
+        }
+        if height == 16 {
+            let table = (tile & 0x01) * 0x1000;
+            let tile = (tile & 0xFE) + if row > 7 { 1 } else { 0 };
+            table + tile * 16 + (row & 0x07)
+        } else {
+            let table = (self.ctrl as u16 & 0x08) << 9;
+            table + tile * 16 + row
+        }
+    }
+
+    fn store_sprite(&mut self, slot: usize) {
+        let entry = self.sprite_indexes[slot] as usize * 4;
+        let attributes = self.oam[entry + 2];
+        let palette = (attributes & 0x03) << 2;
+        let mut data: u32 = 0;
+        for pixel in 0..8 {
+            // the leftmost pixel is bit 7 unless the sprite is flipped horizontally
+            let bit = if attributes & 0x40 > 0 { pixel } else { 7 - pixel };
+            let pattern = ((self.pattern_low >> bit) & 0x01) | (((self.pattern_high >> bit) & 0x01) << 1);
+            data = (data << 4) | (palette | pattern) as u32;
+        }
+        self.sprite_patterns[slot] = data;
+        self.sprite_attributes[slot] = attributes;
+        self.sprite_positions[slot] = self.oam[entry + 3];
+    }
+
+    // true once per frame when vblank starts, the picture is complete then
+    pub fn take_frame_complete(&mut self) -> bool {
+        let complete = self.frame_complete;
+        self.frame_complete = false;
+        complete
+    }
+
+    pub fn get_frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask & 0x18 > 0
+    }
+
+    // NMI output, active while in vblank with NMI enabled
+    pub fn nmi_line(&self) -> bool {
+        self.status & 0x80 > 0 && self.ctrl & 0x80 > 0
+    }
+
+    #[cfg(test)]
+    pub fn get_scanline(&self) -> usize {
+        self.scanline
+    }
+
+    #[cfg(test)]
+    pub fn get_dot(&self) -> usize {
+        self.dot
+    }
+
+    pub fn get_ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    // CPU read of $2000-$2007
+    pub fn read_register(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        match addr & 0x07 {
+            0x02 => {
+                let data = (self.status & 0xE0) | (self.open_bus & 0x1F);
+                self.status &= 0x7F;
+                self.suppress_vblank = self.scanline == 241 && self.dot == 1;
+                self.w = false;
+                self.open_bus = data;
+            },
+            0x04 => {
+                self.open_bus = self.oam[self.oam_addr as usize];
+            },
+            0x07 => {
+                let addr = self.v & 0x3FFF;
+                let data = self.fetch(addr, mapper);
+                self.open_bus = if addr >= 0x3F00 {
+                    // palette is read directly, the buffer gets the nametable byte below it
+                    self.read_buffer = self.fetch(addr - 0x1000, mapper);
+                    (data & 0x3F) | (self.open_bus & 0xC0)
+                } else {
+                    let buffered = self.read_buffer;
+                    self.read_buffer = data;
+                    buffered
+                };
+                self.increment_v();
+                mapper.ppu_address(self.v & 0x3FFF);
+            },
+            _ => {}
+        }
+        self.open_bus
+    }
+
+    // CPU write of $2000-$2007
+    pub fn write_register(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        self.open_bus = data;
+        match addr & 0x07 {
+            0x00 => {
+                self.ctrl = data;
+                self.t = (self.t & 0xF3FF) | ((data as u16 & 0x03) << 10);
+            },
+            0x01 => {
+                self.mask = data;
+            },
+            0x03 => {
+                self.oam_addr = data;
+            },
+            0x04 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            0x05 => {
+                if !self.w {
+                    self.t = (self.t & 0xFFE0) | (data as u16 >> 3);
+                    self.x = data & 0x07;
+                } else {
+                    self.t = (self.t & 0x8C1F) | ((data as u16 & 0x07) << 12) | ((data as u16 & 0xF8) << 2);
+                }
+                self.w = !self.w;
+            },
+            0x06 => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                    mapper.ppu_address(self.v & 0x3FFF);
+                }
+                self.w = !self.w;
+            },
+            0x07 => {
+                self.write(self.v & 0x3FFF, data, mapper);
+                self.increment_v();
+                mapper.ppu_address(self.v & 0x3FFF);
+            },
+            _ => {}
+        }
+    }
+
+    // OAM DMA ($4014) writes go through OAMDATA
+    pub fn write_oam(&mut self, data: u8) {
+        self.open_bus = data;
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn increment_v(&mut self) {
+        let step = if self.ctrl & 0x04 > 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    fn palette_index(addr: u16) -> usize {
+        let idx = (addr & 0x1F) as usize;
+        // $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries
+        if idx & 0x13 == 0x10 { idx & 0x0F } else { idx }
+    }
+
+    // PPU bus read without side effects
+    pub fn read(&self, addr: u16, mapper: &dyn Mapper) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x3EFF => mapper.ppu_peek(addr, &self.ciram),
+            _ => self.palette[Self::palette_index(addr)],
+        }
+    }
+
+    // PPU bus read as done by the PPU itself, the cartridge sees it
+    fn fetch(&self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x3EFF => {
+                mapper.ppu_address(addr);
+                mapper.ppu_read(addr, &self.ciram)
+            },
+            _ => self.palette[Self::palette_index(addr)],
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x3EFF => {
+                mapper.ppu_address(addr);
+                mapper.ppu_write(addr, data, &mut self.ciram)
+            },
+            _ => {
+                self.palette[Self::palette_index(addr)] = data & 0x3F;
+            }
+        }
+    }
+}
@@ -7,81 +7,399 @@
 // PlayChoice INST-ROM, if present (0 or 8192 bytes)
 // PlayChoice PROM, if present (16 bytes Data, 16 bytes CounterOut) (this is often missing, see PC10 ROM-Images for details)
 // Some ROM-Images additionally contain a 128-byte (or sometimes 127-byte) title at the end of the file.
+//
+// The trainer is meant to be loaded at $7000-$71FF. INST-ROM and PROM only exist on PlayChoice
+// images. A truncated file gets its missing ROM bytes as zeros, anything past the sections that
+// isn't a title is kept aside as extra data.
 
 
 pub trait Rom {
-    fn new(data: &Vec<u8>) -> Self;
+    fn new(data: &[u8]) -> Self;
     fn get_header(&self) -> &Header;
-    fn get_rom_data(&self) -> &Vec<u8>;
-//    fn get_trainer(&self) -> Vec<u16>;
-//    fn get_prg_rom_data(&self) -> Vec<u16>;
-//    fn get_chr_rom_data(&self) -> Vec<u8>;
-//    fn get_inst_rom(&self) -> Vec<u16>;
+    fn get_trainer(&self) -> Option<&Vec<u8>>;
+    fn get_prg_rom_data(&self) -> &Vec<u8>;
+    fn get_chr_rom_data(&self) -> &Vec<u8>;
+    fn get_inst_rom(&self) -> Option<&Vec<u8>>;
+    fn get_title(&self) -> Option<&str>;
 }
 
 
 // The format of the header is as follows:
 //
 // 0-3: Constant $4E $45 $53 $1A ("NES" followed by MS-DOS end-of-file)
-// 4: Size of PRG ROM in 16 KB units
-// 5: Size of CHR ROM in 8 KB units (Value 0 means the board uses CHR RAM)
-// 6: Flags 6 - Mapper, mirroring, battery, trainer
-// 7: Flags 7 - Mapper, VS/Playchoice, NES 2.0
-// 8: Flags 8 - PRG-RAM size (rarely used extension)
-// 9: Flags 9 - TV system (rarely used extension)
-// 10: Flags 10 - TV system, PRG-RAM presence (unofficial, rarely used extension)
-// 11-15: Unused padding (should be filled with zero, but some rippers put their name across bytes 7-15)
-pub struct Header {
-    first_four_constants : [u8;4],
-    prg_rom_size: u8,
-    chr_rom_size: u8,
+// 4: Size of PRG ROM in 16 KB units (NES 2.0: LSB)
+// 5: Size of CHR ROM in 8 KB units (Value 0 means the board uses CHR RAM) (NES 2.0: LSB)
+// 6: Flags 6 - Mapper D0-D3, four screen, trainer, battery, mirroring (1: vertical)
+// 7: Flags 7 - Mapper D4-D7, NES 2.0 identifier (bits 2-3 = 2), console type
+// 8: iNES: PRG-RAM size in 8 KB units (0 means 8 KB), NES 2.0: submapper, mapper D8-D11
+// 9: iNES: TV system (bit 0: PAL), NES 2.0: CHR ROM size MSB, PRG ROM size MSB
+// 10: NES 2.0: PRG-NVRAM shift, PRG-RAM shift
+// 11: NES 2.0: CHR-NVRAM shift, CHR-RAM shift
+// 12: NES 2.0: CPU/PPU timing (0: NTSC, 1: PAL, 2: multiple region, 3: Dendy)
+// 13: NES 2.0: Vs. hardware type and PPU type, or the extended console type
+// 14: NES 2.0: number of miscellaneous ROMs
+// 15: NES 2.0: default expansion device
+//
+// iNES 1.0 leaves 11-15 unused (should be filled with zero, but some rippers put their name
+// across bytes 7-15).
+//
+// NES 2.0 sizes: an MSB nibble of $F switches the size to exponent-multiplier notation,
+// the LSB byte is EEEE EEMM and the size 2^E * (MM * 2 + 1) bytes. A shift count S gives
+// 64 << S bytes of RAM, 0 means none.
+//
+// Detection: NES 2.0 when bits 2-3 of flags 7 are 2 and the ROM sizes fit in the file, iNES 1.0
+// when they are 0 and bytes 12-15 are zero, archaic iNES otherwise. Archaic headers are
+// only trusted up to flags 6, the rest is likely a ripper's name.
+
+use crate::nes::ppu::Mirroring;
+
+pub const HEADER_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    ArchaicINes,
+    INes,
+    Nes2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    // extended console type from byte 13
+    Extended(u8),
+}
 
-    //flags 6 to 10
-    flags: [u8; 5],
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultipleRegion,
+    Dendy,
+}
+
+pub struct Header {
+    raw: [u8; HEADER_SIZE],
+    format: Format,
+    mapper: u16,
+    submapper: u8,
+    // in bytes
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    mirroring: Mirroring,
+    battery: bool,
+    trainer: bool,
+    console_type: ConsoleType,
+    timing: Timing,
+    vs_ppu_type: u8,
+    vs_hardware_type: u8,
+    misc_roms: u8,
+    expansion_device: u8,
 }
 
 impl Header {
 
-    pub fn new(data:&[u8]) -> Header {
-        Header {
-            first_four_constants:[data[0], data[1], data[2], data[3]],
-            prg_rom_size: data[4],
-            chr_rom_size: data[5],
-            flags: [data[6], data[7], data[8], data[9], data[10]]
+    // `data` is the whole file, its size tells NES 2.0 from garbage in byte 9. Missing bytes
+    // read as zero.
+    pub fn new(data: &[u8]) -> Header {
+        let mut raw = [0; HEADER_SIZE];
+        let len = data.len().min(HEADER_SIZE);
+        raw[..len].copy_from_slice(&data[..len]);
+
+        let nes_2_sizes = (
+            rom_size(raw[4], raw[9] & 0x0F, 0x4000),
+            rom_size(raw[5], raw[9] >> 4, 0x2000),
+        );
+        let trainer = raw[6] & 0x04 > 0;
+        let nes_2_end = (HEADER_SIZE + if trainer { 512 } else { 0 })
+            .saturating_add(nes_2_sizes.0)
+            .saturating_add(nes_2_sizes.1);
+        let format = if raw[7] & 0x0C == 0x08 && nes_2_end <= data.len() {
+            Format::Nes2
+        } else if raw[7] & 0x0C == 0x00 && raw[12..16].iter().all(|x| *x == 0) {
+            Format::INes
+        } else {
+            Format::ArchaicINes
+        };
+
+        let battery = raw[6] & 0x02 > 0;
+        let mirroring = if raw[6] & 0x08 > 0 {
+            Mirroring::FourScreen
+        } else if raw[6] & 0x01 > 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let mut header = Header {
+            raw,
+            format,
+            mapper: (raw[6] >> 4) as u16,
+            submapper: 0,
+            prg_rom_size: raw[4] as usize * 0x4000,
+            chr_rom_size: raw[5] as usize * 0x2000,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer,
+            console_type: ConsoleType::Nes,
+            timing: Timing::Ntsc,
+            vs_ppu_type: 0,
+            vs_hardware_type: 0,
+            misc_roms: 0,
+            expansion_device: 0,
+        };
+
+        match format {
+            Format::Nes2 => {
+                header.mapper |= (raw[7] & 0xF0) as u16 | ((raw[8] & 0x0F) as u16) << 8;
+                header.submapper = raw[8] >> 4;
+                header.prg_rom_size = nes_2_sizes.0;
+                header.chr_rom_size = nes_2_sizes.1;
+                header.prg_ram_size = ram_size(raw[10] & 0x0F);
+                header.prg_nvram_size = ram_size(raw[10] >> 4);
+                header.chr_ram_size = ram_size(raw[11] & 0x0F);
+                header.chr_nvram_size = ram_size(raw[11] >> 4);
+                header.console_type = match raw[7] & 0x03 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::PlayChoice10,
+                    _ => ConsoleType::Extended(raw[13] & 0x0F),
+                };
+                header.timing = match raw[12] & 0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultipleRegion,
+                    _ => Timing::Dendy,
+                };
+                if header.console_type == ConsoleType::VsSystem {
+                    header.vs_ppu_type = raw[13] & 0x0F;
+                    header.vs_hardware_type = raw[13] >> 4;
+                }
+                header.misc_roms = raw[14] & 0x03;
+                header.expansion_device = raw[15] & 0x3F;
+            },
+            Format::INes | Format::ArchaicINes => {
+                // 8K of PRG RAM unless byte 8 says more, battery backed with the battery flag
+                let prg_ram = if format == Format::INes { (raw[8] as usize).max(1) } else { 1 } * 0x2000;
+                if battery {
+                    header.prg_nvram_size = prg_ram;
+                } else {
+                    header.prg_ram_size = prg_ram;
+                }
+                if header.chr_rom_size == 0 {
+                    header.chr_ram_size = 0x2000;
+                }
+                if format == Format::INes {
+                    header.mapper |= (raw[7] & 0xF0) as u16;
+                    header.console_type = if raw[7] & 0x01 > 0 {
+                        ConsoleType::VsSystem
+                    } else if raw[7] & 0x02 > 0 {
+                        ConsoleType::PlayChoice10
+                    } else {
+                        ConsoleType::Nes
+                    };
+                    header.timing = if raw[9] & 0x01 > 0 { Timing::Pal } else { Timing::Ntsc };
+                }
+            },
         }
+        header
     }
 
     pub fn get_constants(&self) -> [u8;4]{
-        self.first_four_constants
+        [self.raw[0], self.raw[1], self.raw[2], self.raw[3]]
     }
     pub fn constant_as_str(&self) -> String {
-        self.first_four_constants.iter().map(|x|*x as char).collect()
+        self.get_constants().iter().map(|x|*x as char).collect()
+    }
+    pub fn get_raw(&self) -> &[u8; HEADER_SIZE] {
+        &self.raw
+    }
+    pub fn get_format(&self) -> Format {
+        self.format
+    }
+    pub fn get_mapper(&self) -> u16 {
+        self.mapper
     }
-    pub fn get_prg_rom_size(&self) -> u8 {
+    // 0 unless NES 2.0
+    pub fn get_submapper(&self) -> u8 {
+        self.submapper
+    }
+    // sizes in bytes
+    pub fn get_prg_rom_size(&self) -> usize {
         self.prg_rom_size
     }
-    pub fn get_chr_rom_size(&self) -> u8 {
+    pub fn get_chr_rom_size(&self) -> usize {
         self.chr_rom_size
     }
-    pub fn get_all_flag(&self) -> [u8;5] {
-        self.flags
+    pub fn get_prg_ram_size(&self) -> usize {
+        self.prg_ram_size
+    }
+    pub fn get_prg_nvram_size(&self) -> usize {
+        self.prg_nvram_size
+    }
+    pub fn get_chr_ram_size(&self) -> usize {
+        self.chr_ram_size
+    }
+    pub fn get_chr_nvram_size(&self) -> usize {
+        self.chr_nvram_size
+    }
+    pub fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+    pub fn has_trainer(&self) -> bool {
+        self.trainer
+    }
+    pub fn get_console_type(&self) -> ConsoleType {
+        self.console_type
+    }
+    pub fn get_timing(&self) -> Timing {
+        self.timing
+    }
+    pub fn get_vs_ppu_type(&self) -> u8 {
+        self.vs_ppu_type
     }
-    pub fn get_flag(&self, idx: usize) -> u8 {
-        self.flags[idx]
+    pub fn get_vs_hardware_type(&self) -> u8 {
+        self.vs_hardware_type
+    }
+    pub fn get_misc_rom_count(&self) -> u8 {
+        self.misc_roms
+    }
+    pub fn get_expansion_device(&self) -> u8 {
+        self.expansion_device
+    }
+}
+
+// NES 2.0 ROM size from the LSB byte and the MSB nibble
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        // too big to be real, it won't fit in the file
+        2usize.checked_pow(exponent).and_then(|x| x.checked_mul(multiplier)).unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
     }
 }
 
+// NES 2.0 RAM size from a shift count
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+pub const TRAINER_SIZE: usize = 512;
+pub const INST_ROM_SIZE: usize = 0x2000;
+pub const PROM_SIZE: usize = 32;
+
 pub struct RomV1 {
     header: Header,
-    data: Vec<u8>
+    trainer: Option<Vec<u8>>,
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    inst_rom: Option<Vec<u8>>,
+    prom: Option<Vec<u8>>,
+    title: Option<String>,
+    // bytes past the sections that aren't a title
+    extra: Vec<u8>,
+    // the file ended inside a section
+    truncated: bool,
 }
 
+impl RomV1 {
+    pub fn get_prom(&self) -> Option<&Vec<u8>> {
+        self.prom.as_ref()
+    }
+
+    pub fn get_extra_data(&self) -> &Vec<u8> {
+        &self.extra
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    // for files whose header can't name the board revision (iNES 1.0 has no submapper)
+    pub fn set_submapper(&mut self, submapper: u8) {
+        self.header.submapper = submapper;
+    }
+}
+
+// Reads the file section by section
+struct Sections<'a> {
+    data: &'a [u8],
+    position: usize,
+    truncated: bool,
+}
+
+impl<'a> Sections<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    // `size` bytes, padded with zeros past the end of the file
+    fn take(&mut self, size: usize) -> Vec<u8> {
+        let start = self.position.min(self.data.len());
+        let end = start.saturating_add(size).min(self.data.len());
+        let mut section = self.data[start..end].to_vec();
+        if section.len() < size {
+            self.truncated = true;
+            section.resize(size, 0);
+        }
+        self.position = self.position.saturating_add(size);
+        section
+    }
+}
 
 impl Rom for RomV1 {
-    fn new(data: &Vec<u8>) -> RomV1 {
+    fn new(data: &[u8]) -> RomV1 {
+        let header = Header::new(data);
+        let mut sections = Sections { data, position: HEADER_SIZE, truncated: data.len() < HEADER_SIZE };
+        let trainer = if header.has_trainer() { Some(sections.take(TRAINER_SIZE)) } else { None };
+        let prg_rom = sections.take(header.get_prg_rom_size());
+        let chr_rom = sections.take(header.get_chr_rom_size());
+
+        let is_title = |size: usize| size == 127 || size == 128;
+        let (inst_rom, prom) = if header.get_console_type() == ConsoleType::PlayChoice10 && sections.remaining() > 0 {
+            let inst_rom = sections.take(INST_ROM_SIZE);
+            let remaining = sections.remaining();
+            let prom = if remaining >= PROM_SIZE && (remaining == PROM_SIZE || is_title(remaining - PROM_SIZE)) {
+                Some(sections.take(PROM_SIZE))
+            } else {
+                None
+            };
+            (Some(inst_rom), prom)
+        } else {
+            (None, None)
+        };
+
+        let rest = data.get(sections.position..).unwrap_or_default();
+        let (title, extra) = if is_title(rest.len()) {
+            let title = String::from_utf8_lossy(rest).trim_end_matches(|x: char| x == '\0' || x.is_whitespace()).to_string();
+            (Some(title).filter(|x| !x.is_empty()), vec![])
+        } else {
+            (None, rest.to_vec())
+        };
+
         RomV1 {
-            header: Header::new(&data[0..11]),
-            data: data[16..].to_vec()
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+            inst_rom,
+            prom,
+            title,
+            extra,
+            truncated: sections.truncated,
         }
     }
 
@@ -89,7 +407,107 @@ impl Rom for RomV1 {
         &self.header
     }
 
-    fn get_rom_data(&self) -> &Vec<u8> {
-        &self.data
+    fn get_trainer(&self) -> Option<&Vec<u8>> {
+        self.trainer.as_ref()
+    }
+
+    fn get_prg_rom_data(&self) -> &Vec<u8> {
+        &self.prg_rom
+    }
+
+    fn get_chr_rom_data(&self) -> &Vec<u8> {
+        &self.chr_rom
+    }
+
+    fn get_inst_rom(&self) -> Option<&Vec<u8>> {
+        self.inst_rom.as_ref()
+    }
+
+    fn get_title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header followed by `size` bytes of ROM
+    fn file(header: [u8; HEADER_SIZE], size: usize) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(HEADER_SIZE + size, 0);
+        data
+    }
+
+    #[test]
+    fn nes_2_0_fields() {
+        let raw = [
+            0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x33, 0x49,
+            0x51, 0x00, 0x70, 0x07, 0x01, 0x00, 0x01, 0x02,
+        ];
+        let header = Header::new(&file(raw, 0x8000 + 0x2000));
+        assert_eq!(header.get_format(), Format::Nes2);
+        assert_eq!(header.get_mapper(), 0x143);
+        assert_eq!(header.get_submapper(), 5);
+        assert_eq!(header.get_prg_rom_size(), 0x8000);
+        assert_eq!(header.get_chr_rom_size(), 0x2000);
+        assert_eq!(header.get_prg_ram_size(), 0);
+        assert_eq!(header.get_prg_nvram_size(), 0x2000);
+        assert_eq!(header.get_chr_ram_size(), 0x2000);
+        assert_eq!(header.get_chr_nvram_size(), 0);
+        assert_eq!(header.get_mirroring(), Mirroring::Vertical);
+        assert!(header.has_battery());
+        assert!(!header.has_trainer());
+        assert_eq!(header.get_console_type(), ConsoleType::VsSystem);
+        assert_eq!(header.get_timing(), Timing::Pal);
+        assert_eq!(header.get_misc_rom_count(), 1);
+        assert_eq!(header.get_expansion_device(), 0x02);
+    }
+
+    #[test]
+    fn nes_2_0_exponent_multiplier_sizes() {
+        // 2^4 * 3 bytes of PRG ROM
+        let raw = [0x4E, 0x45, 0x53, 0x1A, 0x11, 0x00, 0x00, 0x08, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let header = Header::new(&file(raw, 48));
+        assert_eq!(header.get_format(), Format::Nes2);
+        assert_eq!(header.get_prg_rom_size(), 48);
+        assert_eq!(header.get_chr_rom_size(), 0);
+    }
+
+    #[test]
+    fn nes_2_0_sizes_must_fit_in_the_file() {
+        // byte 9 claims 4M of PRG ROM
+        let raw = [0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x10, 0x08, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let header = Header::new(&file(raw, 0x8000 + 0x2000));
+        assert_eq!(header.get_format(), Format::ArchaicINes);
+        assert_eq!(header.get_prg_rom_size(), 0x8000);
+        assert_eq!(header.get_mapper(), 1);
+    }
+
+    #[test]
+    fn ines_1_0_fields() {
+        let raw = [0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x48, 0x10, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let header = Header::new(&file(raw, 0x20000));
+        assert_eq!(header.get_format(), Format::INes);
+        assert_eq!(header.get_mapper(), 0x14);
+        assert_eq!(header.get_mirroring(), Mirroring::FourScreen);
+        assert!(!header.has_battery());
+        assert_eq!(header.get_prg_ram_size(), 0x4000);
+        assert_eq!(header.get_chr_ram_size(), 0x2000);
+        assert_eq!(header.get_timing(), Timing::Pal);
+        assert_eq!(header.get_console_type(), ConsoleType::Nes);
+    }
+
+    #[test]
+    fn archaic_ines_ignores_a_rippers_name() {
+        let mut raw = [0; HEADER_SIZE];
+        raw[..7].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x12]);
+        raw[7..].copy_from_slice(b"DiskDude!");
+        let header = Header::new(&file(raw, 0x8000 + 0x2000));
+        assert_eq!(header.get_format(), Format::ArchaicINes);
+        assert_eq!(header.get_mapper(), 1);
+        assert_eq!(header.get_prg_nvram_size(), 0x2000);
+        assert_eq!(header.get_timing(), Timing::Ntsc);
+        assert_eq!(header.get_console_type(), ConsoleType::Nes);
     }
 }
@@ -0,0 +1,85 @@
+// Result protocol of blargg's test ROMs (instr_test-v3, apu_test, mmc3_test_2, ppu_vbl_nmi, ...)
+//
+// $6000      .... status: $80 running, $81 reset needed, $00-$7F final result (0 = passed)
+// $6001-6003 .... signature $DE $B0 $61, written once $6000 is valid
+// $6004-     .... text output, NUL terminated
+//
+// On $81 the ROM waits for the reset button, which must not be pressed within 100 ms.
+
+use crate::nes::console::Console;
+
+pub const STATUS_ADDRESS: u16 = 0x6000;
+pub const SIGNATURE_ADDRESS: u16 = 0x6001;
+pub const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+pub const TEXT_ADDRESS: u16 = 0x6004;
+const TEXT_END: u16 = 0x8000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    // no signature, the ROM doesn't use the protocol (or didn't start yet)
+    NotDetected,
+    Running,
+    NeedsReset,
+    Done(u8),
+}
+
+pub fn read_status(console: &Console) -> Status {
+    let signature = [
+        console.peek(SIGNATURE_ADDRESS),
+        console.peek(SIGNATURE_ADDRESS + 1),
+        console.peek(SIGNATURE_ADDRESS + 2),
+    ];
+    if signature != SIGNATURE {
+        return Status::NotDetected;
+    }
+    match console.peek(STATUS_ADDRESS) {
+        0x80 => Status::Running,
+        0x81 => Status::NeedsReset,
+        code => Status::Done(code),
+    }
+}
+
+pub fn read_message(console: &Console) -> String {
+    let mut text = vec![];
+    for addr in TEXT_ADDRESS..TEXT_END {
+        match console.peek(addr) {
+            0x00 => break,
+            byte => text.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&text).trim().to_string()
+}
+
+// Watches the protocol while the ROM runs and presses reset when asked to.
+pub struct Monitor {
+    reset_at: Option<usize>,
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            reset_at: None,
+        }
+    }
+
+    // call between frames
+    pub fn update(&mut self, console: &mut Console) -> Status {
+        let status = read_status(console);
+        if status == Status::NeedsReset {
+            match self.reset_at {
+                None => {
+                    let delay = console.get_system().cpu_frequency() / 10;
+                    self.reset_at = Some(console.get_cycle() + delay);
+                },
+                Some(cycle) if console.get_cycle() >= cycle => {
+                    console.reset();
+                    self.reset_at = None;
+                },
+                _ => {}
+            }
+        } else {
+            self.reset_at = None;
+        }
+        status
+    }
+}
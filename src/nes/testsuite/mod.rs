@@ -0,0 +1,360 @@
+// Regression runner for resources/test/test_roms.xml
+//
+// Every <test> entry of the file looks like
+//
+// <test runframes="60" failcomment="" testnotes="" testresult="pass" filename="apu_reset\4015_cleared.nes" system="ntsc">
+//  <tvsha1><![CDATA[75NVOeAT7/jVw73+CEdeKsb2Pic=]]></tvsha1>
+//  <recordedinput><![CDATA[]]></recordedinput>
+// </test>
+//
+// runframes     .... number of frames to emulate before taking the picture
+// testresult    .... outcome of the reference emulator, the screen below shows that outcome
+// filename      .... ROM path relative to the xml, with `\` separators
+// system        .... ntsc or pal
+// submapper     .... optional, NES 2.0 submapper for iNES 1.0 files that can't tell the board
+// tvsha1        .... base64 SHA-1 of the screen after `runframes` frames (see ppu::frame)
+// recordedinput .... base64 controller log replayed while the test runs (see controller::recording)
+//
+// A test passes when our screen matches the reference one and the reference outcome is "pass".
+// ROMs speaking blargg's $6000 protocol are judged by their own result code instead, and their
+// text output becomes the diagnostic. For the others the text found on screen is reported.
+// A screen differing from a reference that recorded a failure can't be judged, it gets its own
+// status instead of FAIL.
+
+pub mod blargg;
+pub mod report;
+pub mod screen;
+
+use crate::nes::console::{Console, System};
+use crate::nes::controller::recording::RecordedInput;
+use crate::nes::digest;
+use crate::nes::loader;
+use crate::nes::rom::{Rom, RomV1};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// longest diagnostic kept in a report
+const MESSAGE_LENGTH: usize = 120;
+
+pub struct TestRom {
+    pub filename: String,
+    pub runframes: usize,
+    pub system: System,
+    pub submapper: Option<u8>,
+    pub tvsha1: String,
+    pub recorded_input: Vec<u8>,
+    pub expected_result: String,
+    pub fail_comment: String,
+    pub test_notes: String,
+}
+
+impl TestRom {
+    // path with `/` separators
+    pub fn get_path(&self) -> String {
+        self.filename.replace('\\', "/")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestStatus {
+    Pass,
+    // screen differs from the reference, or matches a failing reference screen
+    Fail,
+    // screen differs from a reference that recorded a failure
+    Differs,
+    // ROM missing, unreadable or emulation panicked
+    Error,
+}
+
+impl TestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestStatus::Pass => "PASS",
+            TestStatus::Fail => "FAIL",
+            TestStatus::Differs => "DIFF",
+            TestStatus::Error => "FAIL",
+        }
+    }
+}
+
+pub struct TestResult {
+    pub status: TestStatus,
+    pub tvsha1: Option<String>,
+    pub message: String,
+    pub duration: Duration,
+}
+
+pub fn parse_test_roms(xml: &str) -> Result<Vec<TestRom>, String> {
+    let mut tests = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find("<test ") {
+        rest = &rest[start..];
+        let tag_end = rest.find('>').ok_or("unterminated <test> tag")?;
+        let body_end = rest.find("</test>").ok_or("missing </test>")?;
+        let tag = &rest[5..tag_end];
+        let body = &rest[tag_end + 1..body_end];
+        rest = &rest[body_end + 7..];
+
+        let attribute = |name: &str| -> String {
+            get_attribute(tag, name).unwrap_or_default()
+        };
+        let filename = get_attribute(tag, "filename").ok_or("<test> without filename")?;
+        let runframes = attribute("runframes").parse::<usize>()
+            .map_err(|e| format!("{}: invalid runframes, {}", filename, e))?;
+        let system = match attribute("system").to_lowercase().as_str() {
+            "ntsc" | "" => System::Ntsc,
+            "pal" => System::Pal,
+            other => return Err(format!("{}: unknown system {:?}", filename, other)),
+        };
+        let submapper = match get_attribute(tag, "submapper") {
+            Some(submapper) => Some(submapper.parse::<u8>()
+                .map_err(|e| format!("{}: invalid submapper, {}", filename, e))?),
+            None => None,
+        };
+        let recorded_input = digest::base64_decode(&get_element_text(body, "recordedinput"))
+            .map_err(|e| format!("{}: invalid recordedinput, {}", filename, e))?;
+
+        tests.push(TestRom {
+            filename,
+            runframes,
+            system,
+            submapper,
+            tvsha1: get_element_text(body, "tvsha1").trim().to_string(),
+            recorded_input,
+            expected_result: attribute("testresult"),
+            fail_comment: attribute("failcomment"),
+            test_notes: attribute("testnotes"),
+        });
+    }
+    Ok(tests)
+}
+
+fn get_attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    loop {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        let open = eq + 1 + rest[eq + 1..].find(['"', '\''])?;
+        let quote = &rest[open..open + 1];
+        let close = open + 1 + rest[open + 1..].find(quote)?;
+        if key == name {
+            return Some(unescape(&rest[open + 1..close]));
+        }
+        rest = &rest[close + 1..];
+    }
+}
+
+// content of <name>...</name>, with the CDATA wrapper removed
+fn get_element_text(body: &str, name: &str) -> String {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let text = match (body.find(&open), body.find(&close)) {
+        (Some(start), Some(end)) if start + open.len() <= end => &body[start + open.len()..end],
+        _ => return String::new(),
+    };
+    let text = text.trim();
+    if text.starts_with("<![CDATA[") && text.ends_with("]]>") {
+        text[9..text.len() - 3].to_string()
+    } else {
+        unescape(text)
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn run_test(base: &Path, test: &TestRom) -> TestResult {
+    let start = Instant::now();
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(String, blargg::Status, String, String), String> {
+        let path = loader::find_file(base, &test.get_path())
+            .ok_or_else(|| format!("ROM not found: {}", test.get_path()))?;
+        let data = loader::load_rom(&path.to_string_lossy())
+            .map_err(|e| format!("cannot read ROM: {}", e))?;
+        let mut rom: RomV1 = Rom::new(&data);
+        if let Some(submapper) = test.submapper {
+            rom.set_submapper(submapper);
+        }
+        let mut console = Console::new(&rom, test.system)?;
+        console.set_trace(false);
+        if !test.recorded_input.is_empty() {
+            let input = RecordedInput::decode(&test.recorded_input)?;
+            console.set_input_source(Some(Box::new(input)));
+        }
+        let mut monitor = blargg::Monitor::new();
+        for _ in 0..test.runframes {
+            console.run_frame();
+            monitor.update(&mut console);
+        }
+        let screen_text = screen::read_text(&console, &screen::Font::builtin());
+        Ok((console.tv_sha1(), blargg::read_status(&console), blargg::read_message(&console), screen_text))
+    }));
+
+    let (status, tvsha1, message) = match outcome {
+        Ok(Ok((tvsha1, blargg_status, text, screen_text))) => {
+            let (status, message) = judge(test, &tvsha1, blargg_status, &text, &screen_text);
+            (status, Some(tvsha1), shorten(message))
+        },
+        Ok(Err(e)) => (TestStatus::Error, None, e),
+        Err(payload) => {
+            let reason = payload.downcast_ref::<&str>().map(|x| x.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            (TestStatus::Error, None, format!("emulation panicked: {}", reason))
+        },
+    };
+
+    TestResult {
+        status,
+        tvsha1,
+        message,
+        duration: start.elapsed(),
+    }
+}
+
+// Status and diagnostic of a finished run, the frame hash is compared in every case
+fn judge(test: &TestRom, tvsha1: &str, blargg_status: blargg::Status, text: &str, screen_text: &str) -> (TestStatus, String) {
+    let reference_passes = test.expected_result.eq_ignore_ascii_case("pass");
+    let difference = if tvsha1 == test.tvsha1 {
+        None
+    } else if reference_passes {
+        Some("screen differs from reference")
+    } else {
+        Some("screen differs from a failing reference")
+    };
+    let text = text.trim().replace('\n', " ");
+    let screen_text = screen_text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    match (blargg_status, difference) {
+        (blargg::Status::Done(code), _) => {
+            let status = if code == 0 { TestStatus::Pass } else { TestStatus::Fail };
+            let message = match difference {
+                Some(difference) => format!("${:02X}: {} ({})", code, text, difference),
+                None => format!("${:02X}: {}", code, text),
+            };
+            (status, message)
+        },
+        (blargg::Status::NotDetected, None) if reference_passes => (TestStatus::Pass, String::new()),
+        (blargg::Status::NotDetected, None) => {
+            (TestStatus::Fail, format!("matches reference failure: {}", test.fail_comment))
+        },
+        (blargg::Status::NotDetected, Some(difference)) => {
+            let status = if reference_passes { TestStatus::Fail } else { TestStatus::Differs };
+            let message = if screen_text.is_empty() {
+                difference.to_string()
+            } else {
+                format!("{}: {}", difference, screen_text)
+            };
+            (status, message)
+        },
+        (blargg_status, _) => (TestStatus::Fail, format!("{:?}: {}", blargg_status, text)),
+    }
+}
+
+// keeps the Notes column of the status table readable
+fn shorten(message: String) -> String {
+    if message.chars().count() <= MESSAGE_LENGTH {
+        return message;
+    }
+    let mut short = message.chars().take(MESSAGE_LENGTH - 3).collect::<String>();
+    short.push_str("...");
+    short
+}
+
+// Runs all tests on `jobs` threads, results are in the order of `tests`.
+pub fn run_all(base: &Path, tests: &[TestRom], jobs: usize) -> Vec<TestResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(tests.len()));
+
+    // panics are reported per test, keep them out of stderr
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                if idx >= tests.len() {
+                    break;
+                }
+                let result = run_test(base, &tests[idx]);
+                results.lock().unwrap().push((idx, result));
+            });
+        }
+    });
+    panic::set_hook(hook);
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(idx, _)| *idx);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rom(expected_result: &str) -> TestRom {
+        TestRom {
+            filename: String::from("dir\\test.nes"),
+            runframes: 60,
+            system: System::Ntsc,
+            submapper: None,
+            tvsha1: String::from("reference"),
+            recorded_input: vec![],
+            expected_result: expected_result.to_string(),
+            fail_comment: String::from("hangs"),
+            test_notes: String::new(),
+        }
+    }
+
+    #[test]
+    fn screens_are_judged_against_the_reference_outcome() {
+        let status = |expected: &str, tvsha1: &str| judge(&test_rom(expected), tvsha1, blargg::Status::NotDetected, "", "").0;
+        assert_eq!(status("pass", "reference"), TestStatus::Pass);
+        assert_eq!(status("pass", "other"), TestStatus::Fail);
+        assert_eq!(status("fail", "reference"), TestStatus::Fail);
+        assert_eq!(status("fail", "other"), TestStatus::Differs);
+    }
+
+    #[test]
+    fn blargg_results_report_a_differing_screen() {
+        let test = test_rom("pass");
+        let (status, message) = judge(&test, "other", blargg::Status::Done(0), "Passed\n", "");
+        assert_eq!(status, TestStatus::Pass);
+        assert_eq!(message, "$00: Passed (screen differs from reference)");
+        let (status, message) = judge(&test, "reference", blargg::Status::Done(2), "Failed", "");
+        assert_eq!(status, TestStatus::Fail);
+        assert_eq!(message, "$02: Failed");
+    }
+
+    #[test]
+    fn long_messages_are_shortened() {
+        assert_eq!(shorten(String::from("short")), "short");
+        let message = shorten("x".repeat(500));
+        assert_eq!(message.len(), MESSAGE_LENGTH);
+        assert!(message.ends_with("..."));
+    }
+
+    #[test]
+    fn submapper_overrides_are_parsed() {
+        let xml = r#"<testsuite>
+ <test runframes="60" testresult="pass" filename="a.nes" system="ntsc" submapper="1">
+  <tvsha1><![CDATA[x]]></tvsha1>
+ </test>
+ <test runframes="60" testresult="pass" filename="b.nes" system="pal">
+ </test>
+</testsuite>"#;
+        let tests = parse_test_roms(xml).unwrap();
+        assert_eq!(tests[0].submapper, Some(1));
+        assert_eq!(tests[1].submapper, None);
+        assert_eq!(tests[1].system, System::Pal);
+        assert!(parse_test_roms(&xml.replace("\"1\"", "\"one\"")).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// Reports of a test_roms.xml run
+//
+// status_table follows resources/test/status.txt:
+//
+// ^ NESICIDE ^ Nestopia v1.40 ^ Nintendulator 0.975 ^ NESICIDE Notes ^ ROM Path ^ Other Info ^
+// | PASS | PASS | | | apu_reset\4015_cleared.nes | |
+//
+// with a single emulator column, DIFF marks a screen differing from a failing reference.
+// junit_xml is the usual JUnit layout read by CI dashboards, one <testcase> per xml entry with
+// the ROM directory as class name, DIFF entries are skipped.
+
+use super::{TestResult, TestRom, TestStatus};
+
+pub const EMULATOR_NAME: &str = "genuine-nes-emulator-rust";
+
+pub fn status_table(tests: &[TestRom], results: &[TestResult]) -> String {
+    let mut table = format!("^ {} ^ Notes ^ ROM Path ^ Other Info ^\n", EMULATOR_NAME);
+    for (test, result) in tests.iter().zip(results) {
+        let cells = [result.status.as_str(), &result.message, &test.filename, &test.test_notes];
+        for cell in cells.iter() {
+            if cell.is_empty() {
+                table.push_str("| ");
+            } else {
+                table.push_str(&format!("| {} ", cell.replace('|', "/")));
+            }
+        }
+        table.push_str("|\n");
+    }
+    table
+}
+
+pub fn junit_xml(suite_name: &str, tests: &[TestRom], results: &[TestResult]) -> String {
+    let failures = results.iter().filter(|x| x.status == TestStatus::Fail).count();
+    let errors = results.iter().filter(|x| x.status == TestStatus::Error).count();
+    let skipped = results.iter().filter(|x| x.status == TestStatus::Differs).count();
+    let time: f64 = results.iter().map(|x| x.duration.as_secs_f64()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        escape(suite_name), tests.len(), failures, errors, skipped, time
+    ));
+    for (test, result) in tests.iter().zip(results) {
+        let path = test.get_path();
+        let (class_name, file_name) = match path.rfind('/') {
+            Some(idx) => (path[..idx].replace('/', "."), &path[idx + 1..]),
+            None => (String::from(suite_name), &path[..]),
+        };
+        let name = if test.test_notes.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{} ({})", file_name, test.test_notes)
+        };
+        xml.push_str(&format!(
+            "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            escape(&class_name), escape(&name), result.duration.as_secs_f64()
+        ));
+        match result.status {
+            TestStatus::Pass => xml.push_str("/>\n"),
+            TestStatus::Differs => {
+                xml.push_str(">\n");
+                xml.push_str(&format!("    <skipped message=\"{}\"/>\n", escape(&result.message)));
+                xml.push_str("  </testcase>\n");
+            },
+            TestStatus::Fail | TestStatus::Error => {
+                let element = if result.status == TestStatus::Fail { "failure" } else { "error" };
+                xml.push_str(">\n");
+                xml.push_str(&format!(
+                    "    <{} message=\"{}\">expected tvsha1 {}, got {}</{}>\n",
+                    element,
+                    escape(&result.message),
+                    escape(&test.tvsha1),
+                    escape(result.tvsha1.as_deref().unwrap_or("nothing")),
+                    element
+                ));
+                xml.push_str("  </testcase>\n");
+            },
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
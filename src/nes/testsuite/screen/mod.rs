@@ -0,0 +1,110 @@
+// Text recognition for ROMs that only report on screen
+// (blargg_ppu_tests_2005.09.15b, sprite_hit_tests_2005.10.05, vbl_nmi_timing, ...)
+//
+// Tiles of the visible nametable are fetched through the PPU, reduced to 1 bit glyphs (any
+// non-zero pixel is ink) and looked up in a font, so the tile numbering of the ROM doesn't
+// matter. Built in fonts are the ones the test ROMs upload:
+//
+// common/ascii.chr   .... 16 bytes per tile (2 planes), first tile is ' '
+// support/chr.bin    .... 8 bytes per glyph (1 plane), first glyph is ' '
+
+use crate::nes::console::Console;
+
+use std::collections::HashMap;
+
+pub const COLUMNS: usize = 32;
+pub const ROWS: usize = 30;
+
+const ASCII_CHR: &[u8] = include_bytes!("../../../../resources/test/apu_test/source/common/ascii.chr");
+const ASCII_CHR_2048: &[u8] = include_bytes!("../../../../resources/test/cpu_dummy_reads/source/common/ascii.chr");
+const CHR_BIN: &[u8] = include_bytes!("../../../../resources/test/vbl_nmi_timing/source/support/chr.bin");
+
+pub type Glyph = [u8; 8];
+
+pub struct Font {
+    glyphs: HashMap<Glyph, char>,
+}
+
+impl Font {
+    pub fn new() -> Font {
+        Font {
+            glyphs: HashMap::new()
+        }
+    }
+
+    pub fn builtin() -> Font {
+        let mut font = Font::new();
+        font.add_chr(ASCII_CHR, ' ');
+        font.add_chr(ASCII_CHR_2048, ' ');
+        font.add_1bpp(CHR_BIN, ' ');
+        font
+    }
+
+    // CHR tiles, both planes
+    pub fn add_chr(&mut self, data: &[u8], first: char) {
+        for (i, tile) in data.chunks_exact(16).enumerate() {
+            let mut glyph = [0u8; 8];
+            for row in 0..8 {
+                glyph[row] = tile[row] | tile[row + 8];
+            }
+            self.add_glyph(glyph, first as u32 + i as u32);
+        }
+    }
+
+    // one plane, 8 bytes per glyph
+    pub fn add_1bpp(&mut self, data: &[u8], first: char) {
+        for (i, rows) in data.chunks_exact(8).enumerate() {
+            let mut glyph = [0u8; 8];
+            glyph.copy_from_slice(rows);
+            self.add_glyph(glyph, first as u32 + i as u32);
+        }
+    }
+
+    fn add_glyph(&mut self, glyph: Glyph, code: u32) {
+        if let Some(c) = std::char::from_u32(code).filter(|x| x.is_ascii() && !x.is_ascii_control()) {
+            // the first font wins when two glyphs look the same
+            self.glyphs.entry(glyph).or_insert(c);
+        }
+    }
+
+    pub fn get_char(&self, glyph: &Glyph) -> Option<char> {
+        if glyph.iter().all(|x| *x == 0) {
+            return Some(' ');
+        }
+        self.glyphs.get(glyph).copied()
+    }
+}
+
+// Text of the nametable selected in PPUCTRL, one string per tile row with trailing spaces
+// removed. Unknown tiles are returned as '?'. Scrolling is ignored, test ROMs keep it at 0.
+pub fn read_screen(console: &Console, font: &Font) -> Vec<String> {
+    let ctrl = console.get_ppu().get_ctrl();
+    let nametable = 0x2000 + (ctrl as u16 & 0x03) * 0x400;
+    let pattern_table = if ctrl & 0x10 > 0 { 0x1000 } else { 0x0000 };
+    (0..ROWS).map(|row| {
+        let line = (0..COLUMNS).map(|column| {
+            let tile = console.ppu_peek(nametable + (row * COLUMNS + column) as u16) as u16;
+            let mut glyph = [0u8; 8];
+            for (y, bits) in glyph.iter_mut().enumerate() {
+                let addr = pattern_table + tile * 16 + y as u16;
+                *bits = console.ppu_peek(addr) | console.ppu_peek(addr + 8);
+            }
+            font.get_char(&glyph).unwrap_or('?')
+        }).collect::<String>();
+        line.trim_end().to_string()
+    }).collect()
+}
+
+// whole screen as one string, empty rows dropped. Empty when less than half of the tiles are
+// letters or digits, graphics then come out as unknown tiles or blocks matching some glyph.
+pub fn read_text(console: &Console, font: &Font) -> String {
+    let lines = read_screen(console, font).into_iter()
+        .filter(|x| !x.trim().is_empty())
+        .collect::<Vec<String>>();
+    let tiles = lines.iter().flat_map(|x| x.chars()).filter(|x| *x != ' ');
+    let letters = tiles.clone().filter(|x| x.is_ascii_alphanumeric()).count();
+    if letters * 2 < tiles.count() {
+        return String::new();
+    }
+    lines.join("\n")
+}